@@ -0,0 +1,256 @@
+use crate::SpanConverterLimits;
+use opentelemetry::baggage::BaggageExt;
+use opentelemetry::trace::Span as _;
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_sdk::error::OTelSdkResult;
+use opentelemetry_sdk::trace::{Span, SpanData, SpanProcessor};
+use std::collections::HashSet;
+use std::time::Duration;
+
+/// Copies allow-listed W3C Baggage entries of the parent context to span attributes
+/// when spans start (e.g. a tenant id), so traces can be filtered by them in Cloud Trace.
+///
+/// Only the allowed keys are copied, so untrusted incoming baggage can't add attributes.
+/// With limits, at most `max_attributes` entries are copied and values are truncated
+/// to `max_attribute_value_len`. The entries are added after the attributes the span started with,
+/// so the Cloud Trace conversion only keeps them in the attribute slots left by those.
+/// Unless set with `with_limits`, [`crate::GcpCloudTraceExporterBuilder::with_baggage_span_processor`]
+/// uses the exporter limits with the Cloud Trace v2 backend, and no limits with the OTLP backend.
+#[derive(Debug, Clone)]
+pub struct BaggageSpanProcessor {
+    allowed_keys: HashSet<String>,
+    prefix: Option<String>,
+    limits: Option<SpanConverterLimits>,
+}
+
+impl BaggageSpanProcessor {
+    pub fn new<I, K>(allowed_keys: I) -> Self
+    where
+        I: IntoIterator<Item = K>,
+        K: Into<String>,
+    {
+        Self {
+            allowed_keys: allowed_keys.into_iter().map(Into::into).collect(),
+            prefix: None,
+            limits: None,
+        }
+    }
+
+    /// Prefix added to the attribute keys (e.g. `baggage.`).
+    pub fn with_prefix(self, prefix: &str) -> Self {
+        Self {
+            prefix: Some(prefix.to_string()),
+            ..self
+        }
+    }
+
+    pub fn with_limits(self, limits: SpanConverterLimits) -> Self {
+        Self {
+            limits: Some(limits),
+            ..self
+        }
+    }
+
+    /// Uses the limits when none were set.
+    pub(crate) fn with_default_limits(self, limits: SpanConverterLimits) -> Self {
+        Self {
+            limits: self.limits.or(Some(limits)),
+            ..self
+        }
+    }
+
+    fn truncate(value: &str, max_len: usize) -> &str {
+        if value.len() <= max_len {
+            return value;
+        }
+        let mut truncated_len = max_len;
+        while !value.is_char_boundary(truncated_len) {
+            truncated_len -= 1;
+        }
+        &value[..truncated_len]
+    }
+}
+
+impl SpanProcessor for BaggageSpanProcessor {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        if self.allowed_keys.is_empty() || !span.is_recording() {
+            return;
+        }
+        let baggage = cx.baggage();
+        let mut entries = baggage
+            .iter()
+            .filter(|(key, _)| self.allowed_keys.contains(key.as_str()))
+            .peekable();
+        if entries.peek().is_none() {
+            return;
+        }
+
+        let (max_attributes, max_value_len) = match &self.limits {
+            Some(limits) => (limits.max_attributes, limits.max_attribute_value_len),
+            None => (usize::MAX, usize::MAX),
+        };
+        let attributes: Vec<KeyValue> = entries
+            .take(max_attributes)
+            .map(|(key, (value, _))| {
+                let key = match &self.prefix {
+                    Some(prefix) => format!("{prefix}{key}"),
+                    None => key.to_string(),
+                };
+                let value = value.as_str();
+                KeyValue::new(key, Self::truncate(value, max_value_len).to_string())
+            })
+            .collect();
+        if !attributes.is_empty() {
+            span.set_attributes(attributes);
+        }
+    }
+
+    fn on_end(&self, _span: SpanData) {}
+
+    fn force_flush(&self) -> OTelSdkResult {
+        Ok(())
+    }
+
+    fn shutdown_with_timeout(&self, _timeout: Duration) -> OTelSdkResult {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::baggage::BaggageExt;
+    use opentelemetry::trace::{Tracer, TracerProvider};
+    use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider};
+
+    fn test_span() -> SpanData {
+        SpanData {
+            span_context: opentelemetry::trace::SpanContext::new(
+                opentelemetry::trace::TraceId::from(1),
+                opentelemetry::trace::SpanId::from(1),
+                opentelemetry::trace::TraceFlags::SAMPLED,
+                false,
+                opentelemetry::trace::TraceState::default(),
+            ),
+            parent_span_id: opentelemetry::trace::SpanId::INVALID,
+            parent_span_is_remote: false,
+            span_kind: opentelemetry::trace::SpanKind::Internal,
+            name: "test".into(),
+            start_time: std::time::SystemTime::now(),
+            end_time: std::time::SystemTime::now(),
+            attributes: Vec::new(),
+            dropped_attributes_count: 0,
+            events: opentelemetry_sdk::trace::SpanEvents::default(),
+            links: opentelemetry_sdk::trace::SpanLinks::default(),
+            status: opentelemetry::trace::Status::Unset,
+            instrumentation_scope: opentelemetry::InstrumentationScope::builder("test").build(),
+        }
+    }
+
+    fn test_limits(max_attributes: usize) -> SpanConverterLimits {
+        SpanConverterLimits::new()
+            .with_max_attributes(max_attributes)
+            .with_max_attribute_value_len(8)
+    }
+
+    // Starts a span with the attributes in a context with the baggage and returns its attributes
+    fn span_attributes(
+        processor: BaggageSpanProcessor,
+        span_attributes: Vec<KeyValue>,
+    ) -> Vec<KeyValue> {
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_span_processor(processor)
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let tracer = provider.tracer("test");
+        let cx = Context::new().with_baggage(vec![
+            KeyValue::new("tenant.id", "tenant-with-a-long-id"),
+            KeyValue::new("request.origin", "web"),
+            KeyValue::new("user.email", "john@example.com"),
+        ]);
+        tracer
+            .span_builder("test")
+            .with_attributes(span_attributes)
+            .start_with_context(&tracer, &cx)
+            .end();
+        exporter.get_finished_spans().unwrap()[0].attributes.clone()
+    }
+
+    #[test]
+    fn copies_allowed_baggage() {
+        let mut attributes = span_attributes(
+            BaggageSpanProcessor::new(["tenant.id", "request.origin"])
+                .with_prefix("baggage.")
+                .with_limits(test_limits(32)),
+            vec![],
+        );
+        attributes.sort_by(|a, b| a.key.cmp(&b.key));
+        assert_eq!(
+            attributes,
+            vec![
+                KeyValue::new("baggage.request.origin", "web"),
+                KeyValue::new("baggage.tenant.id", "tenant-w"),
+            ]
+        );
+    }
+
+    #[test]
+    fn uses_remaining_attribute_slots() {
+        let processor =
+            BaggageSpanProcessor::new(["tenant.id", "request.origin"]).with_limits(test_limits(3));
+        let start_attributes = vec![
+            KeyValue::new("http.method", "GET"),
+            KeyValue::new("http.route", "/"),
+        ];
+        let attributes = span_attributes(processor.clone(), start_attributes.clone());
+        // Added after the attributes the span started with
+        assert_eq!(attributes.len(), 4);
+        assert_eq!(attributes[..2], start_attributes[..]);
+
+        // Only the remaining slots are used in Cloud Trace
+        let converter = crate::SpanConverter::new("test-project".to_string())
+            .with_options(crate::SpanConverterOptions::new().with_limits(test_limits(3)));
+        let mut span = test_span();
+        span.attributes = attributes;
+        let converted = converter.convert_span(&span).attributes.unwrap();
+        assert_eq!(converted.attribute_map.len(), 3);
+        assert!(converted.attribute_map.contains_key("http.method"));
+        assert!(converted.attribute_map.contains_key("http.route"));
+        assert_eq!(converted.dropped_attributes_count, 1);
+
+        let attributes = span_attributes(
+            BaggageSpanProcessor::new(["tenant.id", "request.origin"]).with_limits(test_limits(1)),
+            vec![],
+        );
+        assert_eq!(attributes.len(), 1);
+    }
+
+    #[test]
+    fn copies_without_limits() {
+        let mut attributes = span_attributes(
+            BaggageSpanProcessor::new(["tenant.id", "request.origin"]),
+            vec![],
+        );
+        attributes.sort_by(|a, b| a.key.cmp(&b.key));
+        assert_eq!(
+            attributes,
+            vec![
+                KeyValue::new("request.origin", "web"),
+                KeyValue::new("tenant.id", "tenant-with-a-long-id"),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_user_limits() {
+        let processor = BaggageSpanProcessor::new(["tenant.id"])
+            .with_limits(test_limits(1))
+            .with_default_limits(SpanConverterLimits::new());
+        assert_eq!(processor.limits, Some(test_limits(1)));
+
+        let processor =
+            BaggageSpanProcessor::new(["tenant.id"]).with_default_limits(test_limits(2));
+        assert_eq!(processor.limits, Some(test_limits(2)));
+    }
+}
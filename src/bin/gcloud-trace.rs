@@ -0,0 +1,587 @@
+use clap::{Parser, Subcommand};
+use gcloud_sdk::google::devtools::cloudtrace::v2::trace_service_client::TraceServiceClient;
+use gcloud_sdk::google::devtools::cloudtrace::v2::{BatchWriteSpansRequest, Span as GcpSpan};
+use gcloud_sdk::{GoogleApi, GoogleAuthMiddleware, SecretValue, Source, Token, GCP_DEFAULT_SCOPES};
+use opentelemetry::trace::{SpanId, TraceId};
+use opentelemetry_gcloud_trace::*;
+use opentelemetry_proto::tonic::collector::trace::v1::ExportTraceServiceRequest;
+use opentelemetry_sdk::trace::SpanData;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+type BoxedError = Box<dyn std::error::Error + Send + Sync>;
+
+// Cloud Trace limit for attribute keys
+const MAX_ATTRIBUTE_KEY_LEN: usize = 128;
+
+/// Inspect, convert and upload trace files for Google Cloud Trace.
+///
+/// Trace files are either OTLP JSON (`ExportTraceServiceRequest`)
+/// or Cloud Trace v2 `BatchWriteSpansRequest` JSON.
+#[derive(Parser)]
+#[command(name = "gcloud-trace", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Validate OTLP JSON spans against Cloud Trace limits
+    Validate { file: PathBuf },
+    /// Convert OTLP JSON into Cloud Trace BatchWriteSpansRequest JSON
+    Convert {
+        file: PathBuf,
+        /// Google project id
+        #[arg(long, env = "PROJECT_ID")]
+        project: String,
+        /// Output file (stdout if not specified)
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Upload a trace file to Cloud Trace
+    Upload {
+        file: PathBuf,
+        /// Google project id (required for OTLP JSON files)
+        #[arg(long, env = "PROJECT_ID")]
+        project: Option<String>,
+        /// Cloud Trace API URL (e.g. an emulator)
+        #[arg(long, default_value = GCP_CLOUD_TRACE_API_URL)]
+        endpoint: String,
+        /// Credentials: `adc` (Application Default Credentials), `none` (e.g. an emulator)
+        /// or the path to a service account key JSON file
+        #[arg(long, default_value = "adc", value_parser = parse_credentials)]
+        credentials: GcpCloudTraceCredentials,
+    },
+    /// Print trace trees from a trace file
+    Tree { file: PathBuf },
+    /// Receive spans using OTLP/gRPC and OTLP/HTTP and forward them to Cloud Trace
+    #[cfg(feature = "otlp-receiver")]
+    Receive {
+        /// Google project id
+        #[arg(long, env = "PROJECT_ID")]
+        project: String,
+        /// Cloud Trace API URL (e.g. an emulator)
+        #[arg(long, default_value = GCP_CLOUD_TRACE_API_URL)]
+        endpoint: String,
+        /// Credentials: `adc` (Application Default Credentials), `none` (e.g. an emulator)
+        /// or the path to a service account key JSON file
+        #[arg(long, default_value = "adc", value_parser = parse_credentials)]
+        credentials: GcpCloudTraceCredentials,
+        /// OTLP/gRPC listen address
+        #[arg(long, default_value = "0.0.0.0:4317")]
+        grpc_addr: std::net::SocketAddr,
+        /// OTLP/HTTP listen address
+        #[arg(long, default_value = "0.0.0.0:4318")]
+        http_addr: std::net::SocketAddr,
+    },
+}
+
+/// Token source for endpoints without authentication (e.g. emulators),
+/// sending a placeholder token instead of looking up credentials.
+struct NoCredentialsTokenSource;
+
+#[async_trait::async_trait]
+impl Source for NoCredentialsTokenSource {
+    async fn token(&self) -> gcloud_sdk::error::Result<Token> {
+        Ok(Token::new(
+            "Bearer".to_string(),
+            SecretValue::from("none"),
+            chrono::Utc::now() + chrono::Duration::days(1),
+        ))
+    }
+}
+
+fn parse_credentials(value: &str) -> Result<GcpCloudTraceCredentials, String> {
+    match value {
+        "adc" => Ok(GcpCloudTraceCredentials::Default),
+        "none" => Ok(GcpCloudTraceCredentials::ExternalSource(Arc::new(
+            NoCredentialsTokenSource,
+        ))),
+        "" => Err("expected adc, none or a service account key file".to_string()),
+        path => Ok(GcpCloudTraceCredentials::File(PathBuf::from(path))),
+    }
+}
+
+enum TraceFile {
+    Otlp(ExportTraceServiceRequest),
+    CloudTrace(BatchWriteSpansRequest),
+}
+
+fn read_trace_file(path: &Path) -> Result<TraceFile, BoxedError> {
+    let json: serde_json::Value = serde_json::from_slice(&std::fs::read(path)?)?;
+    if json.get("resourceSpans").is_some() {
+        Ok(TraceFile::Otlp(serde_json::from_value(json)?))
+    } else if json.get("spans").is_some() {
+        Ok(TraceFile::CloudTrace(
+            GcpCloudTraceJson::batch_write_spans_request_from_json(&json)?,
+        ))
+    } else {
+        Err(format!(
+            "{}: unknown file format. Expected OTLP JSON or BatchWriteSpansRequest JSON",
+            path.display()
+        )
+        .into())
+    }
+}
+
+fn convert_otlp(request: ExportTraceServiceRequest, project: &str) -> BatchWriteSpansRequest {
+    let mut batch_request = BatchWriteSpansRequest {
+        name: format!("projects/{project}"),
+        ..BatchWriteSpansRequest::default()
+    };
+    for resource_spans in OtlpTraceConverter::convert_request(request) {
+        let converter =
+            SpanConverter::new(project.to_string()).with_resource(resource_spans.resource);
+        batch_request.spans.extend(
+            resource_spans
+                .spans
+                .iter()
+                .map(|span| converter.convert_span(span)),
+        );
+    }
+    SpanConverter::fill_child_span_counts(&mut batch_request.spans);
+    batch_request
+}
+
+fn to_cloud_trace_request(
+    trace_file: TraceFile,
+    project: Option<&str>,
+) -> Result<BatchWriteSpansRequest, BoxedError> {
+    match trace_file {
+        TraceFile::Otlp(request) => {
+            let project = project.ok_or("Project id is required for OTLP JSON files")?;
+            Ok(convert_otlp(request, project))
+        }
+        TraceFile::CloudTrace(request) => Ok(request),
+    }
+}
+
+fn validate_span(
+    span: &SpanData,
+    resource_attributes_count: usize,
+    limits: &SpanConverterLimits,
+) -> Vec<String> {
+    let mut issues = Vec::new();
+
+    if span.span_context.trace_id() == TraceId::INVALID {
+        issues.push("invalid trace id".to_string());
+    }
+    if span.span_context.span_id() == SpanId::INVALID {
+        issues.push("invalid span id".to_string());
+    }
+    if span.name.len() > limits.max_display_name_len {
+        issues.push(format!(
+            "name is {} bytes, it will be truncated to {} bytes",
+            span.name.len(),
+            limits.max_display_name_len
+        ));
+    }
+    // The converter marker attribute isn't exported
+    let attributes_count = span
+        .attributes
+        .iter()
+        .filter(|kv| kv.key.as_str() != SpanConverter::PARENT_SPAN_IS_REMOTE_ATTRIBUTE)
+        .count()
+        + resource_attributes_count;
+    if attributes_count > limits.max_attributes {
+        issues.push(format!(
+            "{} span and resource attributes, {} will be dropped",
+            attributes_count,
+            attributes_count - limits.max_attributes
+        ));
+    }
+    for kv in span
+        .attributes
+        .iter()
+        .chain(span.events.iter().flat_map(|event| event.attributes.iter()))
+    {
+        if kv.key.as_str().len() > MAX_ATTRIBUTE_KEY_LEN {
+            issues.push(format!(
+                "attribute key {} is longer than {MAX_ATTRIBUTE_KEY_LEN} bytes",
+                kv.key
+            ));
+        }
+        let value_len = kv.value.as_str().len();
+        if value_len > limits.max_attribute_value_len {
+            issues.push(format!(
+                "attribute {} value is {value_len} bytes, it will be truncated to {} bytes",
+                kv.key, limits.max_attribute_value_len
+            ));
+        }
+    }
+    if span.events.len() > limits.max_time_events {
+        issues.push(format!(
+            "{} events, {} will be dropped",
+            span.events.len(),
+            span.events.len() - limits.max_time_events
+        ));
+    }
+    for event in span.events.iter() {
+        if event.name.len() > limits.max_annotation_description_len {
+            issues.push(format!(
+                "event name {} will be truncated to {} bytes",
+                event.name, limits.max_annotation_description_len
+            ));
+        }
+        if event.attributes.len() > limits.max_attributes {
+            issues.push(format!(
+                "event {} has {} attributes, {} will be dropped",
+                event.name,
+                event.attributes.len(),
+                event.attributes.len() - limits.max_attributes
+            ));
+        }
+    }
+    if span.links.len() > limits.max_links {
+        issues.push(format!(
+            "{} links, {} will be dropped",
+            span.links.len(),
+            span.links.len() - limits.max_links
+        ));
+    }
+
+    issues
+}
+
+fn validate(file: &Path) -> Result<bool, BoxedError> {
+    let TraceFile::Otlp(request) = read_trace_file(file)? else {
+        return Err("Only OTLP JSON files can be validated".into());
+    };
+    let limits = SpanConverterLimits::new();
+    let mut spans_count = 0;
+    let mut invalid_spans_count = 0;
+
+    for resource_spans in OtlpTraceConverter::convert_request(request) {
+        let resource_attributes_count = resource_spans.resource.len();
+        for span in &resource_spans.spans {
+            spans_count += 1;
+            let issues = validate_span(span, resource_attributes_count, &limits);
+            if !issues.is_empty() {
+                invalid_spans_count += 1;
+                println!(
+                    "{}/{} '{}':",
+                    span.span_context.trace_id(),
+                    span.span_context.span_id(),
+                    span.name
+                );
+                for issue in issues {
+                    println!("  - {issue}");
+                }
+            }
+        }
+    }
+
+    println!("{spans_count} spans checked, {invalid_spans_count} exceed Cloud Trace limits");
+    Ok(invalid_spans_count == 0)
+}
+
+fn span_trace_id(span: &GcpSpan) -> &str {
+    span.name.split('/').nth(3).unwrap_or_default()
+}
+
+fn span_duration_ms(span: &GcpSpan) -> Option<f64> {
+    let start = span.start_time.as_ref()?;
+    let end = span.end_time.as_ref()?;
+    Some(
+        (end.seconds - start.seconds) as f64 * 1000.0
+            + (end.nanos - start.nanos) as f64 / 1_000_000.0,
+    )
+}
+
+fn span_tree_lines<'a>(
+    span: &'a GcpSpan,
+    children_by_parent: &HashMap<&str, Vec<&'a GcpSpan>>,
+    depth: usize,
+    visited: &mut HashSet<&'a str>,
+    lines: &mut Vec<String>,
+) {
+    // Span ids in files aren't trusted, so parent cycles and duplicated ids are printed only once
+    if !visited.insert(span.span_id.as_str()) {
+        return;
+    }
+    lines.push(format!(
+        "{}{} [{}] {} {}{}",
+        "  ".repeat(depth + 1),
+        span.display_name
+            .as_ref()
+            .map(|name| name.value.as_str())
+            .unwrap_or_default(),
+        span.span_kind().as_str_name(),
+        span.span_id,
+        span_duration_ms(span)
+            .map(|ms| format!("{ms:.3}ms"))
+            .unwrap_or_default(),
+        span.status
+            .as_ref()
+            .filter(|status| status.code != 0)
+            .map(|status| format!(" ERROR({}): {}", status.code, status.message))
+            .unwrap_or_default()
+    ));
+    for child in children_by_parent
+        .get(span.span_id.as_str())
+        .into_iter()
+        .flatten()
+    {
+        span_tree_lines(child, children_by_parent, depth + 1, visited, lines);
+    }
+}
+
+fn tree_lines(request: &BatchWriteSpansRequest) -> Vec<String> {
+    let mut traces: Vec<(&str, Vec<&GcpSpan>)> = Vec::new();
+    for span in &request.spans {
+        let trace_id = span_trace_id(span);
+        match traces.iter_mut().find(|(id, _)| *id == trace_id) {
+            Some((_, spans)) => spans.push(span),
+            None => traces.push((trace_id, vec![span])),
+        }
+    }
+
+    let mut lines = Vec::new();
+    for (trace_id, mut spans) in traces {
+        spans.sort_by_key(|span| {
+            span.start_time
+                .as_ref()
+                .map(|ts| (ts.seconds, ts.nanos))
+                .unwrap_or_default()
+        });
+        let mut children_by_parent: HashMap<&str, Vec<&GcpSpan>> = HashMap::new();
+        let mut roots = Vec::new();
+        for span in &spans {
+            if spans.iter().any(|s| s.span_id == span.parent_span_id) {
+                children_by_parent
+                    .entry(span.parent_span_id.as_str())
+                    .or_default()
+                    .push(span);
+            } else {
+                roots.push(*span);
+            }
+        }
+
+        lines.push(format!("Trace {trace_id} ({} spans)", spans.len()));
+        let mut visited = HashSet::new();
+        for root in roots {
+            span_tree_lines(root, &children_by_parent, 0, &mut visited, &mut lines);
+        }
+        // Spans in parent cycles aren't reachable from the roots
+        for span in &spans {
+            if !visited.contains(span.span_id.as_str()) {
+                span_tree_lines(span, &children_by_parent, 0, &mut visited, &mut lines);
+            }
+        }
+    }
+    lines
+}
+
+fn print_tree(request: &BatchWriteSpansRequest) {
+    for line in tree_lines(request) {
+        println!("{line}");
+    }
+}
+
+/// Moves the request and its spans (named `projects/PROJECT/traces/TRACE_ID/spans/SPAN_ID`)
+/// to another project.
+fn set_project(request: &mut BatchWriteSpansRequest, project: &str) -> Result<(), BoxedError> {
+    request.name = format!("projects/{project}");
+    for span in &mut request.spans {
+        match span.name.split_once("/traces/") {
+            Some((span_project, trace_name)) if span_project.starts_with("projects/") => {
+                span.name = format!("projects/{project}/traces/{trace_name}");
+            }
+            _ => {
+                return Err(format!(
+                    "Invalid span name: {}. Expected projects/[PROJECT_ID]/traces/[TRACE_ID]/spans/[SPAN_ID]",
+                    span.name
+                )
+                .into())
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn upload(
+    request: BatchWriteSpansRequest,
+    endpoint: &str,
+    credentials: &GcpCloudTraceCredentials,
+) -> Result<(), BoxedError> {
+    let spans_count = request.spans.len();
+    let client: GoogleApi<TraceServiceClient<GoogleAuthMiddleware>> =
+        GoogleApi::from_function_with_token_source(
+            TraceServiceClient::new,
+            endpoint,
+            None,
+            GCP_DEFAULT_SCOPES.clone(),
+            credentials.to_token_source_type(),
+        )
+        .await?;
+    client.batch_write_spans(request).await?;
+    println!("{spans_count} spans uploaded");
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), BoxedError> {
+    let cli = Cli::parse();
+
+    match cli.command {
+        Command::Validate { file } => {
+            if !validate(&file)? {
+                std::process::exit(1);
+            }
+        }
+        Command::Convert {
+            file,
+            project,
+            output,
+        } => {
+            let request = to_cloud_trace_request(read_trace_file(&file)?, Some(&project))?;
+            let json = serde_json::to_string_pretty(
+                &GcpCloudTraceJson::batch_write_spans_request_to_json(&request),
+            )?;
+            match output {
+                Some(output) => std::fs::write(output, json)?,
+                None => println!("{json}"),
+            }
+        }
+        Command::Upload {
+            file,
+            project,
+            endpoint,
+            credentials,
+        } => {
+            let mut request = to_cloud_trace_request(read_trace_file(&file)?, project.as_deref())?;
+            if let Some(project) = project {
+                set_project(&mut request, &project)?;
+            }
+            upload(request, &endpoint, &credentials).await?;
+        }
+        Command::Tree { file } => {
+            let request = to_cloud_trace_request(read_trace_file(&file)?, Some("-"))?;
+            print_tree(&request);
+        }
+        #[cfg(feature = "otlp-receiver")]
+        Command::Receive {
+            project,
+            endpoint,
+            credentials,
+            grpc_addr,
+            http_addr,
+        } => {
+            let receiver = GcpCloudTraceExporterBuilder::new(project)
+                .with_cloud_trace_api_url(endpoint)
+                .with_credentials(credentials)
+                .create_otlp_receiver(GcpOtlpReceiverConfig::new())
+                .await?;
+            println!("Receiving OTLP spans on {grpc_addr} (gRPC) and {http_addr} (HTTP)");
+            tokio::select! {
+                result = receiver.serve(Some(grpc_addr), Some(http_addr)) => result?,
+                _ = tokio::signal::ctrl_c() => {}
+            }
+            receiver.shutdown().await;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_span(project: &str, span_id: &str, parent_span_id: &str) -> GcpSpan {
+        GcpSpan {
+            name: format!(
+                "projects/{project}/traces/0af7651916cd43dd8448eb211c80319c/spans/{span_id}"
+            ),
+            span_id: span_id.to_string(),
+            parent_span_id: parent_span_id.to_string(),
+            ..GcpSpan::default()
+        }
+    }
+
+    #[test]
+    fn set_project_renames_spans() {
+        let mut request = BatchWriteSpansRequest {
+            name: "projects/old".to_string(),
+            spans: vec![
+                test_span("old", "b7ad6b7169203331", ""),
+                test_span("other", "00f067aa0ba902b7", "b7ad6b7169203331"),
+            ],
+        };
+        set_project(&mut request, "new").unwrap();
+        assert_eq!(request.name, "projects/new");
+        assert!(request.spans.iter().all(|span| span
+            .name
+            .starts_with("projects/new/traces/0af7651916cd43dd8448eb211c80319c/spans/")));
+    }
+
+    #[test]
+    fn set_project_rejects_invalid_span_names() {
+        let mut request = BatchWriteSpansRequest {
+            name: "projects/old".to_string(),
+            spans: vec![GcpSpan {
+                name: "b7ad6b7169203331".to_string(),
+                ..GcpSpan::default()
+            }],
+        };
+        assert!(set_project(&mut request, "new").is_err());
+    }
+
+    #[test]
+    fn tree_lines_handle_parent_cycles() {
+        let request = BatchWriteSpansRequest {
+            name: "projects/test".to_string(),
+            spans: vec![
+                test_span("test", "b7ad6b7169203331", ""),
+                test_span("test", "b7ad6b7169203331", "b7ad6b7169203331"),
+                test_span("test", "00f067aa0ba902b7", "00f067aa0ba902b8"),
+                test_span("test", "00f067aa0ba902b8", "00f067aa0ba902b7"),
+            ],
+        };
+        let lines = tree_lines(&request);
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("Trace 0af7651916cd43dd8448eb211c80319c (4 spans)"));
+    }
+
+    #[test]
+    fn parses_credentials_option() {
+        let upload_credentials = |args: &[&str]| {
+            let cli =
+                Cli::try_parse_from(["gcloud-trace", "upload", "trace.json"].iter().chain(args))
+                    .unwrap();
+            match cli.command {
+                Command::Upload { credentials, .. } => credentials,
+                _ => unreachable!(),
+            }
+        };
+
+        assert!(matches!(
+            upload_credentials(&[]),
+            GcpCloudTraceCredentials::Default
+        ));
+        assert!(matches!(
+            upload_credentials(&["--credentials", "adc"]),
+            GcpCloudTraceCredentials::Default
+        ));
+        assert!(matches!(
+            upload_credentials(&["--credentials", "none"]),
+            GcpCloudTraceCredentials::ExternalSource(_)
+        ));
+        assert!(matches!(
+            upload_credentials(&["--credentials", "/keys/sa.json"]),
+            GcpCloudTraceCredentials::File(path) if path == Path::new("/keys/sa.json")
+        ));
+        assert!(
+            Cli::try_parse_from(["gcloud-trace", "upload", "trace.json", "--credentials", ""])
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn no_credentials_use_placeholder_token() {
+        let token = NoCredentialsTokenSource.token().await.unwrap();
+        assert_eq!(token.header_value(), "Bearer none");
+    }
+}
@@ -0,0 +1,107 @@
+use crate::TraceExportResult;
+use async_trait::async_trait;
+use gcloud_sdk::*;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Credentials used to authenticate Google API calls.
+#[derive(Clone, Default)]
+pub enum GcpCloudTraceCredentials {
+    /// Application Default Credentials (default).
+    #[default]
+    Default,
+    /// Service account key JSON.
+    Json(String),
+    /// Path to a service account key JSON file.
+    File(PathBuf),
+    /// Default service account from the metadata server.
+    MetadataServer,
+    /// Specified service account from the metadata server.
+    MetadataServerWithAccount(String),
+    /// Custom token source (e.g. static tokens for a local fake server).
+    ExternalSource(Arc<dyn Source + Send + Sync>),
+}
+
+impl std::fmt::Debug for GcpCloudTraceCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GcpCloudTraceCredentials::Default => write!(f, "Default"),
+            GcpCloudTraceCredentials::Json(_) => write!(f, "Json"),
+            GcpCloudTraceCredentials::File(path) => write!(f, "File({path:?})"),
+            GcpCloudTraceCredentials::MetadataServer => write!(f, "MetadataServer"),
+            GcpCloudTraceCredentials::MetadataServerWithAccount(account) => {
+                write!(f, "MetadataServerWithAccount({account})")
+            }
+            GcpCloudTraceCredentials::ExternalSource(_) => write!(f, "ExternalSource"),
+        }
+    }
+}
+
+struct SharedTokenSource(Arc<dyn Source + Send + Sync>);
+
+#[async_trait]
+impl Source for SharedTokenSource {
+    async fn token(&self) -> gcloud_sdk::error::Result<Token> {
+        self.0.token().await
+    }
+}
+
+impl GcpCloudTraceCredentials {
+    pub fn to_token_source_type(&self) -> TokenSourceType {
+        match self {
+            GcpCloudTraceCredentials::Default => TokenSourceType::Default,
+            GcpCloudTraceCredentials::Json(json) => TokenSourceType::Json(json.clone()),
+            GcpCloudTraceCredentials::File(path) => TokenSourceType::File(path.clone()),
+            GcpCloudTraceCredentials::MetadataServer => TokenSourceType::MetadataServer,
+            GcpCloudTraceCredentials::MetadataServerWithAccount(account) => {
+                TokenSourceType::MetadataServerWithAccount(account.clone())
+            }
+            GcpCloudTraceCredentials::ExternalSource(source) => {
+                TokenSourceType::ExternalSource(Box::new(SharedTokenSource(source.clone())))
+            }
+        }
+    }
+}
+
+pub(crate) async fn create_google_api<C>(
+    builder_fn: fn(GoogleAuthMiddleware) -> C,
+    api_url: &str,
+    credentials: &GcpCloudTraceCredentials,
+    headers: HeaderMap,
+) -> TraceExportResult<GoogleApi<C>>
+where
+    C: Clone + Send,
+{
+    Ok(GoogleApi::from_function_with_token_source_and_headers(
+        builder_fn,
+        api_url,
+        None,
+        GCP_DEFAULT_SCOPES.clone(),
+        credentials.to_token_source_type(),
+        headers,
+    )
+    .await?)
+}
+
+/// Static token source for tests against local fake servers.
+#[cfg(test)]
+pub(crate) struct StaticTokenSource;
+
+#[cfg(test)]
+impl StaticTokenSource {
+    pub(crate) fn credentials() -> GcpCloudTraceCredentials {
+        GcpCloudTraceCredentials::ExternalSource(Arc::new(StaticTokenSource))
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl Source for StaticTokenSource {
+    async fn token(&self) -> gcloud_sdk::error::Result<Token> {
+        Ok(Token::new(
+            "Bearer".to_string(),
+            SecretValue::from("test-token"),
+            chrono::Utc::now() + chrono::Duration::hours(1),
+        ))
+    }
+}
@@ -45,6 +45,22 @@ impl std::error::Error for GcloudTraceSystemError {}
 #[derive(Debug, Eq, PartialEq, Clone, Builder)]
 pub struct GcloudTraceNetworkError {
     pub message: String,
+    /// gRPC status code of the failed call (REST API statuses are mapped to the same codes).
+    pub code: Option<gcloud_sdk::tonic::Code>,
+}
+
+impl GcloudTraceNetworkError {
+    /// Whether the call can be retried: the service is unavailable, overloaded or timed out.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self.code,
+            Some(
+                gcloud_sdk::tonic::Code::Unavailable
+                    | gcloud_sdk::tonic::Code::DeadlineExceeded
+                    | gcloud_sdk::tonic::Code::ResourceExhausted
+            )
+        )
+    }
 }
 
 impl std::fmt::Display for GcloudTraceNetworkError {
@@ -66,7 +82,9 @@ impl From<gcloud_sdk::error::Error> for GcloudTraceError {
 
 impl From<gcloud_sdk::tonic::Status> for GcloudTraceError {
     fn from(status: gcloud_sdk::tonic::Status) -> Self {
-        GcloudTraceError::NetworkError(GcloudTraceNetworkError::new(format!("{status}")))
+        GcloudTraceError::NetworkError(
+            GcloudTraceNetworkError::new(format!("{status}")).with_code(status.code()),
+        )
     }
 }
 
@@ -75,3 +93,19 @@ impl ExportError for GcloudTraceError {
         "GoogleCloudTraceExporter"
     }
 }
+
+#[cfg(feature = "rest-transport")]
+impl From<reqwest::Error> for GcloudTraceError {
+    fn from(err: reqwest::Error) -> Self {
+        let code = if err.is_timeout() {
+            Some(gcloud_sdk::tonic::Code::DeadlineExceeded)
+        } else if err.is_connect() {
+            Some(gcloud_sdk::tonic::Code::Unavailable)
+        } else {
+            None
+        };
+        GcloudTraceError::NetworkError(
+            GcloudTraceNetworkError::new(format!("{err}")).opt_code(code),
+        )
+    }
+}
@@ -0,0 +1,636 @@
+use crate::{
+    BinaryTraceContextPropagator, GcpCloudTraceContextPropagator, SdkTracer, GRPC_TRACE_BIN_HEADER,
+};
+use futures::future::BoxFuture;
+use futures::FutureExt as _;
+use gcloud_sdk::tonic;
+use gcloud_sdk::tonic::metadata::{KeyAndValueRef, MetadataKey, MetadataMap, MetadataValue};
+use gcloud_sdk::tonic::service::Interceptor;
+use http::{HeaderMap, Request, Response};
+use http_body::{Body, Frame, SizeHint};
+use opentelemetry::context::FutureExt as OtelContextFutureExt;
+use opentelemetry::propagation::{Extractor, Injector, TextMapPropagator};
+use opentelemetry::trace::{SpanKind, Status, TraceContextExt, Tracer, TracerProvider};
+use opentelemetry::{Context, InstrumentationScope, KeyValue};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use std::fmt::Display;
+use std::pin::Pin;
+use std::task::Poll;
+use tower::{Layer, Service};
+
+struct MetadataInjector<'a>(&'a mut MetadataMap);
+
+impl Injector for MetadataInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(key), Ok(value)) = (
+            MetadataKey::from_bytes(key.as_bytes()),
+            MetadataValue::try_from(value),
+        ) {
+            self.0.insert(key, value);
+        }
+    }
+}
+
+struct MetadataExtractor<'a>(&'a MetadataMap);
+
+impl Extractor for MetadataExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0
+            .iter()
+            .filter_map(|key_and_value| match key_and_value {
+                KeyAndValueRef::Ascii(key, _) => Some(key.as_str()),
+                KeyAndValueRef::Binary(_, _) => None,
+            })
+            .collect()
+    }
+}
+
+/// Injects the span context of `cx` as `traceparent` and `grpc-trace-bin`.
+pub fn inject_grpc_metadata(cx: &Context, metadata: &mut MetadataMap) {
+    let span = cx.span();
+    let span_context = span.span_context();
+    if span_context.is_valid() {
+        TraceContextPropagator::new().inject_context(cx, &mut MetadataInjector(metadata));
+        metadata.insert_bin(
+            GRPC_TRACE_BIN_HEADER,
+            MetadataValue::from_bytes(&BinaryTraceContextPropagator::to_bytes(span_context)),
+        );
+    }
+}
+
+/// Extracts the remote parent from `traceparent`, `grpc-trace-bin` or `X-Cloud-Trace-Context`.
+pub fn extract_grpc_metadata(metadata: &MetadataMap) -> Context {
+    let extractor = MetadataExtractor(metadata);
+    let cx = TraceContextPropagator::new().extract(&extractor);
+    if cx.span().span_context().is_valid() {
+        return cx;
+    }
+    match metadata
+        .get_bin(GRPC_TRACE_BIN_HEADER)
+        .and_then(|value| value.to_bytes().ok())
+        .and_then(|bytes| BinaryTraceContextPropagator::from_bytes(&bytes))
+    {
+        Some(span_context) => Context::new().with_remote_span_context(span_context),
+        None => GcpCloudTraceContextPropagator::new().extract(&extractor),
+    }
+}
+
+/// Client interceptor propagating the current context as `traceparent` and `grpc-trace-bin`.
+#[derive(Debug, Clone, Default)]
+pub struct GcpGrpcClientInterceptor;
+
+impl Interceptor for GcpGrpcClientInterceptor {
+    fn call(
+        &mut self,
+        mut request: tonic::Request<()>,
+    ) -> Result<tonic::Request<()>, tonic::Status> {
+        inject_grpc_metadata(&Context::current(), request.metadata_mut());
+        Ok(request)
+    }
+}
+
+/// Server interceptor extracting the remote parent context into the request extensions,
+/// so handlers can get it with `request.extensions().get::<opentelemetry::Context>()`.
+#[derive(Debug, Clone, Default)]
+pub struct GcpGrpcServerInterceptor;
+
+impl Interceptor for GcpGrpcServerInterceptor {
+    fn call(
+        &mut self,
+        mut request: tonic::Request<()>,
+    ) -> Result<tonic::Request<()>, tonic::Status> {
+        let cx = extract_grpc_metadata(request.metadata());
+        request.extensions_mut().insert(cx);
+        Ok(request)
+    }
+}
+
+/// Tower layer for tonic clients and servers creating CLIENT or SERVER spans for gRPC calls
+/// with the `rpc.*` attributes, and propagating the context the same way as the interceptors.
+///
+/// Spans end with the response stream. The status is read from the `grpc-status` and `grpc-message`
+/// trailers, or the response headers for trailers-only responses (which tonic uses for errors).
+/// Streams ending without a status are recorded as OK, streams failing before a status as UNKNOWN,
+/// and response bodies dropped before their end as CANCELLED.
+#[derive(Debug, Clone)]
+pub struct GcpGrpcTraceLayer {
+    tracer: SdkTracer,
+    span_kind: SpanKind,
+}
+
+impl GcpGrpcTraceLayer {
+    /// Layer for `tonic::transport::Server::builder().layer(..)`.
+    pub fn server(tracer_provider: &SdkTracerProvider) -> Self {
+        Self::with_span_kind(tracer_provider, SpanKind::Server)
+    }
+
+    /// Layer for channels (e.g. `ServiceBuilder::new().layer(..).service(channel)`).
+    pub fn client(tracer_provider: &SdkTracerProvider) -> Self {
+        Self::with_span_kind(tracer_provider, SpanKind::Client)
+    }
+
+    fn with_span_kind(tracer_provider: &SdkTracerProvider, span_kind: SpanKind) -> Self {
+        let scope = InstrumentationScope::builder("opentelemetry-gcloud")
+            .with_version(env!("CARGO_PKG_VERSION"))
+            .build();
+        Self {
+            tracer: tracer_provider.tracer_with_scope(scope),
+            span_kind,
+        }
+    }
+
+    // Server spans only report errors caused by the server
+    fn is_error(&self, code: tonic::Code) -> bool {
+        match self.span_kind {
+            SpanKind::Server => matches!(
+                code,
+                tonic::Code::Unknown
+                    | tonic::Code::DeadlineExceeded
+                    | tonic::Code::Unimplemented
+                    | tonic::Code::Internal
+                    | tonic::Code::Unavailable
+                    | tonic::Code::DataLoss
+            ),
+            _ => code != tonic::Code::Ok,
+        }
+    }
+
+    fn end_span(&self, cx: &Context, code: tonic::Code, message: Option<String>) {
+        let span = cx.span();
+        span.set_attribute(KeyValue::new("rpc.grpc.status_code", code as i64));
+        if self.is_error(code) {
+            span.set_status(Status::error(
+                message.unwrap_or_else(|| code.description().to_string()),
+            ));
+        }
+        span.end();
+    }
+}
+
+// Status code and percent-encoded message of gRPC trailers or trailers-only response headers
+fn grpc_status(headers: &HeaderMap) -> Option<(tonic::Code, Option<String>)> {
+    let code = headers
+        .get("grpc-status")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<i32>().ok())
+        .map(tonic::Code::from)?;
+    let message = headers
+        .get("grpc-message")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| {
+            percent_encoding::percent_decode_str(value)
+                .decode_utf8_lossy()
+                .into_owned()
+        });
+    Some((code, message))
+}
+
+impl<S> Layer<S> for GcpGrpcTraceLayer {
+    type Service = GcpGrpcTraceService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GcpGrpcTraceService {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GcpGrpcTraceService<S> {
+    inner: S,
+    layer: GcpGrpcTraceLayer,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for GcpGrpcTraceService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Display + Send + 'static,
+    ReqBody: Send + 'static,
+    ResBody: Body + Send + 'static,
+{
+    type Response = Response<GcpGrpcTraceBody<ResBody>>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        // The service which was polled ready needs to be used for the call
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let layer = self.layer.clone();
+
+        let parent_cx = match layer.span_kind {
+            SpanKind::Server => {
+                extract_grpc_metadata(&MetadataMap::from_headers(req.headers().clone()))
+            }
+            _ => Context::current(),
+        };
+
+        // Paths are `/package.Service/Method`
+        let full_method = req.uri().path().trim_start_matches('/').to_string();
+        let mut attributes = vec![KeyValue::new("rpc.system", "grpc")];
+        if let Some((service, method)) = full_method.split_once('/') {
+            attributes.push(KeyValue::new("rpc.service", service.to_string()));
+            attributes.push(KeyValue::new("rpc.method", method.to_string()));
+        }
+        if let Some(host) = req.uri().host() {
+            attributes.push(KeyValue::new("server.address", host.to_string()));
+        }
+
+        let span = layer
+            .tracer
+            .span_builder(full_method)
+            .with_kind(layer.span_kind.clone())
+            .with_attributes(attributes)
+            .start_with_context(&layer.tracer, &parent_cx);
+        let cx = parent_cx.with_span(span);
+
+        if layer.span_kind == SpanKind::Client {
+            let mut metadata = MetadataMap::from_headers(std::mem::take(req.headers_mut()));
+            inject_grpc_metadata(&cx, &mut metadata);
+            *req.headers_mut() = metadata.into_headers();
+        }
+
+        async move {
+            match inner.call(req).with_context(cx.clone()).await {
+                Ok(response) => {
+                    let headers_status = grpc_status(response.headers());
+                    Ok(response.map(|body| GcpGrpcTraceBody {
+                        inner: body,
+                        span: Some(GrpcSpan {
+                            cx,
+                            layer,
+                            headers_status,
+                        }),
+                    }))
+                }
+                Err(err) => {
+                    let span = cx.span();
+                    span.set_status(Status::error(err.to_string()));
+                    span.end();
+                    Err(err)
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+struct GrpcSpan {
+    cx: Context,
+    layer: GcpGrpcTraceLayer,
+    headers_status: Option<(tonic::Code, Option<String>)>,
+}
+
+impl GrpcSpan {
+    fn end(self, status: Option<(tonic::Code, Option<String>)>) {
+        let (code, message) = status
+            .or(self.headers_status)
+            .unwrap_or((tonic::Code::Ok, None));
+        self.layer.end_span(&self.cx, code, message);
+    }
+}
+
+pin_project_lite::pin_project! {
+    /// Response body of [`GcpGrpcTraceService`] ending the call span with the response stream.
+    pub struct GcpGrpcTraceBody<B: Body> {
+        #[pin]
+        inner: B,
+        span: Option<GrpcSpan>,
+    }
+
+    impl<B: Body> PinnedDrop for GcpGrpcTraceBody<B> {
+        fn drop(this: Pin<&mut Self>) {
+            let this = this.project();
+            if let Some(span) = this.span.take() {
+                // Empty bodies (e.g. of trailers-only responses) may be dropped without being polled
+                if this.inner.is_end_stream() {
+                    span.end(None);
+                } else {
+                    span.end(Some((tonic::Code::Cancelled, None)));
+                }
+            }
+        }
+    }
+}
+
+impl<B: Body> Body for GcpGrpcTraceBody<B>
+where
+    B::Error: Display,
+{
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let result = std::task::ready!(this.inner.poll_frame(cx));
+        match &result {
+            Some(Ok(frame)) => {
+                if let Some(trailers) = frame.trailers_ref() {
+                    if let Some(span) = this.span.take() {
+                        span.end(grpc_status(trailers));
+                    }
+                }
+            }
+            Some(Err(err)) => {
+                if let Some(span) = this.span.take() {
+                    // The stream failed before a status was received
+                    span.end(Some((tonic::Code::Unknown, Some(err.to_string()))));
+                }
+            }
+            None => {
+                if let Some(span) = this.span.take() {
+                    span.end(None);
+                }
+            }
+        }
+        Poll::Ready(result)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::{BodyExt, Empty, StreamBody};
+    use opentelemetry::trace::{SpanContext, SpanId, TraceFlags, TraceId, TraceState};
+    use opentelemetry_sdk::trace::{InMemorySpanExporter, SpanData};
+    use std::convert::Infallible;
+    use tower::ServiceExt;
+
+    type TestBody = StreamBody<
+        futures::stream::Iter<std::vec::IntoIter<Result<Frame<bytes::Bytes>, Infallible>>>,
+    >;
+
+    fn test_provider() -> (SdkTracerProvider, InMemorySpanExporter) {
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        (provider, exporter)
+    }
+
+    fn streaming_body(trailers: HeaderMap) -> TestBody {
+        StreamBody::new(futures::stream::iter(vec![
+            Ok(Frame::data(bytes::Bytes::from_static(b"message"))),
+            Ok(Frame::trailers(trailers)),
+        ]))
+    }
+
+    fn grpc_headers(code: tonic::Code, message: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("grpc-status", (code as i32).into());
+        headers.insert("grpc-message", message.parse().unwrap());
+        headers
+    }
+
+    fn test_request() -> Request<()> {
+        Request::builder()
+            .uri("http://localhost/test.Service/Method")
+            .body(())
+            .unwrap()
+    }
+
+    fn status_code(span: &SpanData) -> Option<i64> {
+        span.attributes
+            .iter()
+            .find(|kv| kv.key.as_str() == "rpc.grpc.status_code")
+            .and_then(|kv| match kv.value {
+                opentelemetry::Value::I64(code) => Some(code),
+                _ => None,
+            })
+    }
+
+    #[tokio::test]
+    async fn ends_spans_with_trailers_status() {
+        let (provider, exporter) = test_provider();
+        let service =
+            GcpGrpcTraceLayer::client(&provider).layer(tower::service_fn(|_: Request<()>| async {
+                Ok::<_, Infallible>(Response::new(streaming_body(grpc_headers(
+                    tonic::Code::NotFound,
+                    "user%20not%20found%3A%20%E2%9C%93",
+                ))))
+            }));
+
+        let response = service.oneshot(test_request()).await.unwrap();
+        assert!(exporter.get_finished_spans().unwrap().is_empty());
+
+        let body = response.into_body().collect().await.unwrap();
+        assert!(body.trailers().is_some());
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].name, "test.Service/Method");
+        assert_eq!(status_code(&spans[0]), Some(tonic::Code::NotFound as i64));
+        assert_eq!(spans[0].status, Status::error("user not found: ✓"));
+    }
+
+    #[tokio::test]
+    async fn reads_trailers_only_responses() {
+        let (provider, exporter) = test_provider();
+        let service =
+            GcpGrpcTraceLayer::server(&provider).layer(tower::service_fn(|_: Request<()>| async {
+                let mut response = Response::new(Empty::<bytes::Bytes>::new());
+                *response.headers_mut() = grpc_headers(tonic::Code::Internal, "failed");
+                Ok::<_, Infallible>(response)
+            }));
+
+        // Dropped without being polled, since the body is empty
+        drop(service.oneshot(test_request()).await.unwrap());
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 1);
+        assert_eq!(status_code(&spans[0]), Some(tonic::Code::Internal as i64));
+        assert_eq!(spans[0].status, Status::error("failed"));
+    }
+
+    #[tokio::test]
+    async fn records_dropped_streams_as_cancelled() {
+        let (provider, exporter) = test_provider();
+        let service =
+            GcpGrpcTraceLayer::client(&provider).layer(tower::service_fn(|_: Request<()>| async {
+                Ok::<_, Infallible>(Response::new(streaming_body(grpc_headers(
+                    tonic::Code::Ok,
+                    "",
+                ))))
+            }));
+
+        let mut body = service.oneshot(test_request()).await.unwrap().into_body();
+        assert!(body.frame().await.unwrap().unwrap().is_data());
+        drop(body);
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 1);
+        assert_eq!(status_code(&spans[0]), Some(tonic::Code::Cancelled as i64));
+        assert!(matches!(spans[0].status, Status::Error { .. }));
+    }
+    #[tokio::test]
+    async fn records_body_errors() {
+        let (provider, exporter) = test_provider();
+        let service =
+            GcpGrpcTraceLayer::server(&provider).layer(tower::service_fn(|_: Request<()>| async {
+                let body = StreamBody::new(futures::stream::iter(vec![
+                    Ok(Frame::data(bytes::Bytes::from_static(b"message"))),
+                    Err("connection reset"),
+                ]));
+                Ok::<_, Infallible>(Response::new(body))
+            }));
+
+        let body = service.oneshot(test_request()).await.unwrap().into_body();
+        assert!(body.collect().await.is_err());
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 1);
+        assert_eq!(status_code(&spans[0]), Some(tonic::Code::Unknown as i64));
+        assert_eq!(spans[0].status, Status::error("connection reset"));
+    }
+
+    fn remote_span_context(trace_id: u128, span_id: u64) -> SpanContext {
+        SpanContext::new(
+            TraceId::from(trace_id),
+            SpanId::from(span_id),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        )
+    }
+
+    #[test]
+    fn client_interceptor_injects_both_formats() {
+        let span_context = remote_span_context(1, 2);
+        let _guard = Context::new()
+            .with_remote_span_context(span_context.clone())
+            .attach();
+
+        let request = GcpGrpcClientInterceptor
+            .call(tonic::Request::new(()))
+            .unwrap();
+        let metadata = request.metadata();
+        assert_eq!(
+            metadata.get("traceparent").unwrap(),
+            "00-00000000000000000000000000000001-0000000000000002-01"
+        );
+        let bytes = metadata
+            .get_bin(GRPC_TRACE_BIN_HEADER)
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+        assert_eq!(
+            BinaryTraceContextPropagator::from_bytes(&bytes),
+            Some(span_context)
+        );
+    }
+
+    #[test]
+    fn client_interceptor_skips_invalid_contexts() {
+        let request = GcpGrpcClientInterceptor
+            .call(tonic::Request::new(()))
+            .unwrap();
+        assert!(request.metadata().is_empty());
+    }
+
+    fn extracted_parent(metadata: MetadataMap) -> SpanContext {
+        let mut request = tonic::Request::new(());
+        *request.metadata_mut() = metadata;
+        let request = GcpGrpcServerInterceptor.call(request).unwrap();
+        request
+            .extensions()
+            .get::<Context>()
+            .unwrap()
+            .span()
+            .span_context()
+            .clone()
+    }
+
+    #[test]
+    fn server_interceptor_extracts_parents() {
+        let traceparent_context = remote_span_context(1, 2);
+        let binary_context = remote_span_context(3, 4);
+
+        let mut metadata = MetadataMap::new();
+        metadata.insert_bin(
+            GRPC_TRACE_BIN_HEADER,
+            MetadataValue::from_bytes(&BinaryTraceContextPropagator::to_bytes(&binary_context)),
+        );
+        assert_eq!(extracted_parent(metadata.clone()), binary_context);
+
+        // traceparent is preferred
+        metadata.insert(
+            "traceparent",
+            "00-00000000000000000000000000000001-0000000000000002-01"
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(extracted_parent(metadata), traceparent_context);
+
+        let mut metadata = MetadataMap::new();
+        metadata.insert(
+            "x-cloud-trace-context",
+            "00000000000000000000000000000005/6;o=1".parse().unwrap(),
+        );
+        assert_eq!(extracted_parent(metadata), remote_span_context(5, 6));
+
+        assert!(!extracted_parent(MetadataMap::new()).is_valid());
+    }
+
+    #[tokio::test]
+    async fn propagates_between_client_and_server_layers() {
+        let (provider, exporter) = test_provider();
+        // The client layer injects into the request headers read by the server layer
+        let server = GcpGrpcTraceLayer::server(&provider).layer(tower::service_fn(
+            |req: Request<()>| async move {
+                assert!(req.headers().contains_key("traceparent"));
+                assert!(req.headers().contains_key(GRPC_TRACE_BIN_HEADER));
+                let mut response = Response::new(Empty::<bytes::Bytes>::new());
+                *response.headers_mut() = grpc_headers(tonic::Code::Ok, "");
+                Ok::<_, Infallible>(response)
+            },
+        ));
+        let client = GcpGrpcTraceLayer::client(&provider).layer(tower::service_fn(
+            move |req: Request<()>| {
+                let server = server.clone();
+                async move {
+                    let response = server.oneshot(req).await?;
+                    drop(response);
+                    Ok::<_, Infallible>(Response::new(Empty::<bytes::Bytes>::new()))
+                }
+            },
+        ));
+        drop(client.oneshot(test_request()).await.unwrap());
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 2);
+        let (server_span, client_span) = (&spans[0], &spans[1]);
+        assert_eq!(server_span.span_kind, SpanKind::Server);
+        assert_eq!(client_span.span_kind, SpanKind::Client);
+        assert_eq!(
+            server_span.span_context.trace_id(),
+            client_span.span_context.trace_id()
+        );
+        assert_eq!(
+            server_span.parent_span_id,
+            client_span.span_context.span_id()
+        );
+        assert!(server_span.parent_span_is_remote);
+    }
+}
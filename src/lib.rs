@@ -47,6 +47,192 @@
 //!    GcpCloudTraceExporterBuilder::new(google_project_id).with_resource(resource).await?;
 //! ```
 //!
+//! ## Sampling
+//!
+//! [`GcpParentBasedSampler`] always samples requests traced by Google Cloud front ends
+//! (`X-Cloud-Trace-Context` with `o=1` or a sampled `traceparent`) and samples other traces
+//! using a local QPS based rate (about 1 trace per 10 seconds by default):
+//! ```ignore
+//!    opentelemetry::global::set_text_map_propagator(GcpCloudTraceContextPropagator::new());
+//!    gcp_trace_exporter.create_provider_from_builder(
+//!       SdkTracerProvider::builder()
+//!          .with_sampler(GcpParentBasedSampler::with_root_sampler(GcpQpsSampler::new(1.0)))
+//!    )
+//! ```
+//!
+//! To cap Cloud Trace ingestion cost, traces can be limited using [`RateLimitingSampler`]:
+//! ```ignore
+//!    GcpCloudTraceExporterBuilder::new(google_project_id)
+//!       .with_rate_limiting_sampler(RateLimitingSampler::new(10.0).with_route_budget("/health", 0.1));
+//! ```
+//!
+//! Traces can also be sampled after they complete using [`TailSamplingSpanProcessor`],
+//! e.g. to keep all traces with errors or slow requests and a baseline of others:
+//! ```ignore
+//!    GcpCloudTraceExporterBuilder::new(google_project_id)
+//!       .with_tail_sampling(TailSamplingConfig::new(vec![
+//!          TailSamplingPolicy::Error,
+//!          TailSamplingPolicy::RootLatency(Duration::from_secs(1)),
+//!          TailSamplingPolicy::Probabilistic(0.01),
+//!       ]));
+//! ```
+//!
+//! ## REST transport
+//!
+//! By default spans are sent using gRPC. If HTTP/2 gRPC isn't available in your environment,
+//! enable the `rest-transport` feature and use the REST/JSON API over HTTP/1.1:
+//! ```ignore
+//!    GcpCloudTraceExporterBuilder::new(google_project_id)
+//!       .with_transport(GcpCloudTraceExporterTransport::Rest);
+//! ```
+//!
+//! ## OTLP backend
+//!
+//! With the `otlp-backend` feature, spans can be sent using OTLP to the Google Telemetry API
+//! (`telemetry.googleapis.com`) instead of the Cloud Trace v2 API:
+//! ```ignore
+//!    GcpCloudTraceExporterBuilder::new(google_project_id)
+//!       .with_backend(GcpCloudTraceExporterBackend::Otlp);
+//! ```
+//!
+//! ## Span conversion
+//!
+//! The mapping from OpenTelemetry spans to Cloud Trace v2 spans is available as [`SpanConverter`]
+//! and can be configured for the exporter using `with_span_converter_options`:
+//! ```ignore
+//!    let converter = SpanConverter::new(google_project_id).with_resource(resource);
+//!    let gcp_span = converter.convert_span(&span_data);
+//!    // and back, e.g. to replay stored requests using other exporters
+//!    let span_data = converter.convert_gcp_span(&gcp_span)?;
+//! ```
+//!
+//! Cloud Trace attributes can't be floats or arrays, so floats are converted to strings keeping full precision,
+//! and arrays are encoded as JSON by default (see [`ArrayAttributeEncoding`] for the other options).
+//!
+//! Long values of the attributes configured with `with_spilled_attribute_keys` (e.g. `db.statement`)
+//! are exported as truncated previews with the full values in chunked annotations.
+//!
+//! `same_process_as_parent_span` is set from `SpanData::parent_span_is_remote`, and left unset when
+//! the `gcp.parent_span.is_remote` attribute marks it as unknown (OTLP and Zipkin spans without this information).
+//!
+//! Links are exported with their attributes, and can be marked as parent or child links
+//! using the `gcp.link.type` link attribute or a [`SpanLinkTypeClassifier`] in the converter options.
+//!
+//! ## Span filtering
+//!
+//! Spans can be dropped before conversion using declarative rules matching
+//! the span name, kind, attributes and status:
+//! ```ignore
+//!    GcpCloudTraceExporterBuilder::new(google_project_id)
+//!       .with_span_filter(SpanFilter::new(vec![
+//!          SpanFilterRule::new()
+//!             .with_name(SpanFilterMatch::Regex(Regex::new("^GET /health")?))
+//!             .with_drop_subtree(true),
+//!       ]));
+//! ```
+//! With the `span-filters-config` feature, rules can be loaded from TOML or YAML files
+//! using `SpanFilter::from_file`.
+//!
+//! ## Span names
+//!
+//! Span names can be rewritten before conversion to keep Cloud Trace display names low cardinality:
+//! ```ignore
+//!    GcpCloudTraceExporterBuilder::new(google_project_id)
+//!       .with_span_renamer(
+//!          SpanRenamer::new(vec![
+//!             SpanRenameRule::Template("{http.request.method} {http.route}".to_string()),
+//!             SpanRenameRule::Regex { regex: Regex::new(r"/\d+")?, replacement: "/{id}".to_string() },
+//!          ])
+//!          .with_cardinality_limit(1000, "other"),
+//!       );
+//! ```
+//!
+//! ## Baggage
+//!
+//! Allow-listed W3C Baggage entries can be copied to span attributes when spans start:
+//! ```ignore
+//!    GcpCloudTraceExporterBuilder::new(google_project_id)
+//!       .with_baggage_span_processor(BaggageSpanProcessor::new(["tenant.id"]).with_prefix("baggage."));
+//! ```
+//!
+//! ## Redaction
+//!
+//! Sensitive data (URL query parameters, bearer tokens, emails, card numbers and custom regexes)
+//! can be scrubbed from span names, attributes, status descriptions, events and links before export:
+//! ```ignore
+//!    GcpCloudTraceExporterBuilder::new(google_project_id)
+//!       .with_span_redactor(SpanRedactor::new().with_action(RedactionAction::Hash));
+//! ```
+//!
+//! ## Custom transport
+//!
+//! The Cloud Trace client can be replaced with your own implementation of [`CloudTraceTransport`]
+//! (e.g. a tonic client with interceptors or a recording client for tests):
+//! ```ignore
+//!    let exporter = GcpCloudTraceExporter::with_transport(google_project_id, resource, my_transport);
+//! ```
+//!
+//! ## Reading traces
+//!
+//! With the `trace-reader` feature, exported traces can be fetched back using the Cloud Trace v1 API
+//! (e.g. for end-to-end tests):
+//! ```ignore
+//!    let reader = gcp_trace_exporter.create_trace_reader().await?;
+//!    let trace = reader.get_trace(trace_id).await?;
+//!    let span_tree = trace.span_tree();
+//! ```
+//!
+//! ## OTLP receiver
+//!
+//! With the `otlp-receiver` feature, spans sent by other services using OTLP/gRPC or OTLP/HTTP
+//! can be forwarded to Cloud Trace without running a collector:
+//! ```ignore
+//!    let receiver = gcp_trace_exporter.create_otlp_receiver(GcpOtlpReceiverConfig::new()).await?;
+//!    receiver.serve(Some("0.0.0.0:4317".parse()?), Some("0.0.0.0:4318".parse()?)).await?;
+//! ```
+//!
+//! With the `zipkin-receiver` feature, the HTTP server also accepts Zipkin v2 JSON spans
+//! on `/api/v2/spans`.
+//!
+//! ## Pub/Sub
+//!
+//! The context can be propagated through Pub/Sub message attributes using the `googclient_` prefixed keys
+//! of the Google Pub/Sub client libraries, with PRODUCER/CONSUMER spans linked together:
+//! ```ignore
+//!    let pubsub_tracer = GcpPubSubTracer::new(&tracer_provider);
+//!    let mut publish_span = pubsub_tracer.start_publish_span("my-topic", &mut message.attributes);
+//!    // ... publish the message
+//!    publish_span.end();
+//!
+//!    let mut receive_span = pubsub_tracer.start_receive_span("my-subscription", Some(&message.message_id), &message.attributes);
+//! ```
+//!
+//! ## HTTP server middleware
+//!
+//! With the `tower-layer` feature, [`GcpCloudTraceLayer`] creates SERVER spans for HTTP requests
+//! (e.g. in axum), continuing traces from `traceparent` or `X-Cloud-Trace-Context`:
+//! ```ignore
+//!    let app = Router::new()
+//!       .route("/users/{id}", get(get_user))
+//!       .layer(GcpCloudTraceLayer::new(tracer_provider.clone()).with_force_flush(true));
+//! ```
+//!
+//! ## gRPC context propagation
+//!
+//! [`BinaryTraceContextPropagator`] encodes and decodes the OpenCensus binary format
+//! of the `grpc-trace-bin` header used by Google gRPC services.
+//!
+//! With the `tonic-interceptors` feature, the context is propagated over gRPC metadata
+//! as `traceparent` and `grpc-trace-bin` using [`GcpGrpcClientInterceptor`] and [`GcpGrpcServerInterceptor`],
+//! and [`GcpGrpcTraceLayer`] creates CLIENT/SERVER spans with the `rpc.*` attributes:
+//! ```ignore
+//!    Server::builder()
+//!       .layer(GcpGrpcTraceLayer::server(&tracer_provider))
+//!       .add_service(MyServiceServer::new(my_service));
+//!
+//!    let client = MyServiceClient::with_interceptor(channel, GcpGrpcClientInterceptor);
+//! ```
+//!
 //! Have a look at full examples in the `examples` directory.
 //!
 
@@ -55,25 +241,132 @@
 pub mod errors;
 pub type TraceExportResult<E> = Result<E, crate::errors::GcloudTraceError>;
 
+mod baggage_processor;
+mod credentials;
 mod google_trace_exporter_client;
+mod propagator;
+mod pubsub;
+mod sampler;
+mod span_converter;
 mod span_exporter;
+mod span_filter;
+mod span_redactor;
+mod span_renamer;
+mod span_reverse_converter;
+mod tail_sampling;
+#[cfg(feature = "trace-reader")]
+mod trace_reader;
+mod transport;
+
+#[cfg(feature = "tonic-interceptors")]
+mod grpc_interceptors;
+#[cfg(feature = "otlp-conversion")]
+mod otlp_conversion;
+#[cfg(feature = "otlp-backend")]
+mod otlp_exporter_client;
+#[cfg(feature = "otlp-receiver")]
+mod otlp_receiver;
+#[cfg(feature = "json")]
+mod proto_json;
+#[cfg(feature = "rest-transport")]
+mod rest_transport;
+#[cfg(feature = "tower-layer")]
+mod tower_layer;
+#[cfg(feature = "zipkin-receiver")]
+mod zipkin_conversion;
 
 use crate::errors::GcloudTraceError;
+pub use baggage_processor::BaggageSpanProcessor;
+pub use credentials::GcpCloudTraceCredentials;
+pub use google_trace_exporter_client::GCP_CLOUD_TRACE_API_URL;
+#[cfg(feature = "tonic-interceptors")]
+pub use grpc_interceptors::*;
 use opentelemetry::trace::TracerProvider;
 use opentelemetry::InstrumentationScope;
 use opentelemetry_sdk::error::OTelSdkError;
 use opentelemetry_sdk::trace::span_processor_with_async_runtime::BatchSpanProcessor;
-use opentelemetry_sdk::trace::{SdkTracerProvider, TracerProviderBuilder};
+use opentelemetry_sdk::trace::{Sampler, SdkTracerProvider, TracerProviderBuilder};
 use opentelemetry_sdk::{runtime, Resource};
+#[cfg(feature = "otlp-conversion")]
+pub use otlp_conversion::*;
+#[cfg(feature = "otlp-backend")]
+pub use otlp_exporter_client::GCP_TELEMETRY_API_URL;
+#[cfg(feature = "otlp-receiver")]
+pub use otlp_receiver::*;
+pub use propagator::*;
+#[cfg(feature = "json")]
+pub use proto_json::GcpCloudTraceJson;
+pub use pubsub::*;
 use rsb_derive::*;
+pub use sampler::*;
+pub use span_converter::*;
 pub use span_exporter::GcpCloudTraceExporter;
+pub use span_filter::*;
+pub use span_redactor::*;
+pub use span_renamer::*;
+pub use tail_sampling::*;
+#[cfg(feature = "tower-layer")]
+pub use tower_layer::{GcpCloudTraceLayer, GcpCloudTraceService};
+#[cfg(feature = "trace-reader")]
+pub use trace_reader::*;
+pub use transport::CloudTraceTransport;
+#[cfg(feature = "zipkin-receiver")]
+pub use zipkin_conversion::ZipkinTraceConverter;
 
 pub type SdkTracer = opentelemetry_sdk::trace::Tracer;
 
+/// Protocol used to send spans to the Cloud Trace API.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum GcpCloudTraceExporterTransport {
+    /// gRPC over HTTP/2 (default).
+    #[default]
+    Grpc,
+    /// REST/JSON over HTTP/1.1, for environments where HTTP/2 gRPC is not available
+    /// (e.g. behind some corporate proxies).
+    #[cfg(feature = "rest-transport")]
+    Rest,
+}
+
+/// Google API used to ingest spans.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum GcpCloudTraceExporterBackend {
+    /// Cloud Trace v2 API (default).
+    #[default]
+    CloudTraceV2,
+    /// Native OTLP ingestion using the Google Telemetry API (`telemetry.googleapis.com`).
+    /// Keeps OpenTelemetry attribute semantics and isn't subject to the Cloud Trace v2 API limits.
+    #[cfg(feature = "otlp-backend")]
+    Otlp,
+}
+
 #[derive(Debug, Builder)]
 pub struct GcpCloudTraceExporterBuilder {
     pub google_project_id: String,
     pub resource: Option<Resource>,
+    pub transport: Option<GcpCloudTraceExporterTransport>,
+    /// Cloud Trace API URL override (e.g. for a local emulator or a fake server).
+    pub cloud_trace_api_url: Option<String>,
+    pub backend: Option<GcpCloudTraceExporterBackend>,
+    /// Telemetry API URL override for the OTLP backend.
+    pub telemetry_api_url: Option<String>,
+    /// Project used for quota and billing with the OTLP backend (defaults to `google_project_id`).
+    pub quota_project_id: Option<String>,
+    pub span_converter_options: Option<SpanConverterOptions>,
+    pub credentials: Option<GcpCloudTraceCredentials>,
+    /// Limits sampled traces using the rate limiting sampler for root spans,
+    /// replacing the sampler of the provider builder.
+    pub rate_limiting_sampler: Option<RateLimitingSampler>,
+    /// Exports only whole traces matching the tail sampling policies.
+    pub tail_sampling: Option<TailSamplingConfig>,
+    /// Drops matching spans before they are converted and exported.
+    pub span_filter: Option<SpanFilter>,
+    /// Scrubs sensitive data from spans before they are converted and exported.
+    pub span_redactor: Option<SpanRedactor>,
+    /// Renames spans before they are converted and exported.
+    pub span_renamer: Option<SpanRenamer>,
+    /// Copies allow-listed baggage entries to span attributes, using the Cloud Trace v2 exporter
+    /// attribute limits unless the processor has its own.
+    pub baggage_span_processor: Option<BaggageSpanProcessor>,
 }
 
 impl GcpCloudTraceExporterBuilder {
@@ -97,21 +390,180 @@ impl GcpCloudTraceExporterBuilder {
         &self,
         builder: TracerProviderBuilder,
     ) -> Result<SdkTracerProvider, GcloudTraceError> {
-        let exporter = GcpCloudTraceExporter::new(
-            &self.google_project_id,
-            self.resource
-                .clone()
-                .unwrap_or_else(|| Resource::builder_empty().build()),
-        )
-        .await?;
+        let resource = self
+            .resource
+            .clone()
+            .unwrap_or_else(|| Resource::builder_empty().build());
 
-        let tracer_provider = builder
-            .with_span_processor(BatchSpanProcessor::builder(exporter, runtime::Tokio).build())
-            .build();
+        let backend = self.backend.clone().unwrap_or_default();
+        self.check_backend_options(&backend)?;
+
+        let exporter = match backend {
+            GcpCloudTraceExporterBackend::CloudTraceV2 => {
+                GcpCloudTraceExporter::with_transport_options(
+                    SpanConverter::new(self.google_project_id.clone())
+                        .with_resource(resource)
+                        .with_options(
+                            self.span_converter_options
+                                .clone()
+                                .unwrap_or_else(SpanConverterOptions::new),
+                        ),
+                    &self.transport.clone().unwrap_or_default(),
+                    self.cloud_trace_api_url
+                        .as_deref()
+                        .unwrap_or(GCP_CLOUD_TRACE_API_URL),
+                    &self.credentials.clone().unwrap_or_default(),
+                )
+                .await?
+            }
+            #[cfg(feature = "otlp-backend")]
+            GcpCloudTraceExporterBackend::Otlp => {
+                GcpCloudTraceExporter::new_otlp(
+                    &self.google_project_id,
+                    resource,
+                    self.telemetry_api_url
+                        .as_deref()
+                        .unwrap_or(GCP_TELEMETRY_API_URL),
+                    self.quota_project_id.as_deref(),
+                    &self.credentials.clone().unwrap_or_default(),
+                )
+                .await?
+            }
+        };
+
+        let builder = match &self.rate_limiting_sampler {
+            Some(sampler) => builder.with_sampler(Sampler::ParentBased(Box::new(sampler.clone()))),
+            None => builder,
+        };
+
+        let exporter = match &self.span_filter {
+            Some(span_filter) => exporter.with_span_filter(span_filter.clone()),
+            None => exporter,
+        };
+        let exporter = match &self.span_renamer {
+            Some(span_renamer) => exporter.with_span_renamer(span_renamer.clone()),
+            None => exporter,
+        };
+        let exporter = match &self.span_redactor {
+            Some(span_redactor) => exporter.with_span_redactor(span_redactor.clone()),
+            None => exporter,
+        };
+
+        // Marks the descendants of dropped subtrees when they start
+        let builder = match &self.span_filter {
+            Some(span_filter) if span_filter.has_drop_subtree_rules() => {
+                builder.with_span_processor(span_filter.clone())
+            }
+            _ => builder,
+        };
+
+        // The OTLP backend doesn't have the Cloud Trace v2 attribute limits
+        let builder = match (&self.baggage_span_processor, &backend) {
+            (Some(baggage_span_processor), GcpCloudTraceExporterBackend::CloudTraceV2) => builder
+                .with_span_processor(
+                    baggage_span_processor.clone().with_default_limits(
+                        self.span_converter_options
+                            .as_ref()
+                            .map(|options| options.limits.clone())
+                            .unwrap_or_else(SpanConverterLimits::new),
+                    ),
+                ),
+            #[cfg(feature = "otlp-backend")]
+            (Some(baggage_span_processor), GcpCloudTraceExporterBackend::Otlp) => {
+                builder.with_span_processor(baggage_span_processor.clone())
+            }
+            (None, _) => builder,
+        };
+
+        let batch_processor = BatchSpanProcessor::builder(exporter, runtime::Tokio).build();
+        let builder = match &self.tail_sampling {
+            Some(config) => builder.with_span_processor(TailSamplingSpanProcessor::new(
+                batch_processor,
+                config.clone(),
+            )),
+            None => builder.with_span_processor(batch_processor),
+        };
+
+        let tracer_provider = builder.build();
 
         Ok(tracer_provider)
     }
 
+    // Options of the other backend would be silently ignored
+    fn check_backend_options(
+        &self,
+        backend: &GcpCloudTraceExporterBackend,
+    ) -> TraceExportResult<()> {
+        let unsupported_options: Vec<&str> = match backend {
+            GcpCloudTraceExporterBackend::CloudTraceV2 => [
+                ("telemetry_api_url", self.telemetry_api_url.is_some()),
+                ("quota_project_id", self.quota_project_id.is_some()),
+            ]
+            .into_iter()
+            .filter_map(|(option, is_set)| is_set.then_some(option))
+            .collect(),
+            #[cfg(feature = "otlp-backend")]
+            GcpCloudTraceExporterBackend::Otlp => [
+                ("transport", self.transport.is_some()),
+                ("cloud_trace_api_url", self.cloud_trace_api_url.is_some()),
+                (
+                    "span_converter_options",
+                    self.span_converter_options.is_some(),
+                ),
+            ]
+            .into_iter()
+            .filter_map(|(option, is_set)| is_set.then_some(option))
+            .collect(),
+        };
+        if unsupported_options.is_empty() {
+            Ok(())
+        } else {
+            Err(GcloudTraceError::SystemError(
+                crate::errors::GcloudTraceSystemError::new(format!(
+                    "Options not supported by the {backend:?} backend: {}",
+                    unsupported_options.join(", ")
+                )),
+            ))
+        }
+    }
+
+    /// Creates a Cloud Trace reader using the same project, API URL and credentials as the exporter.
+    #[cfg(feature = "trace-reader")]
+    pub async fn create_trace_reader(&self) -> TraceExportResult<GcpCloudTraceReader> {
+        GcpCloudTraceReader::with_options(
+            &self.google_project_id,
+            self.cloud_trace_api_url
+                .as_deref()
+                .unwrap_or(GCP_CLOUD_TRACE_API_URL),
+            &self.credentials.clone().unwrap_or_default(),
+        )
+        .await
+    }
+
+    /// Creates an OTLP receiver forwarding spans to Cloud Trace using the same project, transport,
+    /// API URL, converter options and credentials as the exporter.
+    /// Resources are taken from received spans, so the builder resource isn't used.
+    #[cfg(feature = "otlp-receiver")]
+    pub async fn create_otlp_receiver(
+        &self,
+        config: GcpOtlpReceiverConfig,
+    ) -> TraceExportResult<GcpOtlpReceiver> {
+        GcpOtlpReceiver::with_transport_options(
+            SpanConverter::new(self.google_project_id.clone()).with_options(
+                self.span_converter_options
+                    .clone()
+                    .unwrap_or_else(SpanConverterOptions::new),
+            ),
+            &self.transport.clone().unwrap_or_default(),
+            self.cloud_trace_api_url
+                .as_deref()
+                .unwrap_or(GCP_CLOUD_TRACE_API_URL),
+            &self.credentials.clone().unwrap_or_default(),
+            config,
+        )
+        .await
+    }
+
     pub async fn install(
         self,
         provider: &SdkTracerProvider,
@@ -125,3 +577,108 @@ impl GcpCloudTraceExporterBuilder {
         Ok(tracer)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unsupported_options_error(builder: &GcpCloudTraceExporterBuilder) -> String {
+        let backend = builder.backend.clone().unwrap_or_default();
+        match builder.check_backend_options(&backend) {
+            Err(GcloudTraceError::SystemError(err)) => err.message,
+            other => panic!("unexpected result: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn rejects_otlp_options_for_cloud_trace() {
+        let builder = GcpCloudTraceExporterBuilder::new("test-project".to_string())
+            .with_telemetry_api_url("http://localhost:4317".to_string())
+            .with_quota_project_id("quota-project".to_string());
+        assert_eq!(
+            unsupported_options_error(&builder),
+            "Options not supported by the CloudTraceV2 backend: telemetry_api_url, quota_project_id"
+        );
+    }
+
+    #[cfg(feature = "otlp-backend")]
+    #[test]
+    fn rejects_cloud_trace_options_for_otlp() {
+        let builder = GcpCloudTraceExporterBuilder::new("test-project".to_string())
+            .with_backend(GcpCloudTraceExporterBackend::Otlp)
+            .with_transport(GcpCloudTraceExporterTransport::Grpc)
+            .with_cloud_trace_api_url("http://localhost:8080".to_string())
+            .with_span_converter_options(SpanConverterOptions::new());
+        assert_eq!(
+            unsupported_options_error(&builder),
+            "Options not supported by the Otlp backend: transport, cloud_trace_api_url, span_converter_options"
+        );
+
+        let builder = GcpCloudTraceExporterBuilder::new("test-project".to_string())
+            .with_backend(GcpCloudTraceExporterBackend::Otlp)
+            .with_telemetry_api_url("http://localhost:4317".to_string())
+            .with_quota_project_id("quota-project".to_string());
+        assert!(builder
+            .check_backend_options(&GcpCloudTraceExporterBackend::Otlp)
+            .is_ok());
+    }
+    #[tokio::test(flavor = "multi_thread")]
+    async fn installs_rate_limiting_sampler_for_root_spans() {
+        use opentelemetry::trace::{
+            Span, SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState, Tracer,
+        };
+        use opentelemetry::Context;
+
+        // Local stand-in for the Cloud Trace API accepting all spans
+        let app = axum::Router::new().fallback(|| async {
+            let trailers = axum::http::HeaderMap::from_iter([(
+                axum::http::HeaderName::from_static("grpc-status"),
+                axum::http::HeaderValue::from_static("0"),
+            )]);
+            let body = http_body_util::StreamBody::new(futures::stream::iter([
+                Ok::<_, std::convert::Infallible>(http_body::Frame::data(bytes::Bytes::from(
+                    vec![0u8; 5],
+                ))),
+                Ok(http_body::Frame::trailers(trailers)),
+            ]));
+            axum::response::Response::builder()
+                .header(axum::http::header::CONTENT_TYPE, "application/grpc")
+                .body(axum::body::Body::new(body))
+                .unwrap()
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let provider = GcpCloudTraceExporterBuilder::new("test-project".to_string())
+            .with_cloud_trace_api_url(format!("http://{}", listener.local_addr().unwrap()))
+            .with_credentials(crate::credentials::StaticTokenSource::credentials())
+            .with_rate_limiting_sampler(RateLimitingSampler::new(1.0))
+            .create_provider()
+            .await
+            .unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let tracer = provider.tracer("test");
+
+        let first = tracer.start("first");
+        assert!(first.span_context().is_sampled());
+        assert!(!tracer.start("second").span_context().is_sampled());
+
+        // Children follow their parents instead of using the budget
+        let cx = Context::current_with_span(first);
+        assert!(tracer
+            .start_with_context("child", &cx)
+            .span_context()
+            .is_sampled());
+        let remote = Context::current().with_remote_span_context(SpanContext::new(
+            TraceId::from(1),
+            SpanId::from(1),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        ));
+        assert!(tracer
+            .start_with_context("remote child", &remote)
+            .span_context()
+            .is_sampled());
+
+        provider.shutdown().unwrap();
+    }
+}
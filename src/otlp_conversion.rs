@@ -0,0 +1,304 @@
+use opentelemetry::trace::{
+    Event, Link, SpanContext, SpanId, SpanKind, Status, TraceFlags, TraceId, TraceState,
+};
+use opentelemetry::{InstrumentationScope, KeyValue, StringValue};
+use opentelemetry_proto::tonic::collector::trace::v1::ExportTraceServiceRequest;
+use opentelemetry_proto::tonic::common::v1::{
+    any_value, AnyValue, InstrumentationScope as OtlpInstrumentationScope, KeyValue as OtlpKeyValue,
+};
+use opentelemetry_proto::tonic::trace::v1::{span, status, ResourceSpans, Span as OtlpSpan};
+use opentelemetry_sdk::trace::{SpanData, SpanEvents, SpanLinks};
+use opentelemetry_sdk::Resource;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+/// Spans decoded from OTLP, grouped by their resource.
+#[derive(Debug, Clone)]
+pub struct OtlpResourceSpanData {
+    pub resource: Resource,
+    pub spans: Vec<SpanData>,
+}
+
+/// Converts OTLP trace payloads (e.g. received by a collector or read from OTLP JSON files)
+/// into `SpanData`, so they can be converted and exported by this crate.
+pub struct OtlpTraceConverter;
+
+impl OtlpTraceConverter {
+    // OTLP span flags bits describing whether the parent span is remote
+    const SPAN_FLAGS_CONTEXT_HAS_IS_REMOTE_MASK: u32 = 0x100;
+    const SPAN_FLAGS_CONTEXT_IS_REMOTE_MASK: u32 = 0x200;
+
+    pub fn convert_request(request: ExportTraceServiceRequest) -> Vec<OtlpResourceSpanData> {
+        request
+            .resource_spans
+            .into_iter()
+            .map(Self::convert_resource_spans)
+            .collect()
+    }
+
+    pub fn convert_resource_spans(resource_spans: ResourceSpans) -> OtlpResourceSpanData {
+        let resource_attributes = resource_spans
+            .resource
+            .map(|resource| Self::convert_attrs(resource.attributes))
+            .unwrap_or_default();
+
+        let resource = if resource_spans.schema_url.is_empty() {
+            Resource::builder_empty()
+                .with_attributes(resource_attributes)
+                .build()
+        } else {
+            Resource::builder_empty()
+                .with_schema_url(resource_attributes, resource_spans.schema_url)
+                .build()
+        };
+
+        let spans = resource_spans
+            .scope_spans
+            .into_iter()
+            .flat_map(|scope_spans| {
+                let scope = Self::convert_scope(scope_spans.scope, scope_spans.schema_url);
+                scope_spans
+                    .spans
+                    .into_iter()
+                    .map(move |span| Self::convert_span(span, scope.clone()))
+            })
+            .collect();
+
+        OtlpResourceSpanData { resource, spans }
+    }
+
+    pub fn convert_span(span: OtlpSpan, instrumentation_scope: InstrumentationScope) -> SpanData {
+        let trace_id = Self::convert_trace_id(&span.trace_id);
+        let span_kind = Self::convert_span_kind(span.kind());
+
+        let mut events = SpanEvents::default();
+        events.events = span
+            .events
+            .into_iter()
+            .map(|event| {
+                Event::new(
+                    event.name,
+                    Self::convert_time(event.time_unix_nano),
+                    Self::convert_attrs(event.attributes),
+                    event.dropped_attributes_count,
+                )
+            })
+            .collect();
+        events.dropped_count = span.dropped_events_count;
+
+        let mut links = SpanLinks::default();
+        links.links = span
+            .links
+            .into_iter()
+            .map(|link| {
+                Link::new(
+                    Self::convert_span_context(
+                        Self::convert_trace_id(&link.trace_id),
+                        Self::convert_span_id(&link.span_id),
+                        link.flags,
+                        &link.trace_state,
+                    ),
+                    Self::convert_attrs(link.attributes),
+                    link.dropped_attributes_count,
+                )
+            })
+            .collect();
+        links.dropped_count = span.dropped_links_count;
+
+        let parent_span_id = Self::convert_span_id(&span.parent_span_id);
+        let has_is_remote = span.flags & Self::SPAN_FLAGS_CONTEXT_HAS_IS_REMOTE_MASK != 0;
+        let mut attributes = Self::convert_attrs(span.attributes);
+        if !has_is_remote && parent_span_id != SpanId::INVALID {
+            attributes.push(KeyValue::new(
+                crate::SpanConverter::PARENT_SPAN_IS_REMOTE_ATTRIBUTE,
+                "unknown",
+            ));
+        }
+
+        SpanData {
+            span_context: Self::convert_span_context(
+                trace_id,
+                Self::convert_span_id(&span.span_id),
+                span.flags,
+                &span.trace_state,
+            ),
+            parent_span_id,
+            parent_span_is_remote: has_is_remote
+                && span.flags & Self::SPAN_FLAGS_CONTEXT_IS_REMOTE_MASK != 0,
+            span_kind,
+            name: span.name.into(),
+            start_time: Self::convert_time(span.start_time_unix_nano),
+            end_time: Self::convert_time(span.end_time_unix_nano),
+            attributes,
+            dropped_attributes_count: span.dropped_attributes_count,
+            events,
+            links,
+            status: match span.status {
+                Some(status) if status.code == status::StatusCode::Error as i32 => {
+                    Status::error(status.message)
+                }
+                Some(status) if status.code == status::StatusCode::Ok as i32 => Status::Ok,
+                _ => Status::Unset,
+            },
+            instrumentation_scope,
+        }
+    }
+
+    fn convert_scope(
+        scope: Option<OtlpInstrumentationScope>,
+        schema_url: String,
+    ) -> InstrumentationScope {
+        let scope = scope.unwrap_or_default();
+        let mut builder = InstrumentationScope::builder(scope.name)
+            .with_attributes(Self::convert_attrs(scope.attributes));
+        if !scope.version.is_empty() {
+            builder = builder.with_version(scope.version);
+        }
+        if !schema_url.is_empty() {
+            builder = builder.with_schema_url(schema_url);
+        }
+        builder.build()
+    }
+
+    fn convert_span_context(
+        trace_id: TraceId,
+        span_id: SpanId,
+        flags: u32,
+        trace_state: &str,
+    ) -> SpanContext {
+        SpanContext::new(
+            trace_id,
+            span_id,
+            // The lower 8 bits of OTLP span flags are W3C trace flags
+            TraceFlags::new((flags & 0xff) as u8),
+            flags & Self::SPAN_FLAGS_CONTEXT_IS_REMOTE_MASK != 0,
+            TraceState::from_str(trace_state).unwrap_or_default(),
+        )
+    }
+
+    fn convert_trace_id(bytes: &[u8]) -> TraceId {
+        <[u8; 16]>::try_from(bytes)
+            .map(TraceId::from_bytes)
+            .unwrap_or(TraceId::INVALID)
+    }
+
+    fn convert_span_id(bytes: &[u8]) -> SpanId {
+        <[u8; 8]>::try_from(bytes)
+            .map(SpanId::from_bytes)
+            .unwrap_or(SpanId::INVALID)
+    }
+
+    fn convert_time(unix_nanos: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_nanos(unix_nanos)
+    }
+
+    fn convert_span_kind(span_kind: span::SpanKind) -> SpanKind {
+        match span_kind {
+            span::SpanKind::Server => SpanKind::Server,
+            span::SpanKind::Client => SpanKind::Client,
+            span::SpanKind::Producer => SpanKind::Producer,
+            span::SpanKind::Consumer => SpanKind::Consumer,
+            span::SpanKind::Internal | span::SpanKind::Unspecified => SpanKind::Internal,
+        }
+    }
+
+    fn convert_attrs(attrs: Vec<OtlpKeyValue>) -> Vec<KeyValue> {
+        attrs
+            .into_iter()
+            .filter_map(|kv| {
+                kv.value
+                    .and_then(Self::convert_any_value)
+                    .map(|value| KeyValue::new(kv.key, value))
+            })
+            .collect()
+    }
+
+    fn convert_any_value(value: AnyValue) -> Option<opentelemetry::Value> {
+        Some(match value.value? {
+            any_value::Value::StringValue(value) => opentelemetry::Value::String(value.into()),
+            any_value::Value::BoolValue(value) => opentelemetry::Value::Bool(value),
+            any_value::Value::IntValue(value) => opentelemetry::Value::I64(value),
+            any_value::Value::DoubleValue(value) => opentelemetry::Value::F64(value),
+            any_value::Value::ArrayValue(arr) => Self::convert_array_value(arr.values),
+            // Nested values aren't supported by OpenTelemetry span attributes
+            any_value::Value::KvlistValue(kv_list) => opentelemetry::Value::String(
+                format!(
+                    "{{{}}}",
+                    Self::convert_attrs(kv_list.values)
+                        .iter()
+                        .map(|kv| format!("{}: {}", kv.key, kv.value))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+                .into(),
+            ),
+            any_value::Value::BytesValue(bytes) => opentelemetry::Value::String(
+                bytes
+                    .iter()
+                    .map(|b| format!("{b:02x}"))
+                    .collect::<String>()
+                    .into(),
+            ),
+        })
+    }
+
+    fn convert_array_value(values: Vec<AnyValue>) -> opentelemetry::Value {
+        let values: Vec<opentelemetry::Value> = values
+            .into_iter()
+            .filter_map(Self::convert_any_value)
+            .collect();
+
+        if values
+            .iter()
+            .all(|v| matches!(v, opentelemetry::Value::Bool(_)))
+        {
+            opentelemetry::Value::Array(
+                values
+                    .into_iter()
+                    .filter_map(|v| match v {
+                        opentelemetry::Value::Bool(v) => Some(v),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+                    .into(),
+            )
+        } else if values
+            .iter()
+            .all(|v| matches!(v, opentelemetry::Value::I64(_)))
+        {
+            opentelemetry::Value::Array(
+                values
+                    .into_iter()
+                    .filter_map(|v| match v {
+                        opentelemetry::Value::I64(v) => Some(v),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+                    .into(),
+            )
+        } else if values
+            .iter()
+            .all(|v| matches!(v, opentelemetry::Value::F64(_)))
+        {
+            opentelemetry::Value::Array(
+                values
+                    .into_iter()
+                    .filter_map(|v| match v {
+                        opentelemetry::Value::F64(v) => Some(v),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+                    .into(),
+            )
+        } else {
+            // Mixed arrays aren't supported by OpenTelemetry, so converting everything to strings
+            opentelemetry::Value::Array(
+                values
+                    .into_iter()
+                    .map(|v| StringValue::from(v.to_string()))
+                    .collect::<Vec<_>>()
+                    .into(),
+            )
+        }
+    }
+}
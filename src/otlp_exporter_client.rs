@@ -0,0 +1,288 @@
+use crate::{GcpCloudTraceCredentials, TraceExportResult};
+use gcloud_sdk::*;
+use opentelemetry::KeyValue;
+use opentelemetry_proto::tonic::collector::trace::v1::{
+    trace_service_client::TraceServiceClient, ExportTraceServiceRequest,
+};
+use opentelemetry_proto::transform::common::tonic::ResourceAttributesWithSchema;
+use opentelemetry_proto::transform::trace::tonic::group_spans_by_resource_and_scope;
+use opentelemetry_sdk::{trace::SpanData, Resource};
+
+pub const GCP_TELEMETRY_API_URL: &str = "https://telemetry.googleapis.com";
+
+const GCP_PROJECT_ID_RESOURCE_KEY: &str = "gcp.project_id";
+
+/// Exports spans using OTLP/gRPC to the Google Telemetry API (`telemetry.googleapis.com`).
+pub struct GcpOtlpExporterClient {
+    client: GoogleApi<TraceServiceClient<GoogleAuthMiddleware>>,
+    resource: ResourceAttributesWithSchema,
+}
+
+impl GcpOtlpExporterClient {
+    pub async fn new(
+        google_project_id: &str,
+        resource: Resource,
+        api_url: &str,
+        quota_project_id: Option<&str>,
+        credentials: &GcpCloudTraceCredentials,
+    ) -> TraceExportResult<Self> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-goog-user-project",
+            quota_project_id
+                .unwrap_or(google_project_id)
+                .parse()
+                .map_err(|e| {
+                    crate::errors::GcloudTraceError::SystemError(
+                        crate::errors::GcloudTraceSystemError::new(format!(
+                            "Invalid quota project id: {e}"
+                        )),
+                    )
+                })?,
+        );
+
+        let client: GoogleApi<TraceServiceClient<GoogleAuthMiddleware>> =
+            crate::credentials::create_google_api(
+                TraceServiceClient::new,
+                api_url,
+                credentials,
+                headers,
+            )
+            .await?;
+
+        // The Telemetry API requires the project to be specified as a resource attribute
+        let resource = if resource
+            .get(&opentelemetry::Key::from_static_str(
+                GCP_PROJECT_ID_RESOURCE_KEY,
+            ))
+            .is_some()
+        {
+            resource
+        } else {
+            Resource::builder_empty()
+                .with_attributes(
+                    resource
+                        .iter()
+                        .map(|(k, v)| KeyValue::new(k.clone(), v.clone()))
+                        .chain(std::iter::once(KeyValue::new(
+                            GCP_PROJECT_ID_RESOURCE_KEY,
+                            google_project_id.to_string(),
+                        ))),
+                )
+                .build()
+        };
+
+        Ok(Self {
+            client,
+            resource: (&resource).into(),
+        })
+    }
+
+    pub async fn export_batch(&self, batch: Vec<SpanData>) -> TraceExportResult<()> {
+        let request = ExportTraceServiceRequest {
+            resource_spans: group_spans_by_resource_and_scope(batch, &self.resource),
+        };
+
+        let response = self
+            .client
+            .get()
+            .export(tonic::Request::new(request))
+            .await?
+            .into_inner();
+
+        // The accepted spans are not retried, but rejected spans are reported as an export error
+        match response.partial_success {
+            Some(partial_success) if partial_success.rejected_spans > 0 => {
+                Err(crate::errors::GcloudTraceError::SystemError(
+                    crate::errors::GcloudTraceSystemError::new(format!(
+                        "Google Telemetry API rejected {} spans: {}",
+                        partial_success.rejected_spans, partial_success.error_message
+                    )),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::{
+        SpanContext, SpanId, SpanKind, Status, TraceFlags, TraceId, TraceState,
+    };
+    use opentelemetry::InstrumentationScope;
+    use opentelemetry_proto::tonic::collector::trace::v1::trace_service_server::{
+        TraceService, TraceServiceServer,
+    };
+    use opentelemetry_proto::tonic::collector::trace::v1::{
+        ExportTracePartialSuccess, ExportTraceServiceResponse,
+    };
+    use opentelemetry_proto::tonic::common::v1::any_value;
+    use opentelemetry_sdk::trace::{SpanEvents, SpanLinks};
+    use std::sync::{Arc, Mutex};
+    use std::time::SystemTime;
+
+    type ReceivedRequests =
+        Arc<Mutex<Vec<(tonic::metadata::MetadataMap, ExportTraceServiceRequest)>>>;
+
+    // Local stand-in for the Telemetry API OTLP endpoint
+    struct TestTraceService {
+        requests: ReceivedRequests,
+        partial_success: Option<ExportTracePartialSuccess>,
+    }
+
+    #[tonic::async_trait]
+    impl TraceService for TestTraceService {
+        async fn export(
+            &self,
+            request: tonic::Request<ExportTraceServiceRequest>,
+        ) -> Result<tonic::Response<ExportTraceServiceResponse>, tonic::Status> {
+            let metadata = request.metadata().clone();
+            self.requests
+                .lock()
+                .unwrap()
+                .push((metadata, request.into_inner()));
+            Ok(tonic::Response::new(ExportTraceServiceResponse {
+                partial_success: self.partial_success.clone(),
+            }))
+        }
+    }
+
+    async fn start_server(
+        partial_success: Option<ExportTracePartialSuccess>,
+    ) -> (String, ReceivedRequests) {
+        let requests = ReceivedRequests::default();
+        let service = TestTraceService {
+            requests: requests.clone(),
+            partial_success,
+        };
+        let incoming =
+            tonic::transport::server::TcpIncoming::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = incoming.local_addr().unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(TraceServiceServer::new(service))
+                .serve_with_incoming(incoming),
+        );
+        (format!("http://{addr}"), requests)
+    }
+
+    async fn test_client(api_url: &str, quota_project_id: Option<&str>) -> GcpOtlpExporterClient {
+        GcpOtlpExporterClient::new(
+            "test-project",
+            Resource::builder_empty()
+                .with_attributes([KeyValue::new("service.name", "test-service")])
+                .build(),
+            api_url,
+            quota_project_id,
+            &crate::credentials::StaticTokenSource::credentials(),
+        )
+        .await
+        .unwrap()
+    }
+
+    fn test_span() -> SpanData {
+        SpanData {
+            span_context: SpanContext::new(
+                TraceId::from(1),
+                SpanId::from(2),
+                TraceFlags::SAMPLED,
+                false,
+                TraceState::default(),
+            ),
+            parent_span_id: SpanId::INVALID,
+            parent_span_is_remote: false,
+            span_kind: SpanKind::Server,
+            name: "test".into(),
+            start_time: SystemTime::now(),
+            end_time: SystemTime::now(),
+            attributes: Vec::new(),
+            dropped_attributes_count: 0,
+            events: SpanEvents::default(),
+            links: SpanLinks::default(),
+            status: Status::Unset,
+            instrumentation_scope: InstrumentationScope::builder("test").build(),
+        }
+    }
+
+    fn resource_attribute(request: &ExportTraceServiceRequest, key: &str) -> Option<String> {
+        request.resource_spans[0]
+            .resource
+            .as_ref()?
+            .attributes
+            .iter()
+            .find(|kv| kv.key == key)
+            .and_then(|kv| match kv.value.as_ref()?.value.as_ref()? {
+                any_value::Value::StringValue(value) => Some(value.clone()),
+                _ => None,
+            })
+    }
+
+    #[tokio::test]
+    async fn exports_spans_with_project_headers_and_resource() {
+        let (api_url, requests) = start_server(None).await;
+
+        test_client(&api_url, Some("quota-project"))
+            .await
+            .export_batch(vec![test_span()])
+            .await
+            .unwrap();
+        test_client(&api_url, None)
+            .await
+            .export_batch(vec![test_span()])
+            .await
+            .unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        let (metadata, request) = &requests[0];
+        assert_eq!(
+            metadata.get("x-goog-user-project").unwrap(),
+            "quota-project"
+        );
+        assert_eq!(metadata.get("authorization").unwrap(), "Bearer test-token");
+        assert_eq!(
+            resource_attribute(request, "gcp.project_id").as_deref(),
+            Some("test-project")
+        );
+        assert_eq!(
+            resource_attribute(request, "service.name").as_deref(),
+            Some("test-service")
+        );
+        assert_eq!(request.resource_spans[0].scope_spans[0].spans.len(), 1);
+
+        // The quota project defaults to the exported project
+        let (metadata, _) = &requests[1];
+        assert_eq!(metadata.get("x-goog-user-project").unwrap(), "test-project");
+    }
+
+    #[tokio::test]
+    async fn reports_rejected_spans() {
+        let (api_url, _) = start_server(Some(ExportTracePartialSuccess {
+            rejected_spans: 1,
+            error_message: "invalid span".to_string(),
+        }))
+        .await;
+
+        let result = test_client(&api_url, None)
+            .await
+            .export_batch(vec![test_span()])
+            .await;
+        match result {
+            Err(crate::errors::GcloudTraceError::SystemError(err)) => assert_eq!(
+                err.message,
+                "Google Telemetry API rejected 1 spans: invalid span"
+            ),
+            other => panic!("unexpected result: {other:?}"),
+        }
+
+        // Partial success responses without rejected spans are successful
+        let (api_url, _) = start_server(Some(ExportTracePartialSuccess::default())).await;
+        assert!(test_client(&api_url, None)
+            .await
+            .export_batch(vec![test_span()])
+            .await
+            .is_ok());
+    }
+}
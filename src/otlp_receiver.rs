@@ -0,0 +1,728 @@
+use crate::errors::{GcloudTraceError, GcloudTraceSystemError};
+use crate::google_trace_exporter_client::GcpCloudTraceExporterClient;
+use crate::transport::CloudTraceTransport;
+use crate::{
+    GcpCloudTraceCredentials, GcpCloudTraceExporterTransport, OtlpResourceSpanData,
+    OtlpTraceConverter, SpanConverter, TraceExportResult,
+};
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::Router;
+use gcloud_sdk::prost::Message;
+use gcloud_sdk::tonic;
+use opentelemetry::context::FutureExt as OtelContextFutureExt;
+use opentelemetry::Context;
+use opentelemetry_proto::tonic::collector::trace::v1::trace_service_server::{
+    TraceService, TraceServiceServer,
+};
+use opentelemetry_proto::tonic::collector::trace::v1::{
+    ExportTraceServiceRequest, ExportTraceServiceResponse,
+};
+use rsb_derive::*;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+
+/// Batching and retry options for [`GcpOtlpReceiver`].
+#[derive(Debug, Clone, Builder)]
+pub struct GcpOtlpReceiverConfig {
+    /// Maximum number of spans sent in a single `BatchWriteSpans` call (at least 1).
+    #[default = "512"]
+    pub max_export_batch_size: usize,
+    /// Maximum time received spans are buffered before being sent.
+    #[default = "Duration::from_secs(5)"]
+    pub scheduled_delay: Duration,
+    /// Maximum number of received requests waiting to be exported.
+    /// Clients get a retryable error when the queue is full.
+    #[default = "2048"]
+    pub max_queue_size: usize,
+    /// Number of retries for retryable errors (`UNAVAILABLE`, `DEADLINE_EXCEEDED`
+    /// and `RESOURCE_EXHAUSTED`). Retries use exponential backoff.
+    #[default = "3"]
+    pub max_export_retries: usize,
+    #[default = "Duration::from_millis(500)"]
+    pub initial_retry_delay: Duration,
+}
+
+// Limit for decompressed OTLP/HTTP request bodies
+const MAX_DECOMPRESSED_BODY_SIZE: u64 = 32 * 1024 * 1024;
+
+enum ReceiverMessage {
+    Export(Vec<OtlpResourceSpanData>),
+    Shutdown(oneshot::Sender<()>),
+}
+
+/// OTLP trace receiver forwarding spans to Cloud Trace.
+///
+/// Accepts OTLP/gRPC (`TraceService/Export`) and OTLP/HTTP (`POST /v1/traces` with protobuf or JSON,
+/// optionally gzip compressed) requests. Received spans are buffered and sent in batches by a background task,
+/// so it can be used as a lightweight replacement for a collector sidecar.
+#[derive(Clone)]
+pub struct GcpOtlpReceiver {
+    sender: mpsc::Sender<ReceiverMessage>,
+}
+
+impl GcpOtlpReceiver {
+    pub async fn new(
+        google_project_id: &str,
+        config: GcpOtlpReceiverConfig,
+    ) -> TraceExportResult<Self> {
+        Self::with_transport_options(
+            SpanConverter::new(google_project_id.to_string()),
+            &GcpCloudTraceExporterTransport::default(),
+            crate::GCP_CLOUD_TRACE_API_URL,
+            &GcpCloudTraceCredentials::default(),
+            config,
+        )
+        .await
+    }
+
+    /// Creates a receiver. Resources are taken from received requests,
+    /// so the converter resource isn't used.
+    pub async fn with_transport_options(
+        converter: SpanConverter,
+        transport: &GcpCloudTraceExporterTransport,
+        api_url: &str,
+        credentials: &GcpCloudTraceCredentials,
+        config: GcpOtlpReceiverConfig,
+    ) -> TraceExportResult<Self> {
+        Ok(Self::start(
+            GcpCloudTraceExporterClient::with_transport_options(
+                converter,
+                transport,
+                api_url,
+                credentials,
+            )
+            .await?,
+            config,
+        ))
+    }
+
+    /// Creates a receiver sending spans using the provided transport
+    /// (e.g. a recording transport to test the receiver locally).
+    pub fn with_transport<T>(
+        converter: SpanConverter,
+        transport: T,
+        config: GcpOtlpReceiverConfig,
+    ) -> Self
+    where
+        T: CloudTraceTransport + 'static,
+    {
+        Self::start(
+            GcpCloudTraceExporterClient::with_transport(converter, Arc::new(transport)),
+            config,
+        )
+    }
+
+    fn start(client: GcpCloudTraceExporterClient, config: GcpOtlpReceiverConfig) -> Self {
+        let config = GcpOtlpReceiverConfig {
+            max_export_batch_size: config.max_export_batch_size.max(1),
+            ..config
+        };
+        let (sender, receiver) = mpsc::channel(config.max_queue_size.max(1));
+        tokio::spawn(
+            // Same as for the exporter, the spans of the export calls themselves shouldn't be recorded
+            Self::run_export_worker(client, config, receiver)
+                .with_context(Context::current().with_telemetry_suppressed()),
+        );
+        Self { sender }
+    }
+
+    /// Decodes and enqueues spans for export.
+    pub fn receive(&self, request: ExportTraceServiceRequest) -> TraceExportResult<()> {
+        self.enqueue(OtlpTraceConverter::convert_request(request))
+    }
+
+    /// Decodes and enqueues Zipkin v2 JSON spans for export.
+    #[cfg(feature = "zipkin-receiver")]
+    pub fn receive_zipkin(&self, spans: &serde_json::Value) -> TraceExportResult<()> {
+        self.enqueue(crate::ZipkinTraceConverter::convert_json(spans)?)
+    }
+
+    fn enqueue(&self, resource_spans: Vec<OtlpResourceSpanData>) -> TraceExportResult<()> {
+        if resource_spans.iter().all(|data| data.spans.is_empty()) {
+            return Ok(());
+        }
+        self.sender
+            .try_send(ReceiverMessage::Export(resource_spans))
+            .map_err(|err| {
+                let message = match err {
+                    mpsc::error::TrySendError::Full(_) => "OTLP receiver export queue is full",
+                    mpsc::error::TrySendError::Closed(_) => "OTLP receiver is shut down",
+                };
+                GcloudTraceError::SystemError(GcloudTraceSystemError::new(message.to_string()))
+            })
+    }
+
+    /// Exports all buffered spans and stops the background export task.
+    pub async fn shutdown(&self) {
+        let (sender, receiver) = oneshot::channel();
+        if self
+            .sender
+            .send(ReceiverMessage::Shutdown(sender))
+            .await
+            .is_ok()
+        {
+            let _ = receiver.await;
+        }
+    }
+
+    /// OTLP/gRPC trace service to be added to your own tonic server.
+    pub fn grpc_service(&self) -> TraceServiceServer<GcpOtlpTraceService> {
+        TraceServiceServer::new(GcpOtlpTraceService {
+            receiver: self.clone(),
+        })
+    }
+
+    /// OTLP/HTTP router handling `POST /v1/traces` (and `POST /api/v2/spans` for Zipkin
+    /// with the `zipkin-receiver` feature), to be served or merged into your own axum router.
+    pub fn http_router(&self) -> Router {
+        let router = Router::new().route("/v1/traces", post(Self::export_http));
+        #[cfg(feature = "zipkin-receiver")]
+        let router = router.route("/api/v2/spans", post(Self::export_zipkin_http));
+        router.with_state(self.clone())
+    }
+
+    /// Serves OTLP/gRPC and/or OTLP/HTTP on the specified addresses until one of the servers fails.
+    pub async fn serve(
+        &self,
+        grpc_addr: Option<SocketAddr>,
+        http_addr: Option<SocketAddr>,
+    ) -> TraceExportResult<()> {
+        let grpc_server = async {
+            match grpc_addr {
+                Some(addr) => tonic::transport::Server::builder()
+                    .add_service(self.grpc_service())
+                    .serve(addr)
+                    .await
+                    .map_err(|err| Self::server_error("OTLP/gRPC", err)),
+                None => Ok(()),
+            }
+        };
+        let http_server = async {
+            match http_addr {
+                Some(addr) => {
+                    let listener = tokio::net::TcpListener::bind(addr)
+                        .await
+                        .map_err(|err| Self::server_error("OTLP/HTTP", err))?;
+                    axum::serve(listener, self.http_router())
+                        .await
+                        .map_err(|err| Self::server_error("OTLP/HTTP", err))
+                }
+                None => Ok(()),
+            }
+        };
+        futures::try_join!(grpc_server, http_server)?;
+        Ok(())
+    }
+
+    fn server_error<E>(server: &str, err: E) -> GcloudTraceError
+    where
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        GcloudTraceError::SystemError(
+            GcloudTraceSystemError::new(format!("{server} server error: {err}"))
+                .with_root_cause(Box::new(err)),
+        )
+    }
+
+    async fn export_http(
+        State(receiver): State<GcpOtlpReceiver>,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> Response {
+        let body = match Self::decode_content_encoding(&headers, body) {
+            Ok(body) => body,
+            Err(err) => return err.into_response(),
+        };
+        let is_json = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|content_type| content_type.starts_with("application/json"));
+
+        let request = if is_json {
+            serde_json::from_slice::<ExportTraceServiceRequest>(&body).map_err(|e| e.to_string())
+        } else {
+            ExportTraceServiceRequest::decode(body).map_err(|e| e.to_string())
+        };
+
+        match request {
+            Ok(request) => match receiver.receive(request) {
+                Ok(()) if is_json => (
+                    [(header::CONTENT_TYPE, "application/json")],
+                    serde_json::to_vec(&ExportTraceServiceResponse::default()).unwrap_or_default(),
+                )
+                    .into_response(),
+                Ok(()) => (
+                    [(header::CONTENT_TYPE, "application/x-protobuf")],
+                    ExportTraceServiceResponse::default().encode_to_vec(),
+                )
+                    .into_response(),
+                Err(err) => (StatusCode::SERVICE_UNAVAILABLE, err.to_string()).into_response(),
+            },
+            Err(err) => (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid OTLP trace request: {err}"),
+            )
+                .into_response(),
+        }
+    }
+
+    // OTLP/HTTP clients may compress request bodies with gzip
+    fn decode_content_encoding(
+        headers: &HeaderMap,
+        body: Bytes,
+    ) -> Result<Bytes, (StatusCode, String)> {
+        let encoding = headers.get(header::CONTENT_ENCODING).map(|value| {
+            value
+                .to_str()
+                .unwrap_or_default()
+                .trim()
+                .to_ascii_lowercase()
+        });
+        match encoding.as_deref() {
+            None | Some("identity") => Ok(body),
+            Some("gzip") => {
+                let mut decoded = Vec::new();
+                flate2::read::GzDecoder::new(body.as_ref())
+                    .take(MAX_DECOMPRESSED_BODY_SIZE + 1)
+                    .read_to_end(&mut decoded)
+                    .map_err(|err| {
+                        (
+                            StatusCode::BAD_REQUEST,
+                            format!("Invalid gzip request body: {err}"),
+                        )
+                    })?;
+                if decoded.len() as u64 > MAX_DECOMPRESSED_BODY_SIZE {
+                    return Err((
+                        StatusCode::PAYLOAD_TOO_LARGE,
+                        format!(
+                            "Decompressed request body exceeds {MAX_DECOMPRESSED_BODY_SIZE} bytes"
+                        ),
+                    ));
+                }
+                Ok(decoded.into())
+            }
+            Some(encoding) => Err((
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                format!("Unsupported content encoding: {encoding}"),
+            )),
+        }
+    }
+
+    #[cfg(feature = "zipkin-receiver")]
+    async fn export_zipkin_http(State(receiver): State<GcpOtlpReceiver>, body: Bytes) -> Response {
+        let result = serde_json::from_slice::<serde_json::Value>(&body)
+            .map_err(|e| format!("Invalid Zipkin spans: {e}"))
+            .and_then(|spans| {
+                crate::ZipkinTraceConverter::convert_json(&spans).map_err(|e| e.to_string())
+            });
+
+        match result {
+            Ok(resource_spans) => match receiver.enqueue(resource_spans) {
+                Ok(()) => StatusCode::ACCEPTED.into_response(),
+                Err(err) => (StatusCode::SERVICE_UNAVAILABLE, err.to_string()).into_response(),
+            },
+            Err(err) => (StatusCode::BAD_REQUEST, err).into_response(),
+        }
+    }
+
+    async fn run_export_worker(
+        client: GcpCloudTraceExporterClient,
+        config: GcpOtlpReceiverConfig,
+        mut receiver: mpsc::Receiver<ReceiverMessage>,
+    ) {
+        let mut pending = PendingSpans::default();
+        let mut interval = tokio::time::interval(config.scheduled_delay);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                message = receiver.recv() => match message {
+                    Some(ReceiverMessage::Export(resource_spans)) => {
+                        pending.push(resource_spans);
+                        while pending.spans_count >= config.max_export_batch_size {
+                            let batch = pending.take_batch(config.max_export_batch_size);
+                            Self::export_with_retries(&client, &config, batch).await;
+                        }
+                    }
+                    Some(ReceiverMessage::Shutdown(shutdown_sender)) => {
+                        Self::export_all(&client, &config, &mut pending).await;
+                        let _ = shutdown_sender.send(());
+                        break;
+                    }
+                    None => {
+                        Self::export_all(&client, &config, &mut pending).await;
+                        break;
+                    }
+                },
+                _ = interval.tick() => Self::export_all(&client, &config, &mut pending).await,
+            }
+        }
+    }
+
+    async fn export_all(
+        client: &GcpCloudTraceExporterClient,
+        config: &GcpOtlpReceiverConfig,
+        pending: &mut PendingSpans,
+    ) {
+        while pending.spans_count > 0 {
+            let batch = pending.take_batch(config.max_export_batch_size);
+            Self::export_with_retries(client, config, batch).await;
+        }
+    }
+
+    async fn export_with_retries(
+        client: &GcpCloudTraceExporterClient,
+        config: &GcpOtlpReceiverConfig,
+        batch: Vec<OtlpResourceSpanData>,
+    ) {
+        let mut retry_delay = config.initial_retry_delay;
+        let mut retries = 0;
+        loop {
+            match client.export_resource_batches(&batch).await {
+                Ok(()) => return,
+                Err(GcloudTraceError::NetworkError(err))
+                    if err.is_retryable() && retries < config.max_export_retries =>
+                {
+                    tracing::warn!(
+                        "Failed to export spans received over OTLP, retrying in {retry_delay:?}: {err}"
+                    );
+                    tokio::time::sleep(retry_delay).await;
+                    retry_delay *= 2;
+                    retries += 1;
+                }
+                Err(err) => {
+                    tracing::error!(
+                        "Failed to export {} spans received over OTLP: {err}",
+                        batch.iter().map(|data| data.spans.len()).sum::<usize>()
+                    );
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct PendingSpans {
+    resource_spans: Vec<OtlpResourceSpanData>,
+    spans_count: usize,
+}
+
+impl PendingSpans {
+    fn push(&mut self, resource_spans: Vec<OtlpResourceSpanData>) {
+        for data in resource_spans {
+            self.spans_count += data.spans.len();
+            self.resource_spans.push(data);
+        }
+    }
+
+    // Takes up to `max_spans` spans, splitting resource groups when needed
+    fn take_batch(&mut self, max_spans: usize) -> Vec<OtlpResourceSpanData> {
+        let mut batch = Vec::new();
+        let mut batch_spans_count = 0;
+
+        while batch_spans_count < max_spans && !self.resource_spans.is_empty() {
+            let remaining = max_spans - batch_spans_count;
+            let data = &mut self.resource_spans[0];
+            if data.spans.len() <= remaining {
+                let data = self.resource_spans.remove(0);
+                batch_spans_count += data.spans.len();
+                batch.push(data);
+            } else {
+                batch_spans_count += remaining;
+                batch.push(OtlpResourceSpanData {
+                    resource: data.resource.clone(),
+                    spans: data.spans.drain(..remaining).collect(),
+                });
+            }
+        }
+
+        self.spans_count -= batch_spans_count;
+        batch
+    }
+}
+
+/// OTLP/gRPC trace service implementation used by [`GcpOtlpReceiver::grpc_service`].
+pub struct GcpOtlpTraceService {
+    receiver: GcpOtlpReceiver,
+}
+
+#[tonic::async_trait]
+impl TraceService for GcpOtlpTraceService {
+    async fn export(
+        &self,
+        request: tonic::Request<ExportTraceServiceRequest>,
+    ) -> Result<tonic::Response<ExportTraceServiceResponse>, tonic::Status> {
+        self.receiver
+            .receive(request.into_inner())
+            .map_err(|err| tonic::Status::unavailable(err.to_string()))?;
+        Ok(tonic::Response::new(ExportTraceServiceResponse::default()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::GcloudTraceNetworkError;
+    use gcloud_sdk::google::devtools::cloudtrace::v2::BatchWriteSpansRequest;
+    use opentelemetry_proto::tonic::collector::trace::v1::trace_service_client::TraceServiceClient;
+    use opentelemetry_proto::tonic::resource::v1::Resource as OtlpResource;
+    use opentelemetry_proto::tonic::trace::v1::{ResourceSpans, ScopeSpans, Span as OtlpSpan};
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+    use tower::ServiceExt;
+
+    #[derive(Clone, Default)]
+    struct RecordingTransport {
+        requests: Arc<Mutex<Vec<BatchWriteSpansRequest>>>,
+        errors: Arc<Mutex<VecDeque<tonic::Code>>>,
+    }
+
+    impl RecordingTransport {
+        fn failing_with(codes: Vec<tonic::Code>) -> Self {
+            Self {
+                errors: Arc::new(Mutex::new(codes.into())),
+                ..Self::default()
+            }
+        }
+
+        fn spans_count(&self) -> usize {
+            self.requests
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|request| request.spans.len())
+                .sum()
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl CloudTraceTransport for RecordingTransport {
+        async fn batch_write_spans(
+            &self,
+            request: BatchWriteSpansRequest,
+        ) -> TraceExportResult<()> {
+            self.requests.lock().unwrap().push(request);
+            match self.errors.lock().unwrap().pop_front() {
+                Some(code) => Err(GcloudTraceError::NetworkError(
+                    GcloudTraceNetworkError::new(format!("{code:?}")).with_code(code),
+                )),
+                None => Ok(()),
+            }
+        }
+    }
+
+    fn test_request(spans_count: u8) -> ExportTraceServiceRequest {
+        ExportTraceServiceRequest {
+            resource_spans: vec![ResourceSpans {
+                resource: Some(OtlpResource::default()),
+                scope_spans: vec![ScopeSpans {
+                    spans: (1..=spans_count)
+                        .map(|idx| OtlpSpan {
+                            trace_id: vec![1; 16],
+                            span_id: vec![idx; 8],
+                            name: format!("span-{idx}"),
+                            start_time_unix_nano: 1_000_000_000,
+                            end_time_unix_nano: 2_000_000_000,
+                            ..OtlpSpan::default()
+                        })
+                        .collect(),
+                    ..ScopeSpans::default()
+                }],
+                ..ResourceSpans::default()
+            }],
+        }
+    }
+
+    fn test_receiver(
+        transport: RecordingTransport,
+        config: GcpOtlpReceiverConfig,
+    ) -> GcpOtlpReceiver {
+        GcpOtlpReceiver::with_transport(
+            SpanConverter::new("test-project".to_string()),
+            transport,
+            config
+                .with_scheduled_delay(Duration::from_secs(3600))
+                .with_initial_retry_delay(Duration::from_millis(1)),
+        )
+    }
+
+    #[tokio::test]
+    async fn receives_otlp_grpc() {
+        let transport = RecordingTransport::default();
+        let receiver = test_receiver(transport.clone(), GcpOtlpReceiverConfig::new());
+
+        let incoming =
+            tonic::transport::server::TcpIncoming::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = incoming.local_addr().unwrap();
+        let server = tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(receiver.grpc_service())
+                .serve_with_incoming(incoming),
+        );
+
+        let mut client = TraceServiceClient::connect(format!("http://{addr}"))
+            .await
+            .unwrap();
+        client.export(test_request(3)).await.unwrap();
+        receiver.shutdown().await;
+        server.abort();
+
+        let requests = transport.requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].name, "projects/test-project");
+        assert_eq!(requests[0].spans.len(), 3);
+        assert_eq!(
+            requests[0].spans[0].name,
+            "projects/test-project/traces/01010101010101010101010101010101/spans/0101010101010101"
+        );
+    }
+
+    #[tokio::test]
+    async fn receives_otlp_http() {
+        let transport = RecordingTransport::default();
+        let receiver = test_receiver(transport.clone(), GcpOtlpReceiverConfig::new());
+
+        let protobuf_response = receiver
+            .http_router()
+            .oneshot(
+                axum::http::Request::post("/v1/traces")
+                    .header(header::CONTENT_TYPE, "application/x-protobuf")
+                    .body(axum::body::Body::from(test_request(2).encode_to_vec()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(protobuf_response.status(), StatusCode::OK);
+
+        let json_response = receiver
+            .http_router()
+            .oneshot(
+                axum::http::Request::post("/v1/traces")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(axum::body::Body::from(
+                        serde_json::to_vec(&test_request(1)).unwrap(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(json_response.status(), StatusCode::OK);
+
+        let invalid_response = receiver
+            .http_router()
+            .oneshot(
+                axum::http::Request::post("/v1/traces")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(axum::body::Body::from("{"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(invalid_response.status(), StatusCode::BAD_REQUEST);
+
+        receiver.shutdown().await;
+        assert_eq!(transport.spans_count(), 3);
+    }
+
+    #[tokio::test]
+    async fn decodes_gzip_otlp_http() {
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let transport = RecordingTransport::default();
+        let receiver = test_receiver(transport.clone(), GcpOtlpReceiverConfig::new());
+        let post = |encoding: &str, body: Vec<u8>| {
+            axum::http::Request::post("/v1/traces")
+                .header(header::CONTENT_TYPE, "application/x-protobuf")
+                .header(header::CONTENT_ENCODING, encoding)
+                .body(axum::body::Body::from(body))
+                .unwrap()
+        };
+
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&test_request(2).encode_to_vec()).unwrap();
+        let gzip_response = receiver
+            .http_router()
+            .oneshot(post("gzip", encoder.finish().unwrap()))
+            .await
+            .unwrap();
+        assert_eq!(gzip_response.status(), StatusCode::OK);
+
+        let invalid_gzip_response = receiver
+            .http_router()
+            .oneshot(post("gzip", test_request(1).encode_to_vec()))
+            .await
+            .unwrap();
+        assert_eq!(invalid_gzip_response.status(), StatusCode::BAD_REQUEST);
+
+        let unsupported_response = receiver
+            .http_router()
+            .oneshot(post("br", test_request(1).encode_to_vec()))
+            .await
+            .unwrap();
+        assert_eq!(
+            unsupported_response.status(),
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+
+        receiver.shutdown().await;
+        assert_eq!(transport.spans_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn splits_batches() {
+        let transport = RecordingTransport::default();
+        let receiver = test_receiver(
+            transport.clone(),
+            GcpOtlpReceiverConfig::new().with_max_export_batch_size(2),
+        );
+        receiver.receive(test_request(5)).unwrap();
+        receiver.shutdown().await;
+
+        let requests = transport.requests.lock().unwrap();
+        let batch_sizes: Vec<usize> = requests.iter().map(|request| request.spans.len()).collect();
+        assert_eq!(batch_sizes, vec![2, 2, 1]);
+    }
+
+    #[tokio::test]
+    async fn zero_batch_size_is_clamped() {
+        let transport = RecordingTransport::default();
+        let receiver = test_receiver(
+            transport.clone(),
+            GcpOtlpReceiverConfig::new().with_max_export_batch_size(0),
+        );
+        receiver.receive(test_request(2)).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), receiver.shutdown())
+            .await
+            .unwrap();
+        assert_eq!(transport.requests.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn retries_only_retryable_errors() {
+        let transport = RecordingTransport::failing_with(vec![
+            tonic::Code::Unavailable,
+            tonic::Code::ResourceExhausted,
+        ]);
+        let receiver = test_receiver(transport.clone(), GcpOtlpReceiverConfig::new());
+        receiver.receive(test_request(1)).unwrap();
+        receiver.shutdown().await;
+        assert_eq!(transport.requests.lock().unwrap().len(), 3);
+
+        for code in [tonic::Code::InvalidArgument, tonic::Code::PermissionDenied] {
+            let transport = RecordingTransport::failing_with(vec![code]);
+            let receiver = test_receiver(transport.clone(), GcpOtlpReceiverConfig::new());
+            receiver.receive(test_request(1)).unwrap();
+            receiver.shutdown().await;
+            assert_eq!(transport.requests.lock().unwrap().len(), 1, "{code:?}");
+        }
+    }
+}
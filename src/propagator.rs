@@ -0,0 +1,427 @@
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use base64::Engine;
+use opentelemetry::propagation::text_map_propagator::FieldIter;
+use opentelemetry::propagation::{Extractor, Injector, TextMapPropagator};
+use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState};
+use opentelemetry::Context;
+use std::sync::OnceLock;
+
+pub const GCP_CLOUD_TRACE_CONTEXT_HEADER: &str = "x-cloud-trace-context";
+pub const GRPC_TRACE_BIN_HEADER: &str = "grpc-trace-bin";
+
+/// Propagator for the `X-Cloud-Trace-Context: TRACE_ID/SPAN_ID;o=OPTIONS` header
+/// used by Google Cloud load balancers and serverless front ends.
+///
+/// The span id is a decimal number and `o=1` means the request is traced,
+/// which is converted to the sampled trace flag.
+#[derive(Debug, Clone, Default)]
+pub struct GcpCloudTraceContextPropagator;
+
+impl GcpCloudTraceContextPropagator {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Parses a header value into a remote span context.
+    pub fn parse_header(value: &str) -> Option<SpanContext> {
+        let (trace_id, rest) = value.trim().split_once('/')?;
+        let (span_id, options) = match rest.split_once(';') {
+            Some((span_id, options)) => (span_id, Some(options)),
+            None => (rest, None),
+        };
+
+        let trace_id = TraceId::from_hex(trace_id).ok()?;
+        let span_id = SpanId::from_bytes(span_id.parse::<u64>().ok()?.to_be_bytes());
+        if trace_id == TraceId::INVALID || span_id == SpanId::INVALID {
+            return None;
+        }
+
+        let sampled = options
+            .and_then(|options| options.strip_prefix("o="))
+            .and_then(|options| options.parse::<u8>().ok())
+            .is_some_and(|options| options & 1 == 1);
+
+        Some(SpanContext::new(
+            trace_id,
+            span_id,
+            if sampled {
+                TraceFlags::SAMPLED
+            } else {
+                TraceFlags::default()
+            },
+            true,
+            TraceState::default(),
+        ))
+    }
+
+    /// Formats a span context as a header value.
+    pub fn format_header(span_context: &SpanContext) -> String {
+        format!(
+            "{}/{};o={}",
+            span_context.trace_id(),
+            u64::from_be_bytes(span_context.span_id().to_bytes()),
+            if span_context.is_sampled() { 1 } else { 0 }
+        )
+    }
+}
+
+impl TextMapPropagator for GcpCloudTraceContextPropagator {
+    fn inject_context(&self, cx: &Context, injector: &mut dyn Injector) {
+        let span = cx.span();
+        let span_context = span.span_context();
+        if span_context.is_valid() {
+            injector.set(
+                GCP_CLOUD_TRACE_CONTEXT_HEADER,
+                Self::format_header(span_context),
+            );
+        }
+    }
+
+    fn extract_with_context(&self, cx: &Context, extractor: &dyn Extractor) -> Context {
+        extractor
+            .get(GCP_CLOUD_TRACE_CONTEXT_HEADER)
+            .and_then(Self::parse_header)
+            .map(|span_context| cx.with_remote_span_context(span_context))
+            .unwrap_or_else(|| cx.clone())
+    }
+
+    fn fields(&self) -> FieldIter<'_> {
+        static FIELDS: OnceLock<[String; 1]> = OnceLock::new();
+        FieldIter::new(FIELDS.get_or_init(|| [GCP_CLOUD_TRACE_CONTEXT_HEADER.to_string()]))
+    }
+}
+
+/// Propagator for the OpenCensus binary format used in the `grpc-trace-bin` metadata header
+/// by Google gRPC services.
+///
+/// The format is a version byte (0) followed by fields prefixed by their ids:
+/// trace id (0, 16 bytes), span id (1, 8 bytes) and trace options (2, 1 byte).
+/// As a text map propagator, the value is base64 encoded the same way gRPC encodes `-bin` headers.
+#[derive(Debug, Clone, Default)]
+pub struct BinaryTraceContextPropagator;
+
+impl BinaryTraceContextPropagator {
+    const VERSION: u8 = 0;
+    const TRACE_ID_FIELD: u8 = 0;
+    const SPAN_ID_FIELD: u8 = 1;
+    const TRACE_OPTIONS_FIELD: u8 = 2;
+    pub const ENCODED_LEN: usize = 29;
+
+    pub fn new() -> Self {
+        Self
+    }
+
+    pub fn to_bytes(span_context: &SpanContext) -> [u8; Self::ENCODED_LEN] {
+        let mut bytes = [0u8; Self::ENCODED_LEN];
+        bytes[0] = Self::VERSION;
+        bytes[1] = Self::TRACE_ID_FIELD;
+        bytes[2..18].copy_from_slice(&span_context.trace_id().to_bytes());
+        bytes[18] = Self::SPAN_ID_FIELD;
+        bytes[19..27].copy_from_slice(&span_context.span_id().to_bytes());
+        bytes[27] = Self::TRACE_OPTIONS_FIELD;
+        bytes[28] = span_context.trace_flags().to_u8() & TraceFlags::SAMPLED.to_u8();
+        bytes
+    }
+
+    /// Decodes a remote span context.
+    ///
+    /// Fields need to be in order, and parsing stops at the first unknown field
+    /// so newer versions with additional fields can still be read. Trace options are optional.
+    pub fn from_bytes(bytes: &[u8]) -> Option<SpanContext> {
+        // Versions above 0 are only required to keep the known fields compatible
+        let (_version, mut rest) = bytes.split_first()?;
+
+        let trace_id = match rest.split_first() {
+            Some((&Self::TRACE_ID_FIELD, value)) if value.len() >= 16 => {
+                rest = &value[16..];
+                TraceId::from_bytes(value[..16].try_into().ok()?)
+            }
+            _ => return None,
+        };
+        let span_id = match rest.split_first() {
+            Some((&Self::SPAN_ID_FIELD, value)) if value.len() >= 8 => {
+                rest = &value[8..];
+                SpanId::from_bytes(value[..8].try_into().ok()?)
+            }
+            _ => return None,
+        };
+        let trace_options = match rest.split_first() {
+            Some((&Self::TRACE_OPTIONS_FIELD, value)) => *value.first()?,
+            _ => 0,
+        };
+
+        if trace_id == TraceId::INVALID || span_id == SpanId::INVALID {
+            return None;
+        }
+
+        Some(SpanContext::new(
+            trace_id,
+            span_id,
+            TraceFlags::new(trace_options & TraceFlags::SAMPLED.to_u8()),
+            true,
+            TraceState::default(),
+        ))
+    }
+}
+
+impl TextMapPropagator for BinaryTraceContextPropagator {
+    fn inject_context(&self, cx: &Context, injector: &mut dyn Injector) {
+        let span = cx.span();
+        let span_context = span.span_context();
+        if span_context.is_valid() {
+            injector.set(
+                GRPC_TRACE_BIN_HEADER,
+                STANDARD_NO_PAD.encode(Self::to_bytes(span_context)),
+            );
+        }
+    }
+
+    fn extract_with_context(&self, cx: &Context, extractor: &dyn Extractor) -> Context {
+        extractor
+            .get(GRPC_TRACE_BIN_HEADER)
+            .and_then(|value| {
+                STANDARD_NO_PAD
+                    .decode(value.trim().trim_end_matches('='))
+                    .ok()
+            })
+            .and_then(|bytes| Self::from_bytes(&bytes))
+            .map(|span_context| cx.with_remote_span_context(span_context))
+            .unwrap_or_else(|| cx.clone())
+    }
+
+    fn fields(&self) -> FieldIter<'_> {
+        static FIELDS: OnceLock<[String; 1]> = OnceLock::new();
+        FieldIter::new(FIELDS.get_or_init(|| [GRPC_TRACE_BIN_HEADER.to_string()]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    // Example from the OpenCensus binary encoding specification
+    const SPEC_EXAMPLE: [u8; 29] = [
+        0, 0, 64, 65, 66, 67, 68, 69, 70, 71, 72, 73, 74, 75, 76, 77, 78, 79, 1, 97, 98, 99, 100,
+        101, 102, 103, 104, 2, 1,
+    ];
+
+    fn spec_span_context() -> SpanContext {
+        SpanContext::new(
+            TraceId::from_hex("404142434445464748494a4b4c4d4e4f").unwrap(),
+            SpanId::from_hex("6162636465666768").unwrap(),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        )
+    }
+
+    #[test]
+    fn decodes_spec_example() {
+        assert_eq!(
+            BinaryTraceContextPropagator::from_bytes(&SPEC_EXAMPLE),
+            Some(spec_span_context())
+        );
+    }
+
+    #[test]
+    fn encodes_spec_example() {
+        assert_eq!(
+            BinaryTraceContextPropagator::to_bytes(&spec_span_context()),
+            SPEC_EXAMPLE
+        );
+    }
+
+    #[test]
+    fn round_trips_not_sampled() {
+        let span_context = SpanContext::new(
+            TraceId::from(0x0102030405060708090a0b0c0d0e0f10),
+            SpanId::from(0x1112131415161718),
+            TraceFlags::NOT_SAMPLED,
+            true,
+            TraceState::default(),
+        );
+        let bytes = BinaryTraceContextPropagator::to_bytes(&span_context);
+        assert_eq!(
+            BinaryTraceContextPropagator::from_bytes(&bytes),
+            Some(span_context)
+        );
+    }
+
+    #[test]
+    fn ignores_trailing_unknown_field() {
+        let mut bytes = SPEC_EXAMPLE.to_vec();
+        bytes.extend_from_slice(&[3, 0xaa, 0xbb]);
+        assert_eq!(
+            BinaryTraceContextPropagator::from_bytes(&bytes),
+            Some(spec_span_context())
+        );
+    }
+
+    #[test]
+    fn decodes_missing_trace_options_as_not_sampled() {
+        let decoded = BinaryTraceContextPropagator::from_bytes(&SPEC_EXAMPLE[..27]).unwrap();
+        assert_eq!(decoded.trace_id(), spec_span_context().trace_id());
+        assert_eq!(decoded.span_id(), spec_span_context().span_id());
+        assert!(!decoded.is_sampled());
+    }
+
+    #[test]
+    fn decodes_newer_versions() {
+        let mut bytes = SPEC_EXAMPLE;
+        bytes[0] = 1;
+        assert_eq!(
+            BinaryTraceContextPropagator::from_bytes(&bytes),
+            Some(spec_span_context())
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        for len in [0, 1, 2, 17, 18, 19, 26] {
+            assert_eq!(
+                BinaryTraceContextPropagator::from_bytes(&SPEC_EXAMPLE[..len]),
+                None,
+                "length {len}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_ids() {
+        let mut zero_trace_id = SPEC_EXAMPLE;
+        zero_trace_id[2..18].fill(0);
+        assert_eq!(
+            BinaryTraceContextPropagator::from_bytes(&zero_trace_id),
+            None
+        );
+
+        let mut zero_span_id = SPEC_EXAMPLE;
+        zero_span_id[19..27].fill(0);
+        assert_eq!(
+            BinaryTraceContextPropagator::from_bytes(&zero_span_id),
+            None
+        );
+    }
+
+    #[test]
+    fn round_trips_text_map() {
+        let propagator = BinaryTraceContextPropagator::new();
+        let mut carrier = HashMap::new();
+        propagator.inject_context(
+            &Context::new().with_remote_span_context(spec_span_context()),
+            &mut carrier,
+        );
+        assert_eq!(
+            carrier.get(GRPC_TRACE_BIN_HEADER).map(String::as_str),
+            Some("AABAQUJDREVGR0hJSktMTU5PAWFiY2RlZmdoAgE")
+        );
+
+        let cx = propagator.extract(&carrier);
+        assert_eq!(cx.span().span_context(), &spec_span_context());
+    }
+    fn cloud_trace_span_context(sampled: bool) -> SpanContext {
+        SpanContext::new(
+            TraceId::from_hex("105445aa7843bc8bf206b12000100000").unwrap(),
+            SpanId::from(123),
+            if sampled {
+                TraceFlags::SAMPLED
+            } else {
+                TraceFlags::NOT_SAMPLED
+            },
+            true,
+            TraceState::default(),
+        )
+    }
+
+    #[test]
+    fn parses_cloud_trace_header() {
+        assert_eq!(
+            GcpCloudTraceContextPropagator::parse_header(
+                "105445aa7843bc8bf206b12000100000/123;o=1"
+            ),
+            Some(cloud_trace_span_context(true))
+        );
+        assert_eq!(
+            GcpCloudTraceContextPropagator::parse_header(
+                " 105445aa7843bc8bf206b12000100000/123;o=0 "
+            ),
+            Some(cloud_trace_span_context(false))
+        );
+        // Span ids are decimal, including values above i64::MAX
+        assert_eq!(
+            GcpCloudTraceContextPropagator::parse_header(
+                "105445aa7843bc8bf206b12000100000/18446744073709551615;o=1"
+            )
+            .map(|span_context| span_context.span_id()),
+            Some(SpanId::from(u64::MAX))
+        );
+    }
+
+    #[test]
+    fn parses_missing_or_invalid_options_as_not_sampled() {
+        for value in [
+            "105445aa7843bc8bf206b12000100000/123",
+            "105445aa7843bc8bf206b12000100000/123;o=",
+            "105445aa7843bc8bf206b12000100000/123;o=x",
+            "105445aa7843bc8bf206b12000100000/123;x=1",
+        ] {
+            assert_eq!(
+                GcpCloudTraceContextPropagator::parse_header(value),
+                Some(cloud_trace_span_context(false)),
+                "{value}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_cloud_trace_headers() {
+        for value in [
+            "",
+            "105445aa7843bc8bf206b12000100000",
+            "00000000000000000000000000000000/123;o=1",
+            "105445aa7843bc8bf206b12000100000/0;o=1",
+            "105445aa7843bc8bf206b12000100000/7b;o=1",
+            "105445aa7843bc8bf206b12000100000/-1;o=1",
+            "not-a-trace-id/123;o=1",
+        ] {
+            assert_eq!(
+                GcpCloudTraceContextPropagator::parse_header(value),
+                None,
+                "{value}"
+            );
+        }
+    }
+
+    #[test]
+    fn formats_cloud_trace_header() {
+        assert_eq!(
+            GcpCloudTraceContextPropagator::format_header(&cloud_trace_span_context(true)),
+            "105445aa7843bc8bf206b12000100000/123;o=1"
+        );
+        assert_eq!(
+            GcpCloudTraceContextPropagator::format_header(&cloud_trace_span_context(false)),
+            "105445aa7843bc8bf206b12000100000/123;o=0"
+        );
+    }
+
+    #[test]
+    fn round_trips_cloud_trace_text_map() {
+        let propagator = GcpCloudTraceContextPropagator::new();
+        for sampled in [true, false] {
+            let mut carrier = HashMap::new();
+            propagator.inject_context(
+                &Context::new().with_remote_span_context(cloud_trace_span_context(sampled)),
+                &mut carrier,
+            );
+            let cx = propagator.extract(&carrier);
+            assert_eq!(cx.span().span_context(), &cloud_trace_span_context(sampled));
+        }
+
+        // Nothing is injected without a valid span
+        let mut carrier = HashMap::new();
+        propagator.inject_context(&Context::new(), &mut carrier);
+        assert!(carrier.is_empty());
+        assert!(!propagator.extract(&carrier).has_active_span());
+    }
+}
@@ -0,0 +1,510 @@
+use gcloud_sdk::google::devtools::cloudtrace::v2::{
+    attribute_value as gcp_attribute_value, span as gspan, AttributeValue as GcpAttributeValue,
+    BatchWriteSpansRequest, Span as GcpSpan, TruncatableString,
+};
+use gcloud_sdk::google::rpc::Status as GcpStatus;
+use gcloud_sdk::prost_types;
+use serde_json::{Map, Value};
+
+// Proto3 JSON mapping for the Cloud Trace v2 messages produced by the exporter.
+// Following the proto3 JSON rules: field names are lowerCamelCase, default values are omitted,
+// 64-bit integers are encoded as strings and enums are encoded by their names.
+
+pub(crate) fn span_to_json(span: &GcpSpan) -> Value {
+    let mut obj = Map::new();
+    insert_str(&mut obj, "name", &span.name);
+    insert_str(&mut obj, "spanId", &span.span_id);
+    insert_str(&mut obj, "parentSpanId", &span.parent_span_id);
+    if let Some(display_name) = &span.display_name {
+        obj.insert(
+            "displayName".to_string(),
+            truncatable_string_to_json(display_name),
+        );
+    }
+    if let Some(start_time) = &span.start_time {
+        obj.insert(
+            "startTime".to_string(),
+            Value::String(start_time.to_string()),
+        );
+    }
+    if let Some(end_time) = &span.end_time {
+        obj.insert("endTime".to_string(), Value::String(end_time.to_string()));
+    }
+    if let Some(attributes) = &span.attributes {
+        obj.insert("attributes".to_string(), attributes_to_json(attributes));
+    }
+    if let Some(time_events) = &span.time_events {
+        obj.insert("timeEvents".to_string(), time_events_to_json(time_events));
+    }
+    if let Some(links) = &span.links {
+        obj.insert("links".to_string(), links_to_json(links));
+    }
+    if let Some(status) = &span.status {
+        obj.insert("status".to_string(), status_to_json(status));
+    }
+    if let Some(same_process_as_parent_span) = span.same_process_as_parent_span {
+        obj.insert(
+            "sameProcessAsParentSpan".to_string(),
+            Value::Bool(same_process_as_parent_span),
+        );
+    }
+    if let Some(child_span_count) = span.child_span_count {
+        obj.insert("childSpanCount".to_string(), Value::from(child_span_count));
+    }
+    if span.span_kind != gspan::SpanKind::Unspecified as i32 {
+        obj.insert(
+            "spanKind".to_string(),
+            Value::String(span.span_kind().as_str_name().to_string()),
+        );
+    }
+    Value::Object(obj)
+}
+
+fn insert_str(obj: &mut Map<String, Value>, key: &str, value: &str) {
+    if !value.is_empty() {
+        obj.insert(key.to_string(), Value::String(value.to_string()));
+    }
+}
+
+fn insert_i32(obj: &mut Map<String, Value>, key: &str, value: i32) {
+    if value != 0 {
+        obj.insert(key.to_string(), Value::from(value));
+    }
+}
+
+fn insert_i64(obj: &mut Map<String, Value>, key: &str, value: i64) {
+    if value != 0 {
+        obj.insert(key.to_string(), Value::String(value.to_string()));
+    }
+}
+
+fn truncatable_string_to_json(str: &TruncatableString) -> Value {
+    let mut obj = Map::new();
+    insert_str(&mut obj, "value", &str.value);
+    insert_i32(&mut obj, "truncatedByteCount", str.truncated_byte_count);
+    Value::Object(obj)
+}
+
+fn attributes_to_json(attributes: &gspan::Attributes) -> Value {
+    let mut obj = Map::new();
+    obj.insert(
+        "attributeMap".to_string(),
+        Value::Object(
+            attributes
+                .attribute_map
+                .iter()
+                .map(|(key, value)| (key.clone(), attribute_value_to_json(value)))
+                .collect(),
+        ),
+    );
+    insert_i32(
+        &mut obj,
+        "droppedAttributesCount",
+        attributes.dropped_attributes_count,
+    );
+    Value::Object(obj)
+}
+
+fn attribute_value_to_json(value: &GcpAttributeValue) -> Value {
+    let mut obj = Map::new();
+    match &value.value {
+        Some(gcp_attribute_value::Value::StringValue(str)) => {
+            obj.insert("stringValue".to_string(), truncatable_string_to_json(str));
+        }
+        Some(gcp_attribute_value::Value::IntValue(value)) => {
+            obj.insert("intValue".to_string(), Value::String(value.to_string()));
+        }
+        Some(gcp_attribute_value::Value::BoolValue(value)) => {
+            obj.insert("boolValue".to_string(), Value::Bool(*value));
+        }
+        None => {}
+    }
+    Value::Object(obj)
+}
+
+fn time_events_to_json(time_events: &gspan::TimeEvents) -> Value {
+    let mut obj = Map::new();
+    obj.insert(
+        "timeEvent".to_string(),
+        Value::Array(
+            time_events
+                .time_event
+                .iter()
+                .map(time_event_to_json)
+                .collect(),
+        ),
+    );
+    insert_i32(
+        &mut obj,
+        "droppedAnnotationsCount",
+        time_events.dropped_annotations_count,
+    );
+    insert_i32(
+        &mut obj,
+        "droppedMessageEventsCount",
+        time_events.dropped_message_events_count,
+    );
+    Value::Object(obj)
+}
+
+fn time_event_to_json(time_event: &gspan::TimeEvent) -> Value {
+    let mut obj = Map::new();
+    if let Some(time) = &time_event.time {
+        obj.insert("time".to_string(), Value::String(time.to_string()));
+    }
+    match &time_event.value {
+        Some(gspan::time_event::Value::Annotation(annotation)) => {
+            let mut annotation_obj = Map::new();
+            if let Some(description) = &annotation.description {
+                annotation_obj.insert(
+                    "description".to_string(),
+                    truncatable_string_to_json(description),
+                );
+            }
+            if let Some(attributes) = &annotation.attributes {
+                annotation_obj.insert("attributes".to_string(), attributes_to_json(attributes));
+            }
+            obj.insert("annotation".to_string(), Value::Object(annotation_obj));
+        }
+        Some(gspan::time_event::Value::MessageEvent(message_event)) => {
+            let mut message_event_obj = Map::new();
+            if message_event.r#type != gspan::time_event::message_event::Type::Unspecified as i32 {
+                message_event_obj.insert(
+                    "type".to_string(),
+                    Value::String(message_event.r#type().as_str_name().to_string()),
+                );
+            }
+            insert_i64(&mut message_event_obj, "id", message_event.id);
+            insert_i64(
+                &mut message_event_obj,
+                "uncompressedSizeBytes",
+                message_event.uncompressed_size_bytes,
+            );
+            insert_i64(
+                &mut message_event_obj,
+                "compressedSizeBytes",
+                message_event.compressed_size_bytes,
+            );
+            obj.insert("messageEvent".to_string(), Value::Object(message_event_obj));
+        }
+        None => {}
+    }
+    Value::Object(obj)
+}
+
+fn links_to_json(links: &gspan::Links) -> Value {
+    let mut obj = Map::new();
+    obj.insert(
+        "link".to_string(),
+        Value::Array(links.link.iter().map(link_to_json).collect()),
+    );
+    insert_i32(&mut obj, "droppedLinksCount", links.dropped_links_count);
+    Value::Object(obj)
+}
+
+fn link_to_json(link: &gspan::Link) -> Value {
+    let mut obj = Map::new();
+    insert_str(&mut obj, "traceId", &link.trace_id);
+    insert_str(&mut obj, "spanId", &link.span_id);
+    if link.r#type != gspan::link::Type::Unspecified as i32 {
+        obj.insert(
+            "type".to_string(),
+            Value::String(link.r#type().as_str_name().to_string()),
+        );
+    }
+    if let Some(attributes) = &link.attributes {
+        obj.insert("attributes".to_string(), attributes_to_json(attributes));
+    }
+    Value::Object(obj)
+}
+
+fn status_to_json(status: &GcpStatus) -> Value {
+    let mut obj = Map::new();
+    insert_i32(&mut obj, "code", status.code);
+    insert_str(&mut obj, "message", &status.message);
+    Value::Object(obj)
+}
+
+pub(crate) fn batch_write_spans_request_to_json(request: &BatchWriteSpansRequest) -> Value {
+    let mut obj = Map::new();
+    insert_str(&mut obj, "name", &request.name);
+    obj.insert(
+        "spans".to_string(),
+        Value::Array(request.spans.iter().map(span_to_json).collect()),
+    );
+    Value::Object(obj)
+}
+
+type JsonResult<T> = Result<T, String>;
+
+pub(crate) fn batch_write_spans_request_from_json(
+    value: &Value,
+) -> JsonResult<BatchWriteSpansRequest> {
+    let obj = as_object(value, "BatchWriteSpansRequest")?;
+    Ok(BatchWriteSpansRequest {
+        name: get_str(obj, "name")?,
+        spans: get_array(obj, "spans")?
+            .iter()
+            .map(span_from_json)
+            .collect::<JsonResult<Vec<GcpSpan>>>()?,
+    })
+}
+
+pub(crate) fn span_from_json(value: &Value) -> JsonResult<GcpSpan> {
+    let obj = as_object(value, "Span")?;
+    Ok(GcpSpan {
+        name: get_str(obj, "name")?,
+        span_id: get_str(obj, "spanId")?,
+        parent_span_id: get_str(obj, "parentSpanId")?,
+        display_name: obj
+            .get("displayName")
+            .map(truncatable_string_from_json)
+            .transpose()?,
+        start_time: obj.get("startTime").map(timestamp_from_json).transpose()?,
+        end_time: obj.get("endTime").map(timestamp_from_json).transpose()?,
+        attributes: obj
+            .get("attributes")
+            .map(attributes_from_json)
+            .transpose()?,
+        stack_trace: None,
+        time_events: obj
+            .get("timeEvents")
+            .map(time_events_from_json)
+            .transpose()?,
+        links: obj.get("links").map(links_from_json).transpose()?,
+        status: obj.get("status").map(status_from_json).transpose()?,
+        same_process_as_parent_span: obj
+            .get("sameProcessAsParentSpan")
+            .map(|v| {
+                v.as_bool()
+                    .ok_or_else(|| "sameProcessAsParentSpan must be a boolean".to_string())
+            })
+            .transpose()?,
+        child_span_count: obj
+            .get("childSpanCount")
+            .map(|v| as_i64(v, "childSpanCount").map(|v| v as i32))
+            .transpose()?,
+        span_kind: match obj.get("spanKind") {
+            Some(v) => enum_from_json(v, "spanKind", |name| {
+                gspan::SpanKind::from_str_name(name).map(|v| v as i32)
+            })?,
+            None => 0,
+        },
+    })
+}
+
+fn as_object<'a>(value: &'a Value, name: &str) -> JsonResult<&'a Map<String, Value>> {
+    value
+        .as_object()
+        .ok_or_else(|| format!("{name} must be a JSON object"))
+}
+
+fn get_str(obj: &Map<String, Value>, key: &str) -> JsonResult<String> {
+    match obj.get(key) {
+        None => Ok(String::new()),
+        Some(value) => value
+            .as_str()
+            .map(|v| v.to_string())
+            .ok_or_else(|| format!("{key} must be a string")),
+    }
+}
+
+fn get_array<'a>(obj: &'a Map<String, Value>, key: &str) -> JsonResult<&'a [Value]> {
+    match obj.get(key) {
+        None => Ok(&[]),
+        Some(value) => value
+            .as_array()
+            .map(|v| v.as_slice())
+            .ok_or_else(|| format!("{key} must be an array")),
+    }
+}
+
+// Proto3 JSON accepts both numbers and strings for integers
+fn as_i64(value: &Value, key: &str) -> JsonResult<i64> {
+    value
+        .as_i64()
+        .or_else(|| value.as_str().and_then(|v| v.parse().ok()))
+        .ok_or_else(|| format!("{key} must be an integer"))
+}
+
+fn get_i64(obj: &Map<String, Value>, key: &str) -> JsonResult<i64> {
+    obj.get(key).map(|v| as_i64(v, key)).unwrap_or(Ok(0))
+}
+
+fn get_i32(obj: &Map<String, Value>, key: &str) -> JsonResult<i32> {
+    get_i64(obj, key).map(|v| v as i32)
+}
+
+fn enum_from_json<F>(value: &Value, key: &str, from_str_name: F) -> JsonResult<i32>
+where
+    F: Fn(&str) -> Option<i32>,
+{
+    match value {
+        Value::String(name) => from_str_name(name).ok_or_else(|| format!("Unknown {key}: {name}")),
+        _ => as_i64(value, key).map(|v| v as i32),
+    }
+}
+
+fn timestamp_from_json(value: &Value) -> JsonResult<prost_types::Timestamp> {
+    value
+        .as_str()
+        .ok_or_else(|| "Timestamp must be a string".to_string())?
+        .parse()
+        .map_err(|e| format!("Invalid timestamp: {e}"))
+}
+
+fn truncatable_string_from_json(value: &Value) -> JsonResult<TruncatableString> {
+    let obj = as_object(value, "TruncatableString")?;
+    Ok(TruncatableString {
+        value: get_str(obj, "value")?,
+        truncated_byte_count: get_i32(obj, "truncatedByteCount")?,
+    })
+}
+
+fn attributes_from_json(value: &Value) -> JsonResult<gspan::Attributes> {
+    let obj = as_object(value, "Attributes")?;
+    Ok(gspan::Attributes {
+        attribute_map: match obj.get("attributeMap") {
+            None => Default::default(),
+            Some(map) => as_object(map, "attributeMap")?
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), attribute_value_from_json(value)?)))
+                .collect::<JsonResult<_>>()?,
+        },
+        dropped_attributes_count: get_i32(obj, "droppedAttributesCount")?,
+    })
+}
+
+fn attribute_value_from_json(value: &Value) -> JsonResult<GcpAttributeValue> {
+    let obj = as_object(value, "AttributeValue")?;
+    let value = if let Some(str) = obj.get("stringValue") {
+        Some(gcp_attribute_value::Value::StringValue(
+            truncatable_string_from_json(str)?,
+        ))
+    } else if let Some(value) = obj.get("intValue") {
+        Some(gcp_attribute_value::Value::IntValue(as_i64(
+            value, "intValue",
+        )?))
+    } else if let Some(value) = obj.get("boolValue") {
+        Some(gcp_attribute_value::Value::BoolValue(
+            value
+                .as_bool()
+                .ok_or_else(|| "boolValue must be a boolean".to_string())?,
+        ))
+    } else {
+        None
+    };
+    Ok(GcpAttributeValue { value })
+}
+
+fn time_events_from_json(value: &Value) -> JsonResult<gspan::TimeEvents> {
+    let obj = as_object(value, "TimeEvents")?;
+    Ok(gspan::TimeEvents {
+        time_event: get_array(obj, "timeEvent")?
+            .iter()
+            .map(time_event_from_json)
+            .collect::<JsonResult<_>>()?,
+        dropped_annotations_count: get_i32(obj, "droppedAnnotationsCount")?,
+        dropped_message_events_count: get_i32(obj, "droppedMessageEventsCount")?,
+    })
+}
+
+fn time_event_from_json(value: &Value) -> JsonResult<gspan::TimeEvent> {
+    let obj = as_object(value, "TimeEvent")?;
+    let value = if let Some(annotation) = obj.get("annotation") {
+        let annotation = as_object(annotation, "Annotation")?;
+        Some(gspan::time_event::Value::Annotation(
+            gspan::time_event::Annotation {
+                description: annotation
+                    .get("description")
+                    .map(truncatable_string_from_json)
+                    .transpose()?,
+                attributes: annotation
+                    .get("attributes")
+                    .map(attributes_from_json)
+                    .transpose()?,
+            },
+        ))
+    } else if let Some(message_event) = obj.get("messageEvent") {
+        let message_event = as_object(message_event, "MessageEvent")?;
+        Some(gspan::time_event::Value::MessageEvent(
+            gspan::time_event::MessageEvent {
+                r#type: match message_event.get("type") {
+                    Some(v) => enum_from_json(v, "type", |name| {
+                        gspan::time_event::message_event::Type::from_str_name(name)
+                            .map(|v| v as i32)
+                    })?,
+                    None => 0,
+                },
+                id: get_i64(message_event, "id")?,
+                uncompressed_size_bytes: get_i64(message_event, "uncompressedSizeBytes")?,
+                compressed_size_bytes: get_i64(message_event, "compressedSizeBytes")?,
+            },
+        ))
+    } else {
+        None
+    };
+    Ok(gspan::TimeEvent {
+        time: obj.get("time").map(timestamp_from_json).transpose()?,
+        value,
+    })
+}
+
+fn links_from_json(value: &Value) -> JsonResult<gspan::Links> {
+    let obj = as_object(value, "Links")?;
+    Ok(gspan::Links {
+        link: get_array(obj, "link")?
+            .iter()
+            .map(link_from_json)
+            .collect::<JsonResult<_>>()?,
+        dropped_links_count: get_i32(obj, "droppedLinksCount")?,
+    })
+}
+
+fn link_from_json(value: &Value) -> JsonResult<gspan::Link> {
+    let obj = as_object(value, "Link")?;
+    Ok(gspan::Link {
+        trace_id: get_str(obj, "traceId")?,
+        span_id: get_str(obj, "spanId")?,
+        r#type: match obj.get("type") {
+            Some(v) => enum_from_json(v, "type", |name| {
+                gspan::link::Type::from_str_name(name).map(|v| v as i32)
+            })?,
+            None => 0,
+        },
+        attributes: obj
+            .get("attributes")
+            .map(attributes_from_json)
+            .transpose()?,
+    })
+}
+
+fn status_from_json(value: &Value) -> JsonResult<GcpStatus> {
+    let obj = as_object(value, "Status")?;
+    Ok(GcpStatus {
+        code: get_i32(obj, "code")?,
+        message: get_str(obj, "message")?,
+        ..GcpStatus::default()
+    })
+}
+
+/// Proto3 JSON encoding of Cloud Trace v2 requests (the format used by the Cloud Trace REST API).
+pub struct GcpCloudTraceJson;
+
+impl GcpCloudTraceJson {
+    pub fn batch_write_spans_request_to_json(request: &BatchWriteSpansRequest) -> Value {
+        batch_write_spans_request_to_json(request)
+    }
+
+    pub fn batch_write_spans_request_from_json(
+        value: &Value,
+    ) -> crate::TraceExportResult<BatchWriteSpansRequest> {
+        batch_write_spans_request_from_json(value).map_err(|e| {
+            crate::errors::GcloudTraceError::SystemError(
+                crate::errors::GcloudTraceSystemError::new(format!(
+                    "Invalid BatchWriteSpansRequest JSON: {e}"
+                )),
+            )
+        })
+    }
+}
@@ -0,0 +1,277 @@
+use crate::{SdkTracer, SpanLinkType};
+use opentelemetry::propagation::{Extractor, Injector, TextMapPropagator};
+use opentelemetry::trace::{Link, Span, SpanKind, TraceContextExt, Tracer, TracerProvider};
+use opentelemetry::{Context, InstrumentationScope, KeyValue};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use std::collections::HashMap;
+
+/// Prefix of the message attributes used by the Google Pub/Sub client libraries
+/// (e.g. `googclient_traceparent`).
+pub const PUBSUB_ATTRIBUTE_PREFIX: &str = "googclient_";
+
+struct PubSubAttributesInjector<'a>(&'a mut HashMap<String, String>);
+
+impl Injector for PubSubAttributesInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        self.0
+            .insert(format!("{PUBSUB_ATTRIBUTE_PREFIX}{key}"), value);
+    }
+}
+
+struct PubSubAttributesExtractor<'a>(&'a HashMap<String, String>);
+
+impl Extractor for PubSubAttributesExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .get(&format!("{PUBSUB_ATTRIBUTE_PREFIX}{key}"))
+            .map(|value| value.as_str())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0
+            .keys()
+            .filter_map(|key| key.strip_prefix(PUBSUB_ATTRIBUTE_PREFIX))
+            .collect()
+    }
+}
+
+/// Injects the span context of `cx` into Pub/Sub message attributes
+/// as `googclient_traceparent` and `googclient_tracestate`.
+pub fn inject_pubsub_attributes(cx: &Context, attributes: &mut HashMap<String, String>) {
+    TraceContextPropagator::new().inject_context(cx, &mut PubSubAttributesInjector(attributes));
+}
+
+/// Extracts the producer span context from Pub/Sub message attributes.
+pub fn extract_pubsub_attributes(attributes: &HashMap<String, String>) -> Context {
+    TraceContextPropagator::new().extract(&PubSubAttributesExtractor(attributes))
+}
+
+/// Creates PRODUCER and CONSUMER spans for Pub/Sub messages with the `messaging.*` attributes.
+///
+/// Consumer spans are linked to the producer spans propagated in the message attributes
+/// instead of being their children, since a message can be received long after it was published
+/// (or several times). The links are exported as Cloud Trace parent links.
+#[derive(Debug, Clone)]
+pub struct GcpPubSubTracer {
+    tracer: SdkTracer,
+}
+
+impl GcpPubSubTracer {
+    pub fn new(tracer_provider: &SdkTracerProvider) -> Self {
+        let scope = InstrumentationScope::builder("opentelemetry-gcloud")
+            .with_version(env!("CARGO_PKG_VERSION"))
+            .build();
+        Self {
+            tracer: tracer_provider.tracer_with_scope(scope),
+        }
+    }
+
+    /// Starts a PRODUCER span as a child of the current context and injects it
+    /// into the message attributes. End the span when the message is published
+    /// (e.g. after setting `messaging.message.id`).
+    pub fn start_publish_span(
+        &self,
+        topic: &str,
+        attributes: &mut HashMap<String, String>,
+    ) -> opentelemetry_sdk::trace::Span {
+        let span = self
+            .tracer
+            .span_builder(format!("send {topic}"))
+            .with_kind(SpanKind::Producer)
+            .with_attributes(vec![
+                KeyValue::new("messaging.system", "gcp_pubsub"),
+                KeyValue::new("messaging.operation.type", "send"),
+                KeyValue::new("messaging.operation.name", "send"),
+                KeyValue::new("messaging.destination.name", topic.to_string()),
+            ])
+            .start(&self.tracer);
+
+        inject_pubsub_attributes(
+            &Context::new().with_remote_span_context(span.span_context().clone()),
+            attributes,
+        );
+        span
+    }
+
+    /// Starts a CONSUMER span as a child of the current context
+    /// linked to the producer span found in the message attributes.
+    pub fn start_receive_span(
+        &self,
+        subscription: &str,
+        message_id: Option<&str>,
+        attributes: &HashMap<String, String>,
+    ) -> opentelemetry_sdk::trace::Span {
+        let mut span_attributes = vec![
+            KeyValue::new("messaging.system", "gcp_pubsub"),
+            KeyValue::new("messaging.operation.type", "receive"),
+            KeyValue::new("messaging.operation.name", "receive"),
+            KeyValue::new(
+                "messaging.destination.subscription.name",
+                subscription.to_string(),
+            ),
+        ];
+        if let Some(message_id) = message_id {
+            span_attributes.push(KeyValue::new(
+                "messaging.message.id",
+                message_id.to_string(),
+            ));
+        }
+
+        let producer_cx = extract_pubsub_attributes(attributes);
+        let producer_span_context = producer_cx.span().span_context().clone();
+        let links = if producer_span_context.is_valid() {
+            vec![Link::new(
+                producer_span_context,
+                vec![KeyValue::new(
+                    SpanLinkType::LINK_TYPE_ATTRIBUTE,
+                    SpanLinkType::ParentLinkedSpan
+                        .attribute_value()
+                        .unwrap_or_default(),
+                )],
+                0,
+            )]
+        } else {
+            Vec::new()
+        };
+
+        self.tracer
+            .span_builder(format!("receive {subscription}"))
+            .with_kind(SpanKind::Consumer)
+            .with_attributes(span_attributes)
+            .with_links(links)
+            .start(&self.tracer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::{SpanContext, SpanId, TraceFlags, TraceId, TraceState};
+    use opentelemetry::Value;
+    use opentelemetry_sdk::trace::{InMemorySpanExporter, SpanData};
+
+    fn test_provider() -> (InMemorySpanExporter, SdkTracerProvider) {
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        (exporter, provider)
+    }
+
+    fn attribute(span: &SpanData, key: &str) -> Option<Value> {
+        span.attributes
+            .iter()
+            .find(|kv| kv.key.as_str() == key)
+            .map(|kv| kv.value.clone())
+    }
+
+    #[test]
+    fn round_trips_message_attributes() {
+        let span_context = SpanContext::new(
+            TraceId::from(0x0102030405060708090a0b0c0d0e0f10),
+            SpanId::from(0x1112131415161718),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::from_key_value([("vendor", "value")]).unwrap(),
+        );
+        let mut attributes = HashMap::from([("user".to_string(), "attribute".to_string())]);
+        inject_pubsub_attributes(
+            &Context::new().with_remote_span_context(span_context.clone()),
+            &mut attributes,
+        );
+
+        assert_eq!(
+            attributes.get("googclient_traceparent").map(String::as_str),
+            Some("00-0102030405060708090a0b0c0d0e0f10-1112131415161718-01")
+        );
+        assert_eq!(
+            attributes.get("googclient_tracestate").map(String::as_str),
+            Some("vendor=value")
+        );
+        assert!(!attributes.contains_key("traceparent"));
+
+        let cx = extract_pubsub_attributes(&attributes);
+        assert_eq!(cx.span().span_context(), &span_context);
+    }
+
+    #[test]
+    fn ignores_unprefixed_attributes() {
+        let attributes = HashMap::from([(
+            "traceparent".to_string(),
+            "00-0102030405060708090a0b0c0d0e0f10-1112131415161718-01".to_string(),
+        )]);
+        assert!(!extract_pubsub_attributes(&attributes)
+            .span()
+            .span_context()
+            .is_valid());
+    }
+
+    #[test]
+    fn links_receive_spans_to_publish_spans() {
+        let (exporter, provider) = test_provider();
+        let tracer = GcpPubSubTracer::new(&provider);
+
+        let mut attributes = HashMap::new();
+        tracer.start_publish_span("orders", &mut attributes).end();
+        tracer
+            .start_receive_span("orders-sub", Some("message-1"), &attributes)
+            .end();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 2);
+        let (publish, receive) = (&spans[0], &spans[1]);
+
+        assert_eq!(publish.name, "send orders");
+        assert_eq!(publish.span_kind, SpanKind::Producer);
+        assert_eq!(
+            attribute(publish, "messaging.destination.name"),
+            Some(Value::from("orders"))
+        );
+
+        assert_eq!(receive.name, "receive orders-sub");
+        assert_eq!(receive.span_kind, SpanKind::Consumer);
+        assert_eq!(
+            attribute(receive, "messaging.destination.subscription.name"),
+            Some(Value::from("orders-sub"))
+        );
+        assert_eq!(
+            attribute(receive, "messaging.message.id"),
+            Some(Value::from("message-1"))
+        );
+
+        // Linked to the producer instead of being its child
+        assert_ne!(
+            receive.span_context.trace_id(),
+            publish.span_context.trace_id()
+        );
+        assert_eq!(receive.parent_span_id, SpanId::INVALID);
+        assert_eq!(receive.links.links.len(), 1);
+        let link = &receive.links.links[0];
+        assert_eq!(link.span_context.span_id(), publish.span_context.span_id());
+        assert_eq!(
+            link.attributes,
+            vec![KeyValue::new(
+                SpanLinkType::LINK_TYPE_ATTRIBUTE,
+                SpanLinkType::ParentLinkedSpan
+                    .attribute_value()
+                    .unwrap_or_default(),
+            )]
+        );
+    }
+
+    #[test]
+    fn receives_messages_without_trace_context() {
+        let (exporter, provider) = test_provider();
+        let tracer = GcpPubSubTracer::new(&provider);
+
+        tracer
+            .start_receive_span("orders-sub", None, &HashMap::new())
+            .end();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 1);
+        assert!(spans[0].links.links.is_empty());
+        assert_eq!(attribute(&spans[0], "messaging.message.id"), None);
+    }
+}
@@ -0,0 +1,215 @@
+use crate::errors::{GcloudTraceError, GcloudTraceNetworkError};
+use crate::transport::CloudTraceTransport;
+use crate::{GcpCloudTraceCredentials, TraceExportResult};
+use async_trait::async_trait;
+use gcloud_sdk::google::devtools::cloudtrace::v2::BatchWriteSpansRequest;
+use gcloud_sdk::{tonic, GoogleAuthTokenGenerator, GCP_DEFAULT_SCOPES};
+use std::sync::Arc;
+
+/// Cloud Trace v2 client using the REST API over HTTP/1.1 instead of gRPC.
+#[derive(Clone)]
+pub struct GcpCloudTraceRestClient {
+    http_client: reqwest::Client,
+    token_generator: Arc<GoogleAuthTokenGenerator>,
+    api_url: String,
+}
+
+impl GcpCloudTraceRestClient {
+    pub async fn new(
+        api_url: &str,
+        credentials: &GcpCloudTraceCredentials,
+    ) -> TraceExportResult<Self> {
+        let token_generator = GoogleAuthTokenGenerator::new(
+            credentials.to_token_source_type(),
+            GCP_DEFAULT_SCOPES.clone(),
+        )
+        .await?;
+
+        let http_client = reqwest::Client::builder().http1_only();
+        // Both TLS features enable rustls in reqwest, which otherwise prefers native-tls
+        // when another dependency also enables it
+        #[cfg(any(feature = "tls-roots", feature = "tls-webpki-roots"))]
+        let http_client = http_client.tls_backend_rustls();
+        let http_client = http_client.build()?;
+
+        Ok(Self {
+            http_client,
+            token_generator: Arc::new(token_generator),
+            api_url: api_url.trim_end_matches('/').to_string(),
+        })
+    }
+}
+
+#[async_trait]
+impl CloudTraceTransport for GcpCloudTraceRestClient {
+    async fn batch_write_spans(&self, request: BatchWriteSpansRequest) -> TraceExportResult<()> {
+        let token = self.token_generator.create_token().await?;
+        let url = format!("{}/v2/{}/traces:batchWrite", self.api_url, request.name);
+
+        let response = self
+            .http_client
+            .post(url)
+            .header(reqwest::header::AUTHORIZATION, token.header_value())
+            // The project name is bound to the URL path, so only the spans are sent in the body
+            .json(&serde_json::json!({
+                "spans": request
+                    .spans
+                    .iter()
+                    .map(crate::proto_json::span_to_json)
+                    .collect::<Vec<_>>()
+            }))
+            .send()
+            .await?;
+
+        let status = response.status();
+        if status.is_success() {
+            Ok(())
+        } else {
+            let body = response.text().await.unwrap_or_default();
+            Err(GcloudTraceError::NetworkError(
+                GcloudTraceNetworkError::new(format!(
+                    "Cloud Trace REST API responded with {status}: {body}"
+                ))
+                .with_code(http_status_code(status)),
+            ))
+        }
+    }
+}
+
+// Mapping of Google APIs HTTP statuses to gRPC codes
+fn http_status_code(status: reqwest::StatusCode) -> tonic::Code {
+    match status.as_u16() {
+        400 => tonic::Code::InvalidArgument,
+        401 => tonic::Code::Unauthenticated,
+        403 => tonic::Code::PermissionDenied,
+        404 => tonic::Code::NotFound,
+        409 => tonic::Code::Aborted,
+        429 => tonic::Code::ResourceExhausted,
+        499 => tonic::Code::Cancelled,
+        500 => tonic::Code::Internal,
+        501 => tonic::Code::Unimplemented,
+        503 => tonic::Code::Unavailable,
+        504 => tonic::Code::DeadlineExceeded,
+        _ => tonic::Code::Unknown,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Bytes;
+    use axum::http::{HeaderMap, StatusCode, Uri};
+    use gcloud_sdk::google::devtools::cloudtrace::v2::{Span, TruncatableString};
+    use std::sync::Mutex;
+
+    #[derive(Debug)]
+    struct RecordedRequest {
+        path: String,
+        authorization: Option<String>,
+        body: serde_json::Value,
+    }
+
+    // Local stand-in for the Cloud Trace REST API responding with the given status
+    async fn start_server(status: StatusCode) -> (String, Arc<Mutex<Vec<RecordedRequest>>>) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        let app = axum::Router::new().fallback(move |uri: Uri, headers: HeaderMap, body: Bytes| {
+            let recorded = recorded.clone();
+            async move {
+                recorded.lock().unwrap().push(RecordedRequest {
+                    path: uri.path().to_string(),
+                    authorization: headers
+                        .get(axum::http::header::AUTHORIZATION)
+                        .and_then(|value| value.to_str().ok())
+                        .map(|value| value.to_string()),
+                    body: serde_json::from_slice(&body).unwrap(),
+                });
+                (status, "{}")
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{addr}/"), requests)
+    }
+
+    async fn test_client(api_url: &str) -> GcpCloudTraceRestClient {
+        GcpCloudTraceRestClient::new(
+            api_url,
+            &crate::credentials::StaticTokenSource::credentials(),
+        )
+        .await
+        .unwrap()
+    }
+
+    fn test_request() -> BatchWriteSpansRequest {
+        BatchWriteSpansRequest {
+            name: "projects/test-project".to_string(),
+            spans: vec![Span {
+                name: "projects/test-project/traces/0123456789abcdef0123456789abcdef/spans/0123456789abcdef"
+                    .to_string(),
+                span_id: "0123456789abcdef".to_string(),
+                display_name: Some(TruncatableString {
+                    value: "test_span".to_string(),
+                    truncated_byte_count: 0,
+                }),
+                ..Default::default()
+            }],
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_spans_as_json() {
+        let (api_url, requests) = start_server(StatusCode::OK).await;
+        let client = test_client(&api_url).await;
+        let request = test_request();
+
+        client.batch_write_spans(request.clone()).await.unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(
+            requests[0].path,
+            "/v2/projects/test-project/traces:batchWrite"
+        );
+        assert_eq!(
+            requests[0].authorization.as_deref(),
+            Some("Bearer test-token")
+        );
+        assert_eq!(
+            requests[0].body,
+            serde_json::json!({
+                "spans": [crate::proto_json::span_to_json(&request.spans[0])]
+            })
+        );
+        assert_eq!(
+            requests[0].body["spans"][0]["displayName"]["value"],
+            "test_span"
+        );
+    }
+
+    #[tokio::test]
+    async fn maps_error_statuses() {
+        for (status, code) in [
+            (StatusCode::BAD_REQUEST, tonic::Code::InvalidArgument),
+            (StatusCode::FORBIDDEN, tonic::Code::PermissionDenied),
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                tonic::Code::ResourceExhausted,
+            ),
+            (StatusCode::SERVICE_UNAVAILABLE, tonic::Code::Unavailable),
+            (StatusCode::IM_A_TEAPOT, tonic::Code::Unknown),
+        ] {
+            let (api_url, _) = start_server(status).await;
+            let client = test_client(&api_url).await;
+
+            match client.batch_write_spans(test_request()).await {
+                Err(GcloudTraceError::NetworkError(err)) => {
+                    assert_eq!(err.code, Some(code), "{status}");
+                    assert!(err.message.contains(status.as_str()), "{}", err.message);
+                }
+                other => panic!("unexpected result for {status}: {other:?}"),
+            }
+        }
+    }
+}
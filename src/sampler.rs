@@ -0,0 +1,611 @@
+use opentelemetry::trace::{
+    Link, SamplingDecision, SamplingResult, SpanKind, TraceContextExt, TraceId, TraceState,
+};
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_sdk::trace::ShouldSample;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Samples at most the configured number of traces per second per instance,
+/// the same way as the Google Cloud client libraries do for root spans.
+#[derive(Debug, Clone)]
+pub struct GcpQpsSampler {
+    interval: Duration,
+    started_at: Instant,
+    // Nanoseconds since `started_at` when the next trace can be sampled
+    next_sample_at: Arc<AtomicU64>,
+}
+
+impl GcpQpsSampler {
+    /// About 1 trace per 10 seconds.
+    pub const DEFAULT_TRACES_PER_SECOND: f64 = 0.1;
+
+    pub fn new(traces_per_second: f64) -> Self {
+        Self {
+            // Zero, negative, NaN and very small rates never sample instead of panicking
+            interval: Duration::try_from_secs_f64(1.0 / traces_per_second).unwrap_or(Duration::MAX),
+            started_at: Instant::now(),
+            next_sample_at: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    fn try_acquire(&self) -> bool {
+        if self.interval == Duration::MAX {
+            return false;
+        }
+        let now = self.started_at.elapsed().as_nanos() as u64;
+        let interval = self.interval.as_nanos().min(u64::MAX as u128) as u64;
+        let mut next_sample_at = self.next_sample_at.load(Ordering::Relaxed);
+        while now >= next_sample_at {
+            match self.next_sample_at.compare_exchange_weak(
+                next_sample_at,
+                now.saturating_add(interval),
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(current) => next_sample_at = current,
+            }
+        }
+        false
+    }
+}
+
+impl Default for GcpQpsSampler {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_TRACES_PER_SECOND)
+    }
+}
+
+impl ShouldSample for GcpQpsSampler {
+    fn should_sample(
+        &self,
+        parent_context: Option<&Context>,
+        _trace_id: TraceId,
+        _name: &str,
+        _span_kind: &SpanKind,
+        _attributes: &[KeyValue],
+        _links: &[Link],
+    ) -> SamplingResult {
+        SamplingResult {
+            decision: if self.try_acquire() {
+                SamplingDecision::RecordAndSample
+            } else {
+                SamplingDecision::Drop
+            },
+            attributes: Vec::new(),
+            trace_state: parent_trace_state(parent_context),
+        }
+    }
+}
+
+/// Parent based sampler for services behind Google Cloud front ends.
+///
+/// - Remote parents with the sampled flag (`o=1` in `X-Cloud-Trace-Context` or the W3C `traceparent`
+///   sampled flag) are always sampled.
+/// - Remote parents without the sampled flag (e.g. `o=0`) follow the local rate
+///   (the root sampler by default).
+/// - Local parents are followed.
+/// - Root spans use the root sampler ([`GcpQpsSampler`] with about 1 trace per 10 seconds by default).
+///
+/// Use it with [`crate::GcpCloudTraceContextPropagator`] and/or the W3C propagator
+/// to read the remote parent's sampled flag from either format.
+#[derive(Debug, Clone)]
+pub struct GcpParentBasedSampler {
+    root_sampler: Box<dyn ShouldSample>,
+    remote_parent_not_sampled: Box<dyn ShouldSample>,
+}
+
+impl GcpParentBasedSampler {
+    pub fn new() -> Self {
+        Self::with_root_sampler(GcpQpsSampler::default())
+    }
+
+    /// Uses the root sampler for both root spans and not sampled remote parents.
+    pub fn with_root_sampler<T>(root_sampler: T) -> Self
+    where
+        T: ShouldSample + 'static,
+    {
+        let root_sampler: Box<dyn ShouldSample> = Box::new(root_sampler);
+        Self {
+            remote_parent_not_sampled: root_sampler.clone(),
+            root_sampler,
+        }
+    }
+
+    /// Sampler for remote parents without the sampled flag.
+    pub fn with_remote_parent_not_sampled<T>(self, sampler: T) -> Self
+    where
+        T: ShouldSample + 'static,
+    {
+        Self {
+            remote_parent_not_sampled: Box::new(sampler),
+            ..self
+        }
+    }
+}
+
+impl ShouldSample for GcpParentBasedSampler {
+    fn should_sample(
+        &self,
+        parent_context: Option<&Context>,
+        trace_id: TraceId,
+        name: &str,
+        span_kind: &SpanKind,
+        attributes: &[KeyValue],
+        links: &[Link],
+    ) -> SamplingResult {
+        let parent_span_context = parent_context
+            .filter(|cx| cx.has_active_span())
+            .map(|cx| cx.span().span_context().clone());
+
+        match parent_span_context {
+            None => self.root_sampler.should_sample(
+                parent_context,
+                trace_id,
+                name,
+                span_kind,
+                attributes,
+                links,
+            ),
+            Some(span_context) if span_context.is_remote() && !span_context.is_sampled() => self
+                .remote_parent_not_sampled
+                .should_sample(parent_context, trace_id, name, span_kind, attributes, links),
+            Some(span_context) => SamplingResult {
+                decision: if span_context.is_sampled() {
+                    SamplingDecision::RecordAndSample
+                } else {
+                    SamplingDecision::Drop
+                },
+                attributes: Vec::new(),
+                trace_state: span_context.trace_state().clone(),
+            },
+        }
+    }
+}
+
+fn parent_trace_state(parent_context: Option<&Context>) -> TraceState {
+    parent_context
+        .filter(|cx| cx.has_active_span())
+        .map(|cx| cx.span().span_context().trace_state().clone())
+        .unwrap_or_default()
+}
+
+/// Token bucket sampler limiting the number of sampled traces per second
+/// (e.g. to cap Cloud Trace ingestion cost during traffic spikes).
+///
+/// Spans can be assigned to additional sub-budgets by span name or by `http.route`.
+/// Those spans need to fit in both their sub-budget and the global budget.
+/// The effective sampling probability is recorded as the `sampling.probability` attribute
+/// on sampled spans, so span counts can be extrapolated. It is estimated separately
+/// for each sub-budget and for the spans without one.
+///
+/// Use it for root spans only (e.g. with `Sampler::ParentBased`) to keep traces complete,
+/// as [`crate::GcpCloudTraceExporterBuilder::with_rate_limiting_sampler`] does.
+#[derive(Debug, Clone)]
+pub struct RateLimitingSampler {
+    budget: Arc<TokenBucket>,
+    span_name_budgets: HashMap<String, Arc<TokenBucket>>,
+    route_budgets: HashMap<String, Arc<TokenBucket>>,
+}
+
+impl RateLimitingSampler {
+    pub const SAMPLING_PROBABILITY_ATTRIBUTE: &'static str = "sampling.probability";
+
+    pub fn new(traces_per_second: f64) -> Self {
+        Self {
+            budget: Arc::new(TokenBucket::new(traces_per_second, Instant::now())),
+            span_name_budgets: HashMap::new(),
+            route_budgets: HashMap::new(),
+        }
+    }
+
+    pub fn with_span_name_budget(mut self, span_name: &str, traces_per_second: f64) -> Self {
+        self.span_name_budgets.insert(
+            span_name.to_string(),
+            Arc::new(TokenBucket::new(traces_per_second, Instant::now())),
+        );
+        self
+    }
+
+    /// Sub-budget for spans with the `http.route` attribute set to the route when the span starts.
+    pub fn with_route_budget(mut self, route: &str, traces_per_second: f64) -> Self {
+        self.route_budgets.insert(
+            route.to_string(),
+            Arc::new(TokenBucket::new(traces_per_second, Instant::now())),
+        );
+        self
+    }
+
+    fn sub_budget(&self, name: &str, attributes: &[KeyValue]) -> Option<&TokenBucket> {
+        self.span_name_budgets
+            .get(name)
+            .or_else(|| {
+                attributes
+                    .iter()
+                    .find(|kv| kv.key.as_str() == "http.route")
+                    .and_then(|kv| self.route_budgets.get(kv.value.as_str().as_ref()))
+            })
+            .map(Arc::as_ref)
+    }
+
+    /// Returns whether the span is sampled and the estimated sampling probability.
+    fn try_sample(&self, name: &str, attributes: &[KeyValue], now: Instant) -> (bool, f64) {
+        // Always locked in the same order: the global budget, then the sub-budget
+        let mut budget = self.budget.lock(now);
+        match self.sub_budget(name, attributes) {
+            Some(sub_budget) => {
+                // Tokens are only taken when both budgets have one,
+                // so a rejection by the global budget doesn't waste the sub-budget
+                let mut sub_budget = sub_budget.lock(now);
+                let sampled = sub_budget.has_token() && budget.has_token();
+                if sampled {
+                    sub_budget.take_token();
+                    budget.take_token();
+                }
+                (sampled, sub_budget.record(sampled))
+            }
+            None => {
+                let sampled = budget.has_token();
+                if sampled {
+                    budget.take_token();
+                }
+                (sampled, budget.record(sampled))
+            }
+        }
+    }
+}
+
+impl ShouldSample for RateLimitingSampler {
+    fn should_sample(
+        &self,
+        parent_context: Option<&Context>,
+        _trace_id: TraceId,
+        name: &str,
+        _span_kind: &SpanKind,
+        attributes: &[KeyValue],
+        _links: &[Link],
+    ) -> SamplingResult {
+        let (sampled, probability) = self.try_sample(name, attributes, Instant::now());
+
+        SamplingResult {
+            decision: if sampled {
+                SamplingDecision::RecordAndSample
+            } else {
+                SamplingDecision::Drop
+            },
+            attributes: if sampled {
+                vec![KeyValue::new(
+                    Self::SAMPLING_PROBABILITY_ATTRIBUTE,
+                    probability,
+                )]
+            } else {
+                Vec::new()
+            },
+            trace_state: parent_trace_state(parent_context),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    traces_per_second: f64,
+    state: Mutex<TokenBucketState>,
+}
+
+#[derive(Debug)]
+struct TokenBucketState {
+    tokens: f64,
+    refilled_at: Instant,
+    // Sampling decisions for the spans assigned to this bucket in the current and
+    // the previous one second windows, used to estimate the effective sampling probability
+    window_started_at: Instant,
+    window_seen: u64,
+    window_sampled: u64,
+    previous_window_seen: u64,
+    previous_window_sampled: u64,
+}
+
+impl TokenBucket {
+    const STATS_WINDOW: Duration = Duration::from_secs(1);
+
+    fn new(traces_per_second: f64, now: Instant) -> Self {
+        let traces_per_second = traces_per_second.max(0.0);
+        Self {
+            traces_per_second,
+            state: Mutex::new(TokenBucketState {
+                tokens: Self::capacity(traces_per_second),
+                refilled_at: now,
+                window_started_at: now,
+                window_seen: 0,
+                window_sampled: 0,
+                previous_window_seen: 0,
+                previous_window_sampled: 0,
+            }),
+        }
+    }
+
+    // Allows bursts of up to one second of budget, and at least one trace for low rates
+    fn capacity(traces_per_second: f64) -> f64 {
+        if traces_per_second > 0.0 {
+            traces_per_second.max(1.0)
+        } else {
+            0.0
+        }
+    }
+
+    /// Locks the bucket state refilled up to `now`.
+    fn lock(&self, now: Instant) -> MutexGuard<'_, TokenBucketState> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        let elapsed = now.saturating_duration_since(state.refilled_at);
+        state.tokens = (state.tokens + elapsed.as_secs_f64() * self.traces_per_second)
+            .min(Self::capacity(self.traces_per_second));
+        state.refilled_at = state.refilled_at.max(now);
+
+        if now.saturating_duration_since(state.window_started_at) >= Self::STATS_WINDOW {
+            state.previous_window_seen = state.window_seen;
+            state.previous_window_sampled = state.window_sampled;
+            state.window_seen = 0;
+            state.window_sampled = 0;
+            state.window_started_at = now;
+        }
+        state
+    }
+}
+
+impl TokenBucketState {
+    fn has_token(&self) -> bool {
+        self.tokens >= 1.0
+    }
+
+    fn take_token(&mut self) {
+        self.tokens -= 1.0;
+    }
+
+    /// Records a sampling decision and returns the estimated sampling probability.
+    fn record(&mut self, sampled: bool) -> f64 {
+        self.window_seen += 1;
+        if sampled {
+            self.window_sampled += 1;
+        }
+        (self.previous_window_sampled + self.window_sampled) as f64
+            / (self.previous_window_seen + self.window_seen) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::{SpanContext, SpanId, TraceFlags};
+    use opentelemetry_sdk::trace::Sampler;
+
+    #[test]
+    fn handles_out_of_range_rates() {
+        for traces_per_second in [0.0, -1.0, f64::NAN, f64::MIN_POSITIVE, 1e-300] {
+            let sampler = GcpQpsSampler::new(traces_per_second);
+            assert_eq!(sampler.interval, Duration::MAX, "{traces_per_second}");
+            assert!(!sampler.try_acquire(), "{traces_per_second}");
+        }
+        assert_eq!(GcpQpsSampler::new(f64::INFINITY).interval, Duration::ZERO);
+        assert_eq!(GcpQpsSampler::new(0.1).interval, Duration::from_secs(10));
+    }
+    fn sample_at(
+        sampler: &RateLimitingSampler,
+        name: &str,
+        route: Option<&str>,
+        now: Instant,
+    ) -> (bool, f64) {
+        let attributes: Vec<KeyValue> = route
+            .map(|route| KeyValue::new("http.route", route.to_string()))
+            .into_iter()
+            .collect();
+        sampler.try_sample(name, &attributes, now)
+    }
+
+    #[test]
+    fn limits_traces_per_second() {
+        let sampler = RateLimitingSampler::new(2.0);
+        let now = Instant::now();
+
+        let decisions: Vec<bool> = (0..4)
+            .map(|_| sample_at(&sampler, "span", None, now).0)
+            .collect();
+        assert_eq!(decisions, vec![true, true, false, false]);
+
+        // Refilled at the configured rate
+        let now = now + Duration::from_millis(500);
+        assert!(sample_at(&sampler, "span", None, now).0);
+        assert!(!sample_at(&sampler, "span", None, now).0);
+
+        // Bursts are capped at one second of budget
+        let now = now + Duration::from_secs(10);
+        let sampled = (0..5)
+            .filter(|_| sample_at(&sampler, "span", None, now).0)
+            .count();
+        assert_eq!(sampled, 2);
+    }
+
+    #[test]
+    fn never_samples_without_budget() {
+        let sampler = RateLimitingSampler::new(0.0);
+        let now = Instant::now();
+        for seconds in 0..3 {
+            let now = now + Duration::from_secs(seconds);
+            assert!(!sample_at(&sampler, "span", None, now).0);
+        }
+    }
+
+    #[test]
+    fn applies_sub_budgets() {
+        let sampler = RateLimitingSampler::new(3.0)
+            .with_span_name_budget("health", 1.0)
+            .with_route_budget("/users/{id}", 1.0);
+        let now = Instant::now();
+
+        assert!(sample_at(&sampler, "health", None, now).0);
+        assert!(!sample_at(&sampler, "health", None, now).0);
+        assert!(sample_at(&sampler, "GET", Some("/users/{id}"), now).0);
+        assert!(!sample_at(&sampler, "GET", Some("/users/{id}"), now).0);
+        // Unknown routes only use the global budget
+        assert!(sample_at(&sampler, "GET", Some("/orders"), now).0);
+        assert!(!sample_at(&sampler, "GET", Some("/orders"), now).0);
+    }
+
+    #[test]
+    fn keeps_sub_budget_tokens_rejected_by_global_budget() {
+        let sampler = RateLimitingSampler::new(1.0).with_span_name_budget("health", 1.0);
+        let now = Instant::now();
+
+        assert!(sample_at(&sampler, "span", None, now).0);
+        // The global budget is exhausted, so the sub-budget token must not be taken
+        assert!(!sample_at(&sampler, "health", None, now).0);
+
+        let now = now + Duration::from_secs(1);
+        assert!(sample_at(&sampler, "health", None, now).0);
+    }
+
+    #[test]
+    fn estimates_probability_per_budget() {
+        let sampler = RateLimitingSampler::new(10.0).with_span_name_budget("health", 1.0);
+        let now = Instant::now();
+
+        let (sampled, probability) = sample_at(&sampler, "health", None, now);
+        assert!(sampled);
+        assert_eq!(probability, 1.0);
+        for _ in 0..3 {
+            assert!(!sample_at(&sampler, "health", None, now).0);
+        }
+
+        // Other spans aren't affected by the rejected sub-budget spans
+        let (sampled, probability) = sample_at(&sampler, "span", None, now);
+        assert!(sampled);
+        assert_eq!(probability, 1.0);
+
+        // The previous window is included in the estimate
+        let now = now + Duration::from_secs(1);
+        let (sampled, probability) = sample_at(&sampler, "health", None, now);
+        assert!(sampled);
+        assert_eq!(probability, 2.0 / 5.0);
+
+        // Only the last two windows are included
+        let now = now + Duration::from_secs(1);
+        let (sampled, probability) = sample_at(&sampler, "health", None, now);
+        assert!(sampled);
+        assert_eq!(probability, 1.0);
+    }
+
+    #[test]
+    fn records_probability_attribute() {
+        let sampler = RateLimitingSampler::new(1.0);
+        let sample =
+            || sampler.should_sample(None, TraceId::from(1), "span", &SpanKind::Server, &[], &[]);
+
+        let result = sample();
+        assert_eq!(result.decision, SamplingDecision::RecordAndSample);
+        assert_eq!(
+            result.attributes,
+            vec![KeyValue::new(
+                RateLimitingSampler::SAMPLING_PROBABILITY_ATTRIBUTE,
+                1.0
+            )]
+        );
+
+        let result = sample();
+        assert_eq!(result.decision, SamplingDecision::Drop);
+        assert!(result.attributes.is_empty());
+    }
+    fn parent_based_decision(
+        sampler: &GcpParentBasedSampler,
+        parent: Option<(bool, bool)>,
+    ) -> SamplingDecision {
+        let parent_context = parent.map(|(sampled, is_remote)| {
+            Context::new().with_remote_span_context(SpanContext::new(
+                TraceId::from(1),
+                SpanId::from(1),
+                if sampled {
+                    TraceFlags::SAMPLED
+                } else {
+                    TraceFlags::NOT_SAMPLED
+                },
+                is_remote,
+                TraceState::default(),
+            ))
+        });
+        sampler
+            .should_sample(
+                parent_context.as_ref(),
+                TraceId::from(1),
+                "span",
+                &SpanKind::Server,
+                &[],
+                &[],
+            )
+            .decision
+    }
+
+    #[test]
+    fn samples_remote_sampled_parents() {
+        let sampler = GcpParentBasedSampler::with_root_sampler(Sampler::AlwaysOff);
+        assert_eq!(
+            parent_based_decision(&sampler, Some((true, true))),
+            SamplingDecision::RecordAndSample
+        );
+    }
+
+    #[test]
+    fn uses_root_sampler_for_remote_not_sampled_parents() {
+        let sampler = GcpParentBasedSampler::with_root_sampler(Sampler::AlwaysOn);
+        assert_eq!(
+            parent_based_decision(&sampler, Some((false, true))),
+            SamplingDecision::RecordAndSample
+        );
+        let sampler = GcpParentBasedSampler::with_root_sampler(Sampler::AlwaysOff);
+        assert_eq!(
+            parent_based_decision(&sampler, Some((false, true))),
+            SamplingDecision::Drop
+        );
+
+        let sampler = GcpParentBasedSampler::with_root_sampler(Sampler::AlwaysOff)
+            .with_remote_parent_not_sampled(Sampler::AlwaysOn);
+        assert_eq!(
+            parent_based_decision(&sampler, Some((false, true))),
+            SamplingDecision::RecordAndSample
+        );
+        assert_eq!(
+            parent_based_decision(&sampler, None),
+            SamplingDecision::Drop
+        );
+    }
+
+    #[test]
+    fn follows_local_parents() {
+        for root_sampler in [Sampler::AlwaysOn, Sampler::AlwaysOff] {
+            let sampler = GcpParentBasedSampler::with_root_sampler(root_sampler);
+            assert_eq!(
+                parent_based_decision(&sampler, Some((true, false))),
+                SamplingDecision::RecordAndSample
+            );
+            assert_eq!(
+                parent_based_decision(&sampler, Some((false, false))),
+                SamplingDecision::Drop
+            );
+        }
+    }
+
+    #[test]
+    fn uses_root_sampler_without_parents() {
+        let sampler = GcpParentBasedSampler::with_root_sampler(Sampler::AlwaysOn);
+        assert_eq!(
+            parent_based_decision(&sampler, None),
+            SamplingDecision::RecordAndSample
+        );
+        let sampler = GcpParentBasedSampler::with_root_sampler(Sampler::AlwaysOff);
+        assert_eq!(
+            parent_based_decision(&sampler, None),
+            SamplingDecision::Drop
+        );
+    }
+}
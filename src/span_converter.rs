@@ -0,0 +1,1062 @@
+use gcloud_sdk::google::devtools::cloudtrace::v2::{
+    attribute_value as gcp_attribute_value, span as gspan, AttributeValue as GcpAttributeValue,
+    BatchWriteSpansRequest, Span as GcpSpan, TruncatableString,
+};
+use gcloud_sdk::google::rpc::{Code as GcpStatusCode, Status as GcpStatus};
+use gcloud_sdk::prost_types;
+use opentelemetry::KeyValue;
+use opentelemetry_sdk::{trace::SpanData, Resource};
+use rsb_derive::*;
+use std::collections::{HashMap, HashSet};
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// Cloud Trace v2 API limits applied while converting spans.
+#[derive(Debug, Clone, PartialEq, Eq, Builder)]
+pub struct SpanConverterLimits {
+    #[default = "32"]
+    pub max_attributes: usize,
+    #[default = "256"]
+    pub max_attribute_value_len: usize,
+    #[default = "128"]
+    pub max_display_name_len: usize,
+    #[default = "256"]
+    pub max_annotation_description_len: usize,
+    #[default = "128"]
+    pub max_time_events: usize,
+    #[default = "128"]
+    pub max_links: usize,
+}
+
+/// How resource attributes are added to converted spans.
+///
+/// Resource attributes with the same (mapped) key as a span attribute are always skipped.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum ResourceAttributesMode {
+    /// Resource attributes are added to every span after its own attributes (default),
+    /// so span attributes have priority when the attributes limit is reached.
+    #[default]
+    AfterSpanAttributes,
+    /// Resource attributes are added to every span before its own attributes.
+    BeforeSpanAttributes,
+    /// Resource attributes are not added to spans.
+    Ignore,
+}
+
+/// How array attribute values are converted, since Cloud Trace attributes can't be arrays.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum ArrayAttributeEncoding {
+    /// JSON array string (e.g. `["a","b"]` or `[1,2]`) (default).
+    #[default]
+    Json,
+    /// Values joined with the delimiter (e.g. `a,b`).
+    Delimited(String),
+    /// One attribute per element with indexed keys (`key.0`, `key.1`, ...),
+    /// keeping integer and boolean element types.
+    Expanded,
+}
+
+/// Relationship of a linked span to the span containing the link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SpanLinkType {
+    #[default]
+    Unspecified,
+    /// The linked span is a child of the current span (e.g. fan-out).
+    ChildLinkedSpan,
+    /// The linked span is a parent of the current span (e.g. batch consumers or fan-in).
+    ParentLinkedSpan,
+}
+
+impl SpanLinkType {
+    /// Link attribute marking the link type: `parent_linked_span` or `child_linked_span`.
+    /// It is not exported as a link attribute.
+    pub const LINK_TYPE_ATTRIBUTE: &'static str = "gcp.link.type";
+
+    pub fn from_attribute_value(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "parent_linked_span" => Some(SpanLinkType::ParentLinkedSpan),
+            "child_linked_span" => Some(SpanLinkType::ChildLinkedSpan),
+            _ => None,
+        }
+    }
+
+    pub fn attribute_value(&self) -> Option<&'static str> {
+        match self {
+            SpanLinkType::Unspecified => None,
+            SpanLinkType::ChildLinkedSpan => Some("child_linked_span"),
+            SpanLinkType::ParentLinkedSpan => Some("parent_linked_span"),
+        }
+    }
+}
+
+/// Classifies links without the [`SpanLinkType::LINK_TYPE_ATTRIBUTE`] attribute.
+pub trait SpanLinkTypeClassifier: std::fmt::Debug + Send + Sync {
+    fn classify(&self, span: &SpanData, link: &opentelemetry::trace::Link) -> SpanLinkType;
+}
+
+#[derive(Debug, Clone, Builder)]
+pub struct SpanConverterOptions {
+    #[default = "SpanConverterLimits::new()"]
+    pub limits: SpanConverterLimits,
+    /// Renames attribute keys (e.g. to Cloud Trace well-known labels, see [`SpanConverter::well_known_attribute_mapping`]).
+    pub attribute_mapping: Option<HashMap<String, String>>,
+    #[default = "ResourceAttributesMode::default()"]
+    pub resource_attributes_mode: ResourceAttributesMode,
+    pub link_type_classifier: Option<Arc<dyn SpanLinkTypeClassifier>>,
+    #[default = "ArrayAttributeEncoding::default()"]
+    pub array_attribute_encoding: ArrayAttributeEncoding,
+    /// Attribute keys (e.g. `db.statement` or `exception.message`) with string values
+    /// that are kept in full when they are over `max_attribute_value_len`:
+    /// the attribute is a truncated preview and the full value is added as annotations
+    /// split in chunks of `max_annotation_description_len`.
+    /// Chunks only use the time event slots left by the span events,
+    /// and the chunks over the limit are counted in `dropped_annotations_count`.
+    #[default = "HashSet::new()"]
+    pub spilled_attribute_keys: HashSet<String>,
+}
+
+/// Converts OpenTelemetry spans into Cloud Trace v2 spans.
+///
+/// This is the same mapping used by [`crate::GcpCloudTraceExporter`],
+/// so it can be reused to build custom pipelines.
+#[derive(Debug, Clone, Builder)]
+pub struct SpanConverter {
+    pub google_project_id: String,
+    pub resource: Option<Resource>,
+    #[default = "SpanConverterOptions::new()"]
+    pub options: SpanConverterOptions,
+}
+
+impl SpanConverter {
+    /// Span attribute for spans received from other systems (e.g. OTLP spans without the
+    /// is-remote span flags or Zipkin spans) telling whether the parent span is remote.
+    /// Boolean values override `SpanData::parent_span_is_remote`, and other values
+    /// (e.g. `unknown`) leave `same_process_as_parent_span` unset. It is not exported.
+    pub const PARENT_SPAN_IS_REMOTE_ATTRIBUTE: &'static str = "gcp.parent_span.is_remote";
+
+    /// Mapping from OpenTelemetry semantic conventions to the Cloud Trace well-known labels.
+    pub fn well_known_attribute_mapping() -> HashMap<String, String> {
+        [
+            ("http.host", "/http/host"),
+            ("server.address", "/http/host"),
+            ("http.method", "/http/method"),
+            ("http.request.method", "/http/method"),
+            ("http.target", "/http/path"),
+            ("url.path", "/http/path"),
+            ("http.url", "/http/url"),
+            ("url.full", "/http/url"),
+            ("http.user_agent", "/http/user_agent"),
+            ("user_agent.original", "/http/user_agent"),
+            ("http.status_code", "/http/status_code"),
+            ("http.response.status_code", "/http/status_code"),
+            ("http.route", "/http/route"),
+            ("http.request_content_length", "/http/request/size"),
+            ("http.request.body.size", "/http/request/size"),
+            ("http.response_content_length", "/http/response/size"),
+            ("http.response.body.size", "/http/response/size"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+    }
+
+    pub fn convert_batch(&self, batch: &[SpanData]) -> BatchWriteSpansRequest {
+        let mut spans: Vec<GcpSpan> = batch.iter().map(|span| self.convert_span(span)).collect();
+        Self::fill_child_span_counts(&mut spans);
+        BatchWriteSpansRequest {
+            name: format!("projects/{}", self.google_project_id),
+            spans,
+            ..BatchWriteSpansRequest::default()
+        }
+    }
+
+    /// Sets `child_span_count` for spans with direct children in the same batch.
+    /// Spans without children in the batch are left unset, since their children
+    /// may have been exported in other batches.
+    pub fn fill_child_span_counts(spans: &mut [GcpSpan]) {
+        let mut child_span_counts: HashMap<String, i32> = HashMap::new();
+        for span in spans.iter().filter(|span| !span.parent_span_id.is_empty()) {
+            // Span names are `projects/PROJECT/traces/TRACE_ID/spans/SPAN_ID`
+            if let Some((trace_name, _)) = span.name.rsplit_once('/') {
+                *child_span_counts
+                    .entry(format!("{trace_name}/{}", span.parent_span_id))
+                    .or_default() += 1;
+            }
+        }
+        if child_span_counts.is_empty() {
+            return;
+        }
+        for span in spans.iter_mut() {
+            if let Some(child_span_count) = child_span_counts.get(&span.name) {
+                span.child_span_count = Some(*child_span_count);
+            }
+        }
+    }
+
+    pub fn convert_span(&self, span: &SpanData) -> GcpSpan {
+        let limits = &self.options.limits;
+        GcpSpan {
+            name: format!(
+                "projects/{}/traces/{}/spans/{}",
+                self.google_project_id,
+                span.span_context.trace_id(),
+                span.span_context.span_id()
+            ),
+            span_id: span.span_context.span_id().to_string(),
+            parent_span_id: if span.parent_span_id != opentelemetry::trace::SpanId::INVALID {
+                span.parent_span_id.to_string()
+            } else {
+                "".to_string()
+            },
+            display_name: Some(Self::truncatable_string(
+                span.name.deref(),
+                limits.max_display_name_len,
+            )),
+            start_time: Some(prost_types::Timestamp::from(span.start_time)),
+            end_time: Some(prost_types::Timestamp::from(span.end_time)),
+            attributes: Some(self.convert_span_attrs(&span.attributes)),
+            time_events: Some(self.convert_time_events(span)),
+            links: Some(self.convert_links(span)),
+            status: Self::convert_status(span),
+            span_kind: Self::convert_span_kind(&span.span_kind).into(),
+            same_process_as_parent_span: Self::same_process_as_parent_span(span),
+            ..GcpSpan::default()
+        }
+    }
+
+    fn same_process_as_parent_span(span: &SpanData) -> Option<bool> {
+        if span.parent_span_id == opentelemetry::trace::SpanId::INVALID {
+            return None;
+        }
+        match span
+            .attributes
+            .iter()
+            .find(|kv| kv.key.as_str() == Self::PARENT_SPAN_IS_REMOTE_ATTRIBUTE)
+        {
+            Some(KeyValue {
+                value: opentelemetry::Value::Bool(parent_span_is_remote),
+                ..
+            }) => Some(!parent_span_is_remote),
+            Some(_) => None,
+            None => Some(!span.parent_span_is_remote),
+        }
+    }
+
+    pub fn truncatable_string(str: &str, max_len: usize) -> TruncatableString {
+        if str.len() > max_len {
+            let mut truncated_len = max_len;
+            while !str.is_char_boundary(truncated_len) {
+                truncated_len -= 1;
+            }
+
+            TruncatableString {
+                value: str[..truncated_len].to_string(),
+                truncated_byte_count: (str.len() - truncated_len) as i32,
+            }
+        } else {
+            TruncatableString {
+                value: str.to_string(),
+                truncated_byte_count: 0,
+            }
+        }
+    }
+
+    fn resource_attributes(&self) -> Vec<KeyValue> {
+        match (&self.resource, &self.options.resource_attributes_mode) {
+            (_, ResourceAttributesMode::Ignore) | (None, _) => Vec::new(),
+            (Some(resource), _) => resource
+                .iter()
+                .map(|(k, v)| KeyValue::new(k.clone(), v.clone()))
+                .collect(),
+        }
+    }
+
+    fn convert_span_attrs(&self, attrs: &[KeyValue]) -> gspan::Attributes {
+        let attrs: Vec<&KeyValue> = attrs
+            .iter()
+            .filter(|kv| kv.key.as_str() != Self::PARENT_SPAN_IS_REMOTE_ATTRIBUTE)
+            .collect();
+        // Span attributes have priority over resource attributes with the same (mapped) key
+        let span_keys: HashSet<String> = attrs
+            .iter()
+            .map(|kv| self.convert_attr_key(kv.key.as_str()))
+            .collect();
+        let mut resource_attributes = self.resource_attributes();
+        resource_attributes
+            .retain(|kv| !span_keys.contains(&self.convert_attr_key(kv.key.as_str())));
+        let attrs = attrs.into_iter();
+        let all_attrs: Vec<&KeyValue> = match self.options.resource_attributes_mode {
+            ResourceAttributesMode::BeforeSpanAttributes => {
+                resource_attributes.iter().chain(attrs).collect()
+            }
+            _ => attrs.chain(&resource_attributes).collect(),
+        };
+        self.convert_attrs(all_attrs, 0)
+    }
+
+    fn convert_attrs<'a, I>(&self, attrs: I, dropped_count: u32) -> gspan::Attributes
+    where
+        I: IntoIterator<Item = &'a KeyValue>,
+    {
+        let max_attrs = self.options.limits.max_attributes;
+        // Attributes mapped to the same key (e.g. `http.method` and `/http/method`) are deduplicated
+        // before the limit is applied, with later values replacing earlier ones
+        let mut converted_attrs: Vec<(String, GcpAttributeValue)> = Vec::new();
+        let mut key_positions: HashMap<String, usize> = HashMap::new();
+        for attribute in attrs {
+            for (key, value) in self.convert_attr(
+                self.convert_attr_key(attribute.key.as_str()),
+                &attribute.value,
+            ) {
+                match key_positions.get(&key) {
+                    Some(&position) => converted_attrs[position].1 = value,
+                    None => {
+                        key_positions.insert(key.clone(), converted_attrs.len());
+                        converted_attrs.push((key, value));
+                    }
+                }
+            }
+        }
+        let attrs_len = converted_attrs.len();
+        gspan::Attributes {
+            attribute_map: converted_attrs.into_iter().take(max_attrs).collect(),
+            dropped_attributes_count: (dropped_count as usize + attrs_len.saturating_sub(max_attrs))
+                as i32,
+        }
+    }
+
+    fn convert_attr(
+        &self,
+        key: String,
+        value: &opentelemetry::Value,
+    ) -> Vec<(String, GcpAttributeValue)> {
+        match (value, &self.options.array_attribute_encoding) {
+            (opentelemetry::Value::Array(arr), ArrayAttributeEncoding::Expanded) => {
+                let values: Vec<opentelemetry::Value> = match arr {
+                    opentelemetry::Array::Bool(values) => {
+                        values.iter().map(|value| (*value).into()).collect()
+                    }
+                    opentelemetry::Array::I64(values) => {
+                        values.iter().map(|value| (*value).into()).collect()
+                    }
+                    opentelemetry::Array::F64(values) => {
+                        values.iter().map(|value| (*value).into()).collect()
+                    }
+                    opentelemetry::Array::String(values) => values
+                        .iter()
+                        .map(|value| opentelemetry::Value::String(value.clone()))
+                        .collect(),
+                    other => vec![opentelemetry::Value::String(other.to_string().into())],
+                };
+                values
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, value)| {
+                        (format!("{key}.{idx}"), self.convert_span_attr_value(value))
+                    })
+                    .collect()
+            }
+            _ => vec![(key, self.convert_span_attr_value(value))],
+        }
+    }
+
+    fn convert_attr_key(&self, key: &str) -> String {
+        self.options
+            .attribute_mapping
+            .as_ref()
+            .and_then(|mapping| mapping.get(key))
+            .cloned()
+            .unwrap_or_else(|| key.to_string())
+    }
+
+    fn convert_span_attr_value(&self, attr_value: &opentelemetry::Value) -> GcpAttributeValue {
+        let max_str_len = self.options.limits.max_attribute_value_len;
+        let string_value = |str: &str| {
+            gcp_attribute_value::Value::StringValue(Self::truncatable_string(str, max_str_len))
+        };
+        GcpAttributeValue {
+            value: Some(match attr_value {
+                opentelemetry::Value::I64(value) => gcp_attribute_value::Value::IntValue(*value),
+                // Shortest representation keeping full precision (e.g. 0.0042)
+                opentelemetry::Value::F64(value) => string_value(&value.to_string()),
+                opentelemetry::Value::String(value) => string_value(value.as_str()),
+                opentelemetry::Value::Bool(value) => gcp_attribute_value::Value::BoolValue(*value),
+                opentelemetry::Value::Array(arr) => {
+                    string_value(&self.convert_array_attr_value(arr))
+                }
+                other => string_value(&other.to_string()),
+            }),
+        }
+    }
+
+    fn convert_array_attr_value(&self, arr: &opentelemetry::Array) -> String {
+        fn join<T>(values: &[T], delimiter: &str, to_string: impl Fn(&T) -> String) -> String {
+            values
+                .iter()
+                .map(to_string)
+                .collect::<Vec<String>>()
+                .join(delimiter)
+        }
+
+        match &self.options.array_attribute_encoding {
+            ArrayAttributeEncoding::Delimited(delimiter) => match arr {
+                opentelemetry::Array::Bool(values) => join(values, delimiter, bool::to_string),
+                opentelemetry::Array::I64(values) => join(values, delimiter, i64::to_string),
+                opentelemetry::Array::F64(values) => join(values, delimiter, f64::to_string),
+                opentelemetry::Array::String(values) => {
+                    join(values, delimiter, |value| value.as_str().to_string())
+                }
+                other => other.to_string(),
+            },
+            // Expanded arrays are split in `convert_attr`, so JSON is only a fallback for them
+            ArrayAttributeEncoding::Json | ArrayAttributeEncoding::Expanded => {
+                let json_values = match arr {
+                    opentelemetry::Array::Bool(values) => join(values, ",", bool::to_string),
+                    opentelemetry::Array::I64(values) => join(values, ",", i64::to_string),
+                    // JSON has no NaN or infinity
+                    opentelemetry::Array::F64(values) => join(values, ",", |value| {
+                        if value.is_finite() {
+                            value.to_string()
+                        } else {
+                            "null".to_string()
+                        }
+                    }),
+                    opentelemetry::Array::String(values) => {
+                        join(values, ",", |value| Self::json_string(value.as_str()))
+                    }
+                    other => return other.to_string(),
+                };
+                format!("[{json_values}]")
+            }
+        }
+    }
+
+    fn json_string(str: &str) -> String {
+        let mut json = String::with_capacity(str.len() + 2);
+        json.push('"');
+        for c in str.chars() {
+            match c {
+                '"' => json.push_str("\\\""),
+                '\\' => json.push_str("\\\\"),
+                '\n' => json.push_str("\\n"),
+                '\r' => json.push_str("\\r"),
+                '\t' => json.push_str("\\t"),
+                c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+                c => json.push(c),
+            }
+        }
+        json.push('"');
+        json
+    }
+
+    fn convert_time_events(&self, span: &SpanData) -> gspan::TimeEvents {
+        let max_events = self.options.limits.max_time_events;
+        let events = &span.events;
+
+        let mut time_events: Vec<gspan::TimeEvent> = events
+            .iter()
+            .take(max_events)
+            .map(|event| self.convert_time_event(event))
+            .collect();
+        let dropped_events_count =
+            events.dropped_count as usize + events.len().saturating_sub(max_events);
+
+        // Spilled attributes only use the slots left by the span events,
+        // so long values can't push the events out
+        let spilled_attrs: Vec<gspan::TimeEvent> =
+            self.convert_spilled_attrs(&span.attributes, span.start_time)
+                .into_iter()
+                .chain(events.iter().take(max_events).flat_map(|event| {
+                    self.convert_spilled_attrs(&event.attributes, event.timestamp)
+                }))
+                .collect();
+        let spilled_attrs_len = spilled_attrs.len();
+        let spilled_attrs_slots = max_events - time_events.len();
+        time_events.extend(spilled_attrs.into_iter().take(spilled_attrs_slots));
+
+        gspan::TimeEvents {
+            time_event: time_events,
+            dropped_annotations_count: spilled_attrs_len.saturating_sub(spilled_attrs_slots) as i32,
+            dropped_message_events_count: dropped_events_count as i32,
+        }
+    }
+
+    fn convert_time_event(&self, event: &opentelemetry::trace::Event) -> gspan::TimeEvent {
+        gspan::TimeEvent {
+            time: Some(prost_types::Timestamp::from(event.timestamp)),
+            value: Some(self.convert_time_event_value(event)),
+            ..gspan::TimeEvent::default()
+        }
+    }
+
+    fn convert_spilled_attrs(
+        &self,
+        attrs: &[KeyValue],
+        time: std::time::SystemTime,
+    ) -> Vec<gspan::TimeEvent> {
+        if self.options.spilled_attribute_keys.is_empty() {
+            return Vec::new();
+        }
+        let limits = &self.options.limits;
+        attrs
+            .iter()
+            .filter(|kv| {
+                self.options
+                    .spilled_attribute_keys
+                    .contains(kv.key.as_str())
+            })
+            .filter_map(|kv| match &kv.value {
+                opentelemetry::Value::String(value)
+                    if value.as_str().len() > limits.max_attribute_value_len =>
+                {
+                    Some((kv.key.as_str(), value.as_str()))
+                }
+                _ => None,
+            })
+            .flat_map(|(key, value)| {
+                let chunks = Self::split_chunks(value, limits.max_annotation_description_len);
+                let chunks_len = chunks.len();
+                chunks
+                    .into_iter()
+                    .enumerate()
+                    .map(move |(idx, chunk)| gspan::TimeEvent {
+                        time: Some(prost_types::Timestamp::from(time)),
+                        value: Some(gspan::time_event::Value::Annotation(
+                            gspan::time_event::Annotation {
+                                description: Some(Self::truncatable_string(
+                                    chunk,
+                                    limits.max_annotation_description_len,
+                                )),
+                                attributes: Some(self.convert_attrs(
+                                    &[
+                                        KeyValue::new("attribute.key", key.to_string()),
+                                        KeyValue::new(
+                                            "attribute.chunk",
+                                            format!("{}/{chunks_len}", idx + 1),
+                                        ),
+                                    ],
+                                    0,
+                                )),
+                            },
+                        )),
+                        ..gspan::TimeEvent::default()
+                    })
+            })
+            .collect()
+    }
+
+    fn split_chunks(str: &str, max_len: usize) -> Vec<&str> {
+        let mut chunks = Vec::new();
+        let mut rest = str;
+        while !rest.is_empty() {
+            let mut chunk_len = max_len.min(rest.len());
+            while !rest.is_char_boundary(chunk_len) {
+                chunk_len -= 1;
+            }
+            if chunk_len == 0 {
+                // The limit is smaller than a single character
+                chunk_len = rest
+                    .chars()
+                    .next()
+                    .map(char::len_utf8)
+                    .unwrap_or(rest.len());
+            }
+            let (chunk, tail) = rest.split_at(chunk_len);
+            chunks.push(chunk);
+            rest = tail;
+        }
+        chunks
+    }
+
+    fn convert_time_event_value(
+        &self,
+        event_value: &opentelemetry::trace::Event,
+    ) -> gspan::time_event::Value {
+        gspan::time_event::Value::Annotation(gspan::time_event::Annotation {
+            description: Some(Self::truncatable_string(
+                event_value.name.deref(),
+                self.options.limits.max_annotation_description_len,
+            )),
+            attributes: Some(self.convert_attrs(
+                &event_value.attributes,
+                event_value.dropped_attributes_count,
+            )),
+        })
+    }
+
+    fn convert_links(&self, span: &SpanData) -> gspan::Links {
+        let max_links = self.options.limits.max_links;
+        let links = &span.links;
+
+        gspan::Links {
+            link: links
+                .iter()
+                .take(max_links)
+                .map(|link| self.convert_link(span, link))
+                .collect(),
+            dropped_links_count: (links.dropped_count as usize
+                + links.len().saturating_sub(max_links)) as i32,
+            ..gspan::Links::default()
+        }
+    }
+
+    fn convert_link(&self, span: &SpanData, link: &opentelemetry::trace::Link) -> gspan::Link {
+        let link_type = link
+            .attributes
+            .iter()
+            .find(|kv| kv.key.as_str() == SpanLinkType::LINK_TYPE_ATTRIBUTE)
+            .and_then(|kv| SpanLinkType::from_attribute_value(&kv.value.as_str()))
+            .or_else(|| {
+                self.options
+                    .link_type_classifier
+                    .as_ref()
+                    .map(|classifier| classifier.classify(span, link))
+            })
+            .unwrap_or_default();
+
+        let attrs: Vec<&KeyValue> = link
+            .attributes
+            .iter()
+            .filter(|kv| kv.key.as_str() != SpanLinkType::LINK_TYPE_ATTRIBUTE)
+            .collect();
+
+        gspan::Link {
+            trace_id: link.span_context.trace_id().to_string(),
+            span_id: link.span_context.span_id().to_string(),
+            r#type: match link_type {
+                SpanLinkType::Unspecified => gspan::link::Type::Unspecified,
+                SpanLinkType::ChildLinkedSpan => gspan::link::Type::ChildLinkedSpan,
+                SpanLinkType::ParentLinkedSpan => gspan::link::Type::ParentLinkedSpan,
+            }
+            .into(),
+            attributes: Some(self.convert_attrs(attrs, link.dropped_attributes_count)),
+        }
+    }
+
+    fn convert_status(span: &SpanData) -> Option<GcpStatus> {
+        // gRPC status codes are the same as google.rpc.Code
+        let grpc_status_code = span
+            .attributes
+            .iter()
+            .find(|kv| kv.key.as_str() == "rpc.grpc.status_code")
+            .and_then(|kv| match kv.value {
+                opentelemetry::Value::I64(code) => i32::try_from(code).ok(),
+                _ => None,
+            })
+            .filter(|code| GcpStatusCode::try_from(*code).is_ok());
+
+        match span.status {
+            // OK codes aren't needed without a status, so unset statuses can be restored
+            opentelemetry::trace::Status::Unset => grpc_status_code
+                .filter(|code| *code != GcpStatusCode::Ok as i32)
+                .map(|code| GcpStatus {
+                    code,
+                    ..GcpStatus::default()
+                }),
+            opentelemetry::trace::Status::Ok => Some(GcpStatus {
+                code: GcpStatusCode::Ok.into(),
+                ..GcpStatus::default()
+            }),
+            opentelemetry::trace::Status::Error { ref description } => Some(GcpStatus {
+                code: grpc_status_code
+                    .filter(|code| *code != GcpStatusCode::Ok as i32)
+                    .unwrap_or(GcpStatusCode::Unavailable.into()),
+                message: description.to_string(),
+                ..GcpStatus::default()
+            }),
+        }
+    }
+
+    fn convert_span_kind(span_kind: &opentelemetry::trace::SpanKind) -> gspan::SpanKind {
+        match span_kind {
+            opentelemetry::trace::SpanKind::Client => gspan::SpanKind::Client,
+            opentelemetry::trace::SpanKind::Server => gspan::SpanKind::Server,
+            opentelemetry::trace::SpanKind::Producer => gspan::SpanKind::Producer,
+            opentelemetry::trace::SpanKind::Consumer => gspan::SpanKind::Consumer,
+            opentelemetry::trace::SpanKind::Internal => gspan::SpanKind::Internal,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::{
+        Event, SpanContext, SpanId, SpanKind, Status, TraceFlags, TraceId, TraceState,
+    };
+    use opentelemetry::InstrumentationScope;
+    use opentelemetry_sdk::trace::{SpanEvents, SpanLinks};
+    use std::time::{Duration, SystemTime};
+
+    fn test_span(attributes: Vec<KeyValue>, events: Vec<Event>) -> SpanData {
+        test_child_span(SpanId::INVALID, false, attributes, events)
+    }
+
+    fn test_child_span(
+        parent_span_id: SpanId,
+        parent_span_is_remote: bool,
+        attributes: Vec<KeyValue>,
+        events: Vec<Event>,
+    ) -> SpanData {
+        let mut span_events = SpanEvents::default();
+        span_events.events = events;
+        SpanData {
+            span_context: SpanContext::new(
+                TraceId::from(1),
+                SpanId::from(2),
+                TraceFlags::SAMPLED,
+                false,
+                TraceState::default(),
+            ),
+            parent_span_id,
+            parent_span_is_remote,
+            span_kind: SpanKind::Internal,
+            name: "test".into(),
+            start_time: SystemTime::UNIX_EPOCH + Duration::from_secs(1),
+            end_time: SystemTime::UNIX_EPOCH + Duration::from_secs(2),
+            attributes,
+            dropped_attributes_count: 0,
+            events: span_events,
+            links: SpanLinks::default(),
+            status: Status::Unset,
+            instrumentation_scope: InstrumentationScope::builder("test").build(),
+        }
+    }
+
+    fn annotation_description(time_event: &gspan::TimeEvent) -> &str {
+        match &time_event.value {
+            Some(gspan::time_event::Value::Annotation(annotation)) => annotation
+                .description
+                .as_ref()
+                .map(|description| description.value.as_str())
+                .unwrap_or_default(),
+            _ => "",
+        }
+    }
+
+    #[test]
+    fn spilled_attributes_keep_span_events() {
+        let converter = SpanConverter::new("test-project".to_string()).with_options(
+            SpanConverterOptions::new()
+                .with_spilled_attribute_keys(HashSet::from(["db.statement".to_string()])),
+        );
+        let statement = "x".repeat(32 * 1024);
+        let span = test_span(
+            vec![KeyValue::new("db.statement", statement)],
+            vec![Event::new(
+                "exception",
+                SystemTime::UNIX_EPOCH + Duration::from_secs(2),
+                vec![],
+                0,
+            )],
+        );
+
+        let time_events = converter.convert_span(&span).time_events.unwrap();
+        assert_eq!(time_events.time_event.len(), 128);
+        assert_eq!(
+            annotation_description(&time_events.time_event[0]),
+            "exception"
+        );
+        assert_eq!(time_events.dropped_message_events_count, 0);
+        // 128 chunks of 256 bytes, one slot taken by the event
+        assert_eq!(time_events.dropped_annotations_count, 1);
+    }
+
+    #[test]
+    fn same_process_as_parent_span() {
+        let converter = SpanConverter::new("test-project".to_string());
+        let same_process =
+            |span: SpanData| converter.convert_span(&span).same_process_as_parent_span;
+
+        assert_eq!(same_process(test_span(vec![], vec![])), None);
+        assert_eq!(
+            same_process(test_child_span(SpanId::from(3), false, vec![], vec![])),
+            Some(true)
+        );
+        assert_eq!(
+            same_process(test_child_span(SpanId::from(3), true, vec![], vec![])),
+            Some(false)
+        );
+        assert_eq!(
+            same_process(test_child_span(
+                SpanId::from(3),
+                false,
+                vec![KeyValue::new(
+                    SpanConverter::PARENT_SPAN_IS_REMOTE_ATTRIBUTE,
+                    "unknown"
+                )],
+                vec![]
+            )),
+            None
+        );
+        assert_eq!(
+            same_process(test_child_span(
+                SpanId::from(3),
+                false,
+                vec![KeyValue::new(
+                    SpanConverter::PARENT_SPAN_IS_REMOTE_ATTRIBUTE,
+                    true
+                )],
+                vec![]
+            )),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn parent_span_is_remote_attribute_is_not_exported() {
+        let converter = SpanConverter::new("test-project".to_string());
+        let span = test_child_span(
+            SpanId::from(3),
+            false,
+            vec![
+                KeyValue::new(SpanConverter::PARENT_SPAN_IS_REMOTE_ATTRIBUTE, "unknown"),
+                KeyValue::new("key", "value"),
+            ],
+            vec![],
+        );
+        let attributes = converter.convert_span(&span).attributes.unwrap();
+        assert_eq!(attributes.attribute_map.len(), 1);
+        assert!(attributes.attribute_map.contains_key("key"));
+        assert_eq!(attributes.dropped_attributes_count, 0);
+    }
+
+    fn array<T>(values: Vec<T>) -> opentelemetry::Value
+    where
+        opentelemetry::Array: From<Vec<T>>,
+    {
+        opentelemetry::Value::Array(values.into())
+    }
+
+    fn converted_attrs(
+        encoding: ArrayAttributeEncoding,
+        attributes: Vec<KeyValue>,
+    ) -> gspan::Attributes {
+        SpanConverter::new("test-project".to_string())
+            .with_options(SpanConverterOptions::new().with_array_attribute_encoding(encoding))
+            .convert_span(&test_span(attributes, vec![]))
+            .attributes
+            .unwrap()
+    }
+
+    fn converted_value(encoding: ArrayAttributeEncoding, value: opentelemetry::Value) -> String {
+        let attributes = converted_attrs(encoding, vec![KeyValue::new("key", value)]);
+        match attributes.attribute_map["key"].value.as_ref().unwrap() {
+            gcp_attribute_value::Value::StringValue(str) => format!("string:{}", str.value),
+            gcp_attribute_value::Value::IntValue(value) => format!("int:{value}"),
+            gcp_attribute_value::Value::BoolValue(value) => format!("bool:{value}"),
+        }
+    }
+
+    #[test]
+    fn converts_scalar_values() {
+        let json = || ArrayAttributeEncoding::Json;
+        assert_eq!(converted_value(json(), 42i64.into()), "int:42");
+        assert_eq!(converted_value(json(), true.into()), "bool:true");
+        assert_eq!(converted_value(json(), "value".into()), "string:value");
+        assert_eq!(converted_value(json(), 0.0042.into()), "string:0.0042");
+        assert_eq!(converted_value(json(), 1.0.into()), "string:1");
+        assert_eq!(
+            converted_value(json(), 0.1f64.into()),
+            format!("string:{}", 0.1f64)
+        );
+        assert_eq!(converted_value(json(), f64::NAN.into()), "string:NaN");
+        assert_eq!(converted_value(json(), f64::INFINITY.into()), "string:inf");
+    }
+
+    #[test]
+    fn converts_json_arrays() {
+        let json = || ArrayAttributeEncoding::Json;
+        for value in [
+            array(vec![0.0042, f64::NAN, f64::INFINITY]),
+            array(vec![opentelemetry::StringValue::from("\"\\\n\u{1}")]),
+            array(Vec::<bool>::new()),
+        ] {
+            let converted = converted_value(json(), value);
+            let json_value = converted.strip_prefix("string:").unwrap();
+            assert!(
+                serde_json::from_str::<serde_json::Value>(json_value).is_ok(),
+                "invalid JSON {json_value}"
+            );
+        }
+
+        assert_eq!(
+            converted_value(json(), array(vec![true, false])),
+            "string:[true,false]"
+        );
+        assert_eq!(
+            converted_value(json(), array(vec![1i64, -2])),
+            "string:[1,-2]"
+        );
+        assert_eq!(
+            converted_value(
+                json(),
+                array(vec![0.0042, f64::NAN, f64::INFINITY, f64::NEG_INFINITY])
+            ),
+            "string:[0.0042,null,null,null]"
+        );
+        assert_eq!(
+            converted_value(
+                json(),
+                opentelemetry::Value::Array(
+                    vec![
+                        opentelemetry::StringValue::from("a\"b"),
+                        opentelemetry::StringValue::from("c\\d\n\u{1}"),
+                    ]
+                    .into()
+                )
+            ),
+            "string:[\"a\\\"b\",\"c\\\\d\\n\\u0001\"]"
+        );
+        assert_eq!(
+            converted_value(json(), array(Vec::<i64>::new())),
+            "string:[]"
+        );
+    }
+
+    #[test]
+    fn converts_delimited_arrays() {
+        let delimited = || ArrayAttributeEncoding::Delimited(",".to_string());
+        assert_eq!(
+            converted_value(delimited(), array(vec![true, false])),
+            "string:true,false"
+        );
+        assert_eq!(
+            converted_value(delimited(), array(vec![1i64, 2])),
+            "string:1,2"
+        );
+        assert_eq!(
+            converted_value(delimited(), array(vec![0.0042, 1.5])),
+            "string:0.0042,1.5"
+        );
+        assert_eq!(
+            converted_value(
+                delimited(),
+                opentelemetry::Value::Array(
+                    vec![
+                        opentelemetry::StringValue::from("a"),
+                        opentelemetry::StringValue::from("b"),
+                    ]
+                    .into()
+                )
+            ),
+            "string:a,b"
+        );
+        assert_eq!(
+            converted_value(delimited(), array(Vec::<bool>::new())),
+            "string:"
+        );
+    }
+
+    #[test]
+    fn converts_expanded_arrays() {
+        let attributes = converted_attrs(
+            ArrayAttributeEncoding::Expanded,
+            vec![
+                KeyValue::new("ints", array(vec![1i64, 2])),
+                KeyValue::new("bools", array(vec![true])),
+                KeyValue::new("floats", array(vec![0.0042])),
+            ],
+        );
+        let mut keys: Vec<&str> = attributes
+            .attribute_map
+            .keys()
+            .map(String::as_str)
+            .collect();
+        keys.sort_unstable();
+        assert_eq!(keys, vec!["bools.0", "floats.0", "ints.0", "ints.1"]);
+        assert_eq!(
+            attributes.attribute_map["ints.1"].value,
+            Some(gcp_attribute_value::Value::IntValue(2))
+        );
+        assert_eq!(
+            attributes.attribute_map["bools.0"].value,
+            Some(gcp_attribute_value::Value::BoolValue(true))
+        );
+        assert_eq!(
+            attributes.attribute_map["floats.0"].value,
+            Some(gcp_attribute_value::Value::StringValue(
+                SpanConverter::truncatable_string("0.0042", 256)
+            ))
+        );
+    }
+
+    #[test]
+    fn expanded_arrays_are_limited() {
+        let attributes = converted_attrs(
+            ArrayAttributeEncoding::Expanded,
+            vec![
+                KeyValue::new("first", "value"),
+                KeyValue::new("key", array((0..40i64).collect::<Vec<_>>())),
+            ],
+        );
+        assert_eq!(attributes.attribute_map.len(), 32);
+        assert_eq!(attributes.dropped_attributes_count, 9);
+        assert!(attributes.attribute_map.contains_key("first"));
+        assert!((0..31).all(|idx| attributes.attribute_map.contains_key(&format!("key.{idx}"))));
+        assert!(!attributes.attribute_map.contains_key("key.31"));
+    }
+    fn string_attribute(attributes: &gspan::Attributes, key: &str) -> Option<String> {
+        match attributes.attribute_map.get(key)?.value.as_ref()? {
+            gcp_attribute_value::Value::StringValue(value) => Some(value.value.clone()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn dedupes_mapped_attribute_keys_before_limits() {
+        let converter = SpanConverter::new("test-project".to_string())
+            .with_resource(
+                Resource::builder_empty()
+                    .with_attributes([
+                        KeyValue::new("http.host", "resource.example.com"),
+                        KeyValue::new("service.name", "test-service"),
+                    ])
+                    .build(),
+            )
+            .with_options(
+                SpanConverterOptions::new()
+                    .with_attribute_mapping(SpanConverter::well_known_attribute_mapping())
+                    .with_limits(SpanConverterLimits::new().with_max_attributes(3)),
+            );
+        let span = test_span(
+            vec![
+                KeyValue::new("http.method", "GET"),
+                KeyValue::new("/http/method", "POST"),
+                KeyValue::new("server.address", "span.example.com"),
+            ],
+            vec![],
+        );
+
+        let attributes = converter.convert_span(&span).attributes.unwrap();
+        // Colliding keys take one slot, leaving room for the resource attribute
+        assert_eq!(attributes.attribute_map.len(), 3);
+        assert_eq!(attributes.dropped_attributes_count, 0);
+        assert_eq!(
+            string_attribute(&attributes, "/http/method").as_deref(),
+            Some("POST")
+        );
+        // Span attributes have priority over resource attributes
+        assert_eq!(
+            string_attribute(&attributes, "/http/host").as_deref(),
+            Some("span.example.com")
+        );
+        assert_eq!(
+            string_attribute(&attributes, "service.name").as_deref(),
+            Some("test-service")
+        );
+
+        // Also with resource attributes first
+        let options = converter
+            .options
+            .clone()
+            .with_resource_attributes_mode(ResourceAttributesMode::BeforeSpanAttributes);
+        let converter = converter.with_options(options);
+        let attributes = converter.convert_span(&span).attributes.unwrap();
+        assert_eq!(
+            string_attribute(&attributes, "/http/host").as_deref(),
+            Some("span.example.com")
+        );
+        assert_eq!(attributes.dropped_attributes_count, 0);
+    }
+}
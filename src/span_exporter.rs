@@ -1,7 +1,13 @@
 use crate::google_trace_exporter_client::GcpCloudTraceExporterClient;
-use crate::TraceExportResult;
+use crate::transport::CloudTraceTransport;
+use crate::{
+    GcpCloudTraceCredentials, GcpCloudTraceExporterTransport, SpanConverter, SpanFilter,
+    SpanRedactor, SpanRenamer, TraceExportResult,
+};
 use futures::future::TryFutureExt;
 use futures::FutureExt;
+use opentelemetry::context::FutureExt as OtelContextFutureExt;
+use opentelemetry::Context;
 use opentelemetry_sdk::error::{OTelSdkError, OTelSdkResult};
 use opentelemetry_sdk::{
     trace::{SpanData, SpanExporter},
@@ -10,18 +16,141 @@ use opentelemetry_sdk::{
 use std::fmt::Formatter;
 use std::sync::Arc;
 
+#[derive(Clone)]
+enum GcpExportClient {
+    CloudTraceV2(Arc<GcpCloudTraceExporterClient>),
+    #[cfg(feature = "otlp-backend")]
+    Otlp(Arc<crate::otlp_exporter_client::GcpOtlpExporterClient>),
+}
+
+impl GcpExportClient {
+    async fn export_batch(&self, batch: Vec<SpanData>) -> TraceExportResult<()> {
+        match self {
+            GcpExportClient::CloudTraceV2(client) => client.export_batch(batch).await,
+            #[cfg(feature = "otlp-backend")]
+            GcpExportClient::Otlp(client) => client.export_batch(batch).await,
+        }
+    }
+}
+
 pub struct GcpCloudTraceExporter {
-    gcp_export_client: Arc<GcpCloudTraceExporterClient>,
+    gcp_export_client: GcpExportClient,
+    span_filter: Option<SpanFilter>,
+    span_redactor: Option<SpanRedactor>,
+    span_renamer: Option<SpanRenamer>,
 }
 
 impl GcpCloudTraceExporter {
     pub async fn new(google_project_id: &str, resource: Resource) -> TraceExportResult<Self> {
         Ok(Self {
-            gcp_export_client: Arc::new(
+            gcp_export_client: GcpExportClient::CloudTraceV2(Arc::new(
                 GcpCloudTraceExporterClient::new(google_project_id, resource).await?,
-            ),
+            )),
+            span_filter: None,
+            span_redactor: None,
+            span_renamer: None,
+        })
+    }
+
+    pub async fn with_transport_options(
+        converter: SpanConverter,
+        transport: &GcpCloudTraceExporterTransport,
+        api_url: &str,
+        credentials: &GcpCloudTraceCredentials,
+    ) -> TraceExportResult<Self> {
+        Ok(Self {
+            gcp_export_client: GcpExportClient::CloudTraceV2(Arc::new(
+                GcpCloudTraceExporterClient::with_transport_options(
+                    converter,
+                    transport,
+                    api_url,
+                    credentials,
+                )
+                .await?,
+            )),
+            span_filter: None,
+            span_redactor: None,
+            span_renamer: None,
         })
     }
+
+    /// Creates an exporter sending spans using the provided transport
+    /// instead of the default Cloud Trace client.
+    pub fn with_transport<T>(google_project_id: &str, resource: Resource, transport: T) -> Self
+    where
+        T: CloudTraceTransport + 'static,
+    {
+        Self::with_span_converter(
+            SpanConverter::new(google_project_id.to_string()).with_resource(resource),
+            transport,
+        )
+    }
+
+    /// Creates an exporter using the provided span converter and transport.
+    pub fn with_span_converter<T>(converter: SpanConverter, transport: T) -> Self
+    where
+        T: CloudTraceTransport + 'static,
+    {
+        Self {
+            gcp_export_client: GcpExportClient::CloudTraceV2(Arc::new(
+                GcpCloudTraceExporterClient::with_transport(converter, Arc::new(transport)),
+            )),
+            span_filter: None,
+            span_redactor: None,
+            span_renamer: None,
+        }
+    }
+
+    /// Creates an exporter sending spans using OTLP to the Google Telemetry API
+    /// instead of the Cloud Trace v2 API.
+    #[cfg(feature = "otlp-backend")]
+    pub async fn new_otlp(
+        google_project_id: &str,
+        resource: Resource,
+        api_url: &str,
+        quota_project_id: Option<&str>,
+        credentials: &GcpCloudTraceCredentials,
+    ) -> TraceExportResult<Self> {
+        Ok(Self {
+            gcp_export_client: GcpExportClient::Otlp(Arc::new(
+                crate::otlp_exporter_client::GcpOtlpExporterClient::new(
+                    google_project_id,
+                    resource,
+                    api_url,
+                    quota_project_id,
+                    credentials,
+                )
+                .await?,
+            )),
+            span_filter: None,
+            span_redactor: None,
+            span_renamer: None,
+        })
+    }
+
+    /// Drops spans matching the filter rules before they are converted and exported.
+    pub fn with_span_filter(self, span_filter: SpanFilter) -> Self {
+        Self {
+            span_filter: Some(span_filter),
+            ..self
+        }
+    }
+
+    /// Scrubs sensitive data from spans before they are converted and exported.
+    pub fn with_span_redactor(self, span_redactor: SpanRedactor) -> Self {
+        Self {
+            span_redactor: Some(span_redactor),
+            ..self
+        }
+    }
+
+    /// Renames spans before they are converted and exported.
+    pub fn with_span_renamer(self, span_renamer: SpanRenamer) -> Self {
+        Self {
+            span_renamer: Some(span_renamer),
+            ..self
+        }
+    }
 }
 
 impl std::fmt::Debug for GcpCloudTraceExporter {
@@ -36,12 +165,96 @@ impl SpanExporter for GcpCloudTraceExporter {
         batch: Vec<SpanData>,
     ) -> impl std::future::Future<Output = OTelSdkResult> + Send {
         let client = self.gcp_export_client.clone();
+        let batch = match &self.span_filter {
+            Some(span_filter) => span_filter.filter(batch),
+            None => batch,
+        };
+        let batch = match &self.span_renamer {
+            Some(span_renamer) => span_renamer.rename(batch),
+            None => batch,
+        };
+        let batch = match &self.span_redactor {
+            Some(span_redactor) => span_redactor.redact_batch(batch),
+            None => batch,
+        };
         async move {
             client
                 .export_batch(batch)
                 .map_err(|e| OTelSdkError::InternalFailure(e.to_string()))
                 .await
         }
+        // The gRPC and auth layers emit their own tracing spans and events while exporting.
+        // Running the export in a suppressed context prevents those from being recorded
+        // and exported again.
+        .with_context(Context::current().with_telemetry_suppressed())
         .boxed()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use gcloud_sdk::google::devtools::cloudtrace::v2::BatchWriteSpansRequest;
+    use opentelemetry::trace::TracerProvider;
+    use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider};
+    use std::sync::Mutex;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[derive(Clone, Default)]
+    struct RecordingTransport {
+        requests: Arc<Mutex<Vec<BatchWriteSpansRequest>>>,
+    }
+
+    #[async_trait]
+    impl CloudTraceTransport for RecordingTransport {
+        async fn batch_write_spans(
+            &self,
+            request: BatchWriteSpansRequest,
+        ) -> TraceExportResult<()> {
+            // Emulates the tracing spans and events of the gRPC and auth layers
+            let span = tracing::info_span!("batch_write_spans");
+            let _entered = span.enter();
+            tracing::info!("sending {} spans", request.spans.len());
+            tracing::info_span!("auth_token").in_scope(|| {});
+            self.requests.lock().unwrap().push(request);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn export_does_not_record_exporter_spans() {
+        const SPANS_COUNT: usize = 5;
+
+        let in_memory_exporter = InMemorySpanExporter::default();
+        let tracer_provider = SdkTracerProvider::builder()
+            .with_simple_exporter(in_memory_exporter.clone())
+            .build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(tracer_provider.tracer("test")));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        for i in 0..SPANS_COUNT {
+            tracing::info_span!("app_span", i).in_scope(|| {});
+        }
+        let batch = in_memory_exporter.get_finished_spans().unwrap();
+        assert_eq!(batch.len(), SPANS_COUNT);
+
+        let transport = RecordingTransport::default();
+        let exporter = GcpCloudTraceExporter::with_transport(
+            "test-project",
+            Resource::builder_empty().build(),
+            transport.clone(),
+        );
+        exporter.export(batch).await.unwrap();
+        tracer_provider.force_flush().unwrap();
+
+        let requests = transport.requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].spans.len(), SPANS_COUNT);
+
+        let exported_spans = in_memory_exporter.get_finished_spans().unwrap();
+        assert_eq!(exported_spans.len(), SPANS_COUNT);
+        assert!(exported_spans.iter().all(|span| span.name == "app_span"));
+    }
+}
@@ -0,0 +1,616 @@
+use opentelemetry::trace::{Span as _, SpanId, SpanKind, Status, TraceContextExt};
+use opentelemetry::Context;
+use opentelemetry_sdk::error::OTelSdkResult;
+use opentelemetry_sdk::trace::{Span, SpanData, SpanProcessor};
+use regex::Regex;
+use rsb_derive::*;
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// String matcher for span names and attribute values.
+#[derive(Debug, Clone)]
+pub enum SpanFilterMatch {
+    Equals(String),
+    Regex(Regex),
+}
+
+impl SpanFilterMatch {
+    pub fn matches(&self, value: &str) -> bool {
+        match self {
+            SpanFilterMatch::Equals(expected) => expected == value,
+            SpanFilterMatch::Regex(regex) => regex.is_match(value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SpanFilterStatus {
+    Unset,
+    Ok,
+    Error,
+}
+
+/// Rule matching spans to drop. All specified conditions need to match.
+#[derive(Debug, Clone, Builder)]
+pub struct SpanFilterRule {
+    pub name: Option<SpanFilterMatch>,
+    pub kind: Option<SpanKind>,
+    /// Attribute values are compared using their string representation.
+    #[default = "Vec::new()"]
+    pub attributes: Vec<(String, SpanFilterMatch)>,
+    pub status: Option<SpanFilterStatus>,
+    /// Also drops the descendants of matching spans.
+    #[default = "false"]
+    pub drop_subtree: bool,
+}
+
+impl SpanFilterRule {
+    pub fn matches(&self, span: &SpanData) -> bool {
+        self.name
+            .as_ref()
+            .is_none_or(|name| name.matches(&span.name))
+            && self
+                .kind
+                .as_ref()
+                .is_none_or(|kind| *kind == span.span_kind)
+            && self.status.as_ref().is_none_or(|status| {
+                *status
+                    == match span.status {
+                        Status::Unset => SpanFilterStatus::Unset,
+                        Status::Ok => SpanFilterStatus::Ok,
+                        Status::Error { .. } => SpanFilterStatus::Error,
+                    }
+            })
+            && self.attributes.iter().all(|(key, value_match)| {
+                span.attributes
+                    .iter()
+                    .find(|kv| kv.key.as_str() == key)
+                    .is_some_and(|kv| value_match.matches(&kv.value.as_str()))
+            })
+    }
+}
+
+#[derive(Debug, Default)]
+struct DroppedSubtrees {
+    span_ids: HashSet<SpanId>,
+    span_ids_order: VecDeque<SpanId>,
+}
+
+/// Drops spans matching any of the rules before they are converted and exported
+/// (e.g. health checks or noisy internal spans).
+///
+/// Descendants of spans matched by `drop_subtree` rules are dropped as well. Register the filter
+/// as a span processor (done by [`crate::GcpCloudTraceExporterBuilder`]) to decide it when spans start:
+/// spans matching a `drop_subtree` rule without a status condition and all their descendants are
+/// marked there, so children ending and exported before their parents are dropped too.
+/// Rules matching the status or attributes set after the start are checked at export, and only drop
+/// descendants exported in the same batch as their ancestor or after it.
+#[derive(Debug, Clone, Default)]
+pub struct SpanFilter {
+    rules: Vec<SpanFilterRule>,
+    dropped_subtrees: Arc<Mutex<DroppedSubtrees>>,
+}
+
+impl SpanFilter {
+    // Number of dropped span ids remembered to drop descendants exported in later batches
+    const MAX_DROPPED_SUBTREE_SPANS: usize = 10000;
+
+    pub fn new(rules: Vec<SpanFilterRule>) -> Self {
+        Self {
+            rules,
+            dropped_subtrees: Arc::new(Mutex::new(DroppedSubtrees::default())),
+        }
+    }
+
+    pub fn rules(&self) -> &[SpanFilterRule] {
+        &self.rules
+    }
+
+    /// Whether any rule drops the descendants of matching spans.
+    pub fn has_drop_subtree_rules(&self) -> bool {
+        self.rules.iter().any(|rule| rule.drop_subtree)
+    }
+
+    fn lock_dropped_subtrees(&self) -> std::sync::MutexGuard<'_, DroppedSubtrees> {
+        self.dropped_subtrees
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn filter(&self, batch: Vec<SpanData>) -> Vec<SpanData> {
+        if self.rules.is_empty() {
+            return batch;
+        }
+
+        let mut dropped_subtrees = self.lock_dropped_subtrees();
+
+        let mut kept: Vec<SpanData> = Vec::with_capacity(batch.len());
+        let mut new_subtree_roots: Vec<SpanId> = Vec::new();
+        for span in batch {
+            // Marked when the span started
+            if dropped_subtrees
+                .span_ids
+                .contains(&span.span_context.span_id())
+            {
+                continue;
+            }
+            match self.rules.iter().find(|rule| rule.matches(&span)) {
+                Some(rule) if rule.drop_subtree => {
+                    new_subtree_roots.push(span.span_context.span_id())
+                }
+                Some(_) => {}
+                None => kept.push(span),
+            }
+        }
+
+        for span_id in new_subtree_roots {
+            Self::remember_dropped(&mut dropped_subtrees, span_id);
+        }
+
+        if dropped_subtrees.span_ids.is_empty() {
+            return kept;
+        }
+
+        // Descendants can be at any depth and in any order in the batch
+        loop {
+            let (descendants, rest): (Vec<SpanData>, Vec<SpanData>) = kept
+                .into_iter()
+                .partition(|span| dropped_subtrees.span_ids.contains(&span.parent_span_id));
+            kept = rest;
+            if descendants.is_empty() {
+                break;
+            }
+            for span in descendants {
+                Self::remember_dropped(&mut dropped_subtrees, span.span_context.span_id());
+            }
+        }
+
+        kept
+    }
+
+    fn remember_dropped(dropped_subtrees: &mut DroppedSubtrees, span_id: SpanId) {
+        if dropped_subtrees.span_ids.insert(span_id) {
+            dropped_subtrees.span_ids_order.push_back(span_id);
+            while dropped_subtrees.span_ids_order.len() > Self::MAX_DROPPED_SUBTREE_SPANS {
+                if let Some(oldest_span_id) = dropped_subtrees.span_ids_order.pop_front() {
+                    dropped_subtrees.span_ids.remove(&oldest_span_id);
+                }
+            }
+        }
+    }
+}
+
+impl SpanProcessor for SpanFilter {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        if !self.has_drop_subtree_rules() {
+            return;
+        }
+        let span_id = span.span_context().span_id();
+
+        // The parent is the active span of the context the span starts with
+        if cx.has_active_span() {
+            let parent_span_id = cx.span().span_context().span_id();
+            let mut dropped_subtrees = self.lock_dropped_subtrees();
+            if dropped_subtrees.span_ids.contains(&parent_span_id) {
+                Self::remember_dropped(&mut dropped_subtrees, span_id);
+                return;
+            }
+        }
+
+        // The status is only known when the span ends
+        let start_rules: Vec<&SpanFilterRule> = self
+            .rules
+            .iter()
+            .filter(|rule| rule.drop_subtree && rule.status.is_none())
+            .collect();
+        if start_rules.is_empty() {
+            return;
+        }
+        // The SDK span has no accessors for its name, kind and attributes
+        let Some(span_data) = span.exported_data() else {
+            return;
+        };
+        if start_rules.iter().any(|rule| rule.matches(&span_data)) {
+            Self::remember_dropped(&mut self.lock_dropped_subtrees(), span_id);
+        }
+    }
+
+    fn on_end(&self, _span: SpanData) {}
+
+    fn force_flush(&self) -> OTelSdkResult {
+        Ok(())
+    }
+
+    fn shutdown_with_timeout(&self, _timeout: Duration) -> OTelSdkResult {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "span-filters-config")]
+mod config {
+    use super::*;
+    use crate::errors::{GcloudTraceError, GcloudTraceSystemError};
+    use crate::TraceExportResult;
+    use serde::Deserialize;
+    use std::collections::BTreeMap;
+    use std::path::Path;
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct SpanFilterConfig {
+        #[serde(default)]
+        rules: Vec<SpanFilterRuleConfig>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct SpanFilterRuleConfig {
+        name: Option<String>,
+        name_regex: Option<String>,
+        kind: Option<String>,
+        status: Option<String>,
+        #[serde(default)]
+        attributes: BTreeMap<String, String>,
+        #[serde(default)]
+        attribute_regexes: BTreeMap<String, String>,
+        #[serde(default)]
+        drop_subtree: bool,
+    }
+
+    fn config_error(message: String) -> GcloudTraceError {
+        GcloudTraceError::SystemError(GcloudTraceSystemError::new(message))
+    }
+
+    fn parse_regex(regex: &str) -> TraceExportResult<Regex> {
+        Regex::new(regex)
+            .map_err(|e| config_error(format!("Invalid span filter regex {regex}: {e}")))
+    }
+
+    impl SpanFilterRuleConfig {
+        fn into_rule(self) -> TraceExportResult<SpanFilterRule> {
+            let name = match (self.name, self.name_regex) {
+                (Some(_), Some(_)) => {
+                    return Err(config_error(
+                        "Span filter rule can't have both name and name_regex".to_string(),
+                    ))
+                }
+                (Some(name), None) => Some(SpanFilterMatch::Equals(name)),
+                (None, Some(name_regex)) => Some(SpanFilterMatch::Regex(parse_regex(&name_regex)?)),
+                (None, None) => None,
+            };
+
+            let kind = self
+                .kind
+                .map(|kind| match kind.to_lowercase().as_str() {
+                    "client" => Ok(SpanKind::Client),
+                    "server" => Ok(SpanKind::Server),
+                    "producer" => Ok(SpanKind::Producer),
+                    "consumer" => Ok(SpanKind::Consumer),
+                    "internal" => Ok(SpanKind::Internal),
+                    _ => Err(config_error(format!("Invalid span filter kind: {kind}"))),
+                })
+                .transpose()?;
+
+            let status = self
+                .status
+                .map(|status| match status.to_lowercase().as_str() {
+                    "unset" => Ok(SpanFilterStatus::Unset),
+                    "ok" => Ok(SpanFilterStatus::Ok),
+                    "error" => Ok(SpanFilterStatus::Error),
+                    _ => Err(config_error(format!(
+                        "Invalid span filter status: {status}"
+                    ))),
+                })
+                .transpose()?;
+
+            let mut attributes: Vec<(String, SpanFilterMatch)> = self
+                .attributes
+                .into_iter()
+                .map(|(key, value)| (key, SpanFilterMatch::Equals(value)))
+                .collect();
+            for (key, regex) in self.attribute_regexes {
+                attributes.push((key, SpanFilterMatch::Regex(parse_regex(&regex)?)));
+            }
+
+            Ok(SpanFilterRule {
+                name,
+                kind,
+                attributes,
+                status,
+                drop_subtree: self.drop_subtree,
+            })
+        }
+    }
+
+    impl SpanFilterConfig {
+        fn into_filter(self) -> TraceExportResult<SpanFilter> {
+            Ok(SpanFilter::new(
+                self.rules
+                    .into_iter()
+                    .map(SpanFilterRuleConfig::into_rule)
+                    .collect::<TraceExportResult<_>>()?,
+            ))
+        }
+    }
+
+    impl SpanFilter {
+        /// Loads rules from TOML:
+        /// ```toml
+        /// [[rules]]
+        /// name_regex = "^GET /health"
+        /// kind = "server"
+        /// drop_subtree = true
+        ///
+        /// [[rules]]
+        /// status = "ok"
+        /// attributes = { "db.system" = "redis" }
+        /// attribute_regexes = { "db.statement" = "^PING" }
+        /// ```
+        pub fn from_toml_str(toml: &str) -> TraceExportResult<Self> {
+            toml::from_str::<SpanFilterConfig>(toml)
+                .map_err(|e| config_error(format!("Invalid span filter TOML: {e}")))?
+                .into_filter()
+        }
+
+        /// Loads rules from YAML using the same structure as [`SpanFilter::from_toml_str`].
+        pub fn from_yaml_str(yaml: &str) -> TraceExportResult<Self> {
+            serde_yaml::from_str::<SpanFilterConfig>(yaml)
+                .map_err(|e| config_error(format!("Invalid span filter YAML: {e}")))?
+                .into_filter()
+        }
+
+        /// Loads rules from a `.toml`, `.yaml` or `.yml` file.
+        pub fn from_file(path: impl AsRef<Path>) -> TraceExportResult<Self> {
+            let path = path.as_ref();
+            let content = std::fs::read_to_string(path).map_err(|e| {
+                config_error(format!(
+                    "Failed to read span filter file {}: {e}",
+                    path.display()
+                ))
+            })?;
+            match path.extension().and_then(|ext| ext.to_str()) {
+                Some("toml") => Self::from_toml_str(&content),
+                Some("yaml") | Some("yml") => Self::from_yaml_str(&content),
+                _ => Err(config_error(format!(
+                    "Unknown span filter file format: {}. Expected .toml, .yaml or .yml",
+                    path.display()
+                ))),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::{Tracer, TracerProvider};
+    use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider};
+
+    fn health_check_filter(status: Option<SpanFilterStatus>) -> SpanFilter {
+        SpanFilter::new(vec![SpanFilterRule::new()
+            .with_name(SpanFilterMatch::Equals("GET /health".to_string()))
+            .opt_status(status)
+            .with_drop_subtree(true)])
+    }
+
+    // Runs a health check trace and an unrelated trace, exporting each span in its own batch
+    fn export_separately(span_filter: &SpanFilter) -> Vec<String> {
+        let in_memory_exporter = InMemorySpanExporter::default();
+        let tracer_provider = SdkTracerProvider::builder()
+            .with_span_processor(span_filter.clone())
+            .with_simple_exporter(in_memory_exporter.clone())
+            .build();
+        let tracer = tracer_provider.tracer("test");
+
+        tracer.in_span("GET /health", |_| {
+            tracer.in_span("db_query", |_| {
+                tracer.in_span("db_connect", |_| {});
+            });
+        });
+        tracer.in_span("GET /users", |_| {
+            tracer.in_span("db_query", |_| {});
+        });
+
+        // Children end first
+        in_memory_exporter
+            .get_finished_spans()
+            .unwrap()
+            .into_iter()
+            .flat_map(|span| span_filter.filter(vec![span]))
+            .map(|span| span.name.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn drops_subtrees_exported_before_their_roots() {
+        assert_eq!(
+            export_separately(&health_check_filter(None)),
+            vec!["db_query", "GET /users"]
+        );
+    }
+
+    #[test]
+    fn drops_only_later_descendants_for_status_rules() {
+        // The status is only known at export, after the children were exported
+        assert_eq!(
+            export_separately(&health_check_filter(Some(SpanFilterStatus::Unset))),
+            vec!["db_connect", "db_query", "db_query", "GET /users"]
+        );
+    }
+
+    #[test]
+    fn drops_subtrees_in_the_same_batch() {
+        let span_filter = health_check_filter(Some(SpanFilterStatus::Unset));
+        let in_memory_exporter = InMemorySpanExporter::default();
+        let tracer_provider = SdkTracerProvider::builder()
+            .with_simple_exporter(in_memory_exporter.clone())
+            .build();
+        let tracer = tracer_provider.tracer("test");
+
+        tracer.in_span("GET /health", |_| {
+            tracer.in_span("db_query", |_| {
+                tracer.in_span("db_connect", |_| {});
+            });
+        });
+        tracer.in_span("GET /users", |_| {});
+
+        let kept = span_filter.filter(in_memory_exporter.get_finished_spans().unwrap());
+        assert_eq!(
+            kept.iter()
+                .map(|span| span.name.as_ref())
+                .collect::<Vec<_>>(),
+            vec!["GET /users"]
+        );
+    }
+    #[cfg(feature = "span-filters-config")]
+    mod config {
+        use super::*;
+        use crate::errors::GcloudTraceError;
+        use crate::TraceExportResult;
+
+        const TOML_RULES: &str = r#"
+            [[rules]]
+            name_regex = "^GET /health"
+            kind = "server"
+            drop_subtree = true
+
+            [[rules]]
+            name = "cache_get"
+            status = "ok"
+            attributes = { "db.system" = "redis" }
+            attribute_regexes = { "db.statement" = "^PING" }
+        "#;
+
+        const YAML_RULES: &str = r#"
+rules:
+  - name_regex: "^GET /health"
+    kind: server
+    drop_subtree: true
+  - name: cache_get
+    status: ok
+    attributes:
+      db.system: redis
+    attribute_regexes:
+      db.statement: "^PING"
+"#;
+
+        fn config_error(result: TraceExportResult<SpanFilter>) -> String {
+            match result {
+                Err(GcloudTraceError::SystemError(err)) => err.message,
+                Err(err) => panic!("unexpected error: {err:?}"),
+                Ok(_) => panic!("expected an error"),
+            }
+        }
+
+        fn assert_parsed_rules(span_filter: &SpanFilter) {
+            let rules = span_filter.rules();
+            assert_eq!(rules.len(), 2);
+
+            let name_regex = match &rules[0].name {
+                Some(SpanFilterMatch::Regex(regex)) => regex.as_str(),
+                other => panic!("unexpected name match: {other:?}"),
+            };
+            assert_eq!(name_regex, "^GET /health");
+            assert_eq!(rules[0].kind, Some(SpanKind::Server));
+            assert_eq!(rules[0].status, None);
+            assert!(rules[0].attributes.is_empty());
+            assert!(rules[0].drop_subtree);
+
+            assert!(
+                matches!(&rules[1].name, Some(SpanFilterMatch::Equals(name)) if name == "cache_get")
+            );
+            assert_eq!(rules[1].kind, None);
+            assert_eq!(rules[1].status, Some(SpanFilterStatus::Ok));
+            assert!(!rules[1].drop_subtree);
+            let attributes: Vec<(&str, bool)> = rules[1]
+                .attributes
+                .iter()
+                .map(|(key, value_match)| {
+                    (
+                        key.as_str(),
+                        matches!(value_match, SpanFilterMatch::Regex(_)),
+                    )
+                })
+                .collect();
+            assert_eq!(
+                attributes,
+                vec![("db.system", false), ("db.statement", true)]
+            );
+        }
+
+        #[test]
+        fn parses_toml() {
+            assert_parsed_rules(&SpanFilter::from_toml_str(TOML_RULES).unwrap());
+            assert!(SpanFilter::from_toml_str("").unwrap().rules().is_empty());
+        }
+
+        #[test]
+        fn parses_yaml() {
+            assert_parsed_rules(&SpanFilter::from_yaml_str(YAML_RULES).unwrap());
+        }
+
+        #[test]
+        fn rejects_invalid_rules() {
+            for (toml, expected) in [
+                (
+                    "[[rules]]\nname = \"a\"\nname_regex = \"b\"",
+                    "Span filter rule can't have both name and name_regex",
+                ),
+                (
+                    "[[rules]]\nkind = \"background\"",
+                    "Invalid span filter kind: background",
+                ),
+                (
+                    "[[rules]]\nstatus = \"failed\"",
+                    "Invalid span filter status: failed",
+                ),
+                (
+                    "[[rules]]\nname_regex = \"(\"",
+                    "Invalid span filter regex (",
+                ),
+                (
+                    "[[rules]]\nattribute_regexes = { \"db.statement\" = \"[\" }",
+                    "Invalid span filter regex [",
+                ),
+                ("[[rules]]\nspan_name = \"a\"", "Invalid span filter TOML"),
+            ] {
+                let message = config_error(SpanFilter::from_toml_str(toml));
+                assert!(message.starts_with(expected), "{toml}: {message}");
+            }
+
+            let message = config_error(SpanFilter::from_yaml_str("rules:\n  - kind: [server]"));
+            assert!(message.starts_with("Invalid span filter YAML"), "{message}");
+        }
+
+        #[test]
+        fn loads_files_by_extension() {
+            let dir = std::env::temp_dir().join(format!(
+                "opentelemetry-gcloud-trace-span-filter-{}",
+                std::process::id()
+            ));
+            std::fs::create_dir_all(&dir).unwrap();
+            for (file_name, content) in [
+                ("rules.toml", TOML_RULES),
+                ("rules.yaml", YAML_RULES),
+                ("rules.yml", YAML_RULES),
+            ] {
+                let path = dir.join(file_name);
+                std::fs::write(&path, content).unwrap();
+                assert_parsed_rules(&SpanFilter::from_file(&path).unwrap());
+            }
+
+            let path = dir.join("rules.json");
+            std::fs::write(&path, "{}").unwrap();
+            assert!(config_error(SpanFilter::from_file(&path))
+                .starts_with("Unknown span filter file format"));
+
+            assert!(
+                config_error(SpanFilter::from_file(dir.join("missing.toml")))
+                    .starts_with("Failed to read span filter file")
+            );
+
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use opentelemetry_sdk::trace::SpanData;
+use regex::Regex;
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+/// Rule changing span names before they are converted to Cloud Trace display names.
+#[derive(Debug, Clone)]
+pub enum SpanRenameRule {
+    /// Name built from span attributes, e.g. `{http.request.method} {http.route}`.
+    /// `{span.name}` is the current name. The rule is skipped when any of the attributes is missing.
+    Template(String),
+    /// Regex replacement in the name (e.g. `/users/\d+` to `/users/{id}`),
+    /// using the `regex` crate replacement syntax.
+    Regex { regex: Regex, replacement: String },
+}
+
+impl SpanRenameRule {
+    fn apply<'a>(&self, name: &'a str, span: &SpanData) -> Option<Cow<'a, str>> {
+        match self {
+            SpanRenameRule::Template(template) => {
+                Self::render_template(template, name, span).map(Cow::Owned)
+            }
+            SpanRenameRule::Regex { regex, replacement } => {
+                Some(regex.replace_all(name, replacement.as_str()))
+            }
+        }
+    }
+
+    fn render_template(template: &str, name: &str, span: &SpanData) -> Option<String> {
+        let mut rendered = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..].find('}')? + start;
+            rendered.push_str(&rest[..start]);
+            let key = &rest[start + 1..end];
+            if key == "span.name" {
+                rendered.push_str(name);
+            } else {
+                let value = span
+                    .attributes
+                    .iter()
+                    .find(|kv| kv.key.as_str() == key)?
+                    .value
+                    .as_str();
+                rendered.push_str(&value);
+            }
+            rest = &rest[end + 1..];
+        }
+        rendered.push_str(rest);
+        Some(rendered)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct CardinalityLimit {
+    max_names: usize,
+    fallback_name: String,
+}
+
+/// Renames spans before they are converted and exported, so display names have a low cardinality
+/// (e.g. spans named after raw URLs or SQL statements) and Cloud Trace latency analysis stays useful.
+///
+/// Rules are applied in order, each to the result of the previous one.
+/// With a cardinality limit, names beyond the limit of unique names seen
+/// by this renamer are replaced with the fallback name.
+#[derive(Debug, Clone, Default)]
+pub struct SpanRenamer {
+    rules: Vec<SpanRenameRule>,
+    cardinality_limit: Option<CardinalityLimit>,
+    seen_names: Arc<Mutex<HashSet<String>>>,
+}
+
+impl SpanRenamer {
+    pub fn new(rules: Vec<SpanRenameRule>) -> Self {
+        Self {
+            rules,
+            cardinality_limit: None,
+            seen_names: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    pub fn with_cardinality_limit(self, max_names: usize, fallback_name: &str) -> Self {
+        Self {
+            cardinality_limit: Some(CardinalityLimit {
+                max_names,
+                fallback_name: fallback_name.to_string(),
+            }),
+            ..self
+        }
+    }
+
+    pub fn rules(&self) -> &[SpanRenameRule] {
+        &self.rules
+    }
+
+    pub fn rename(&self, batch: Vec<SpanData>) -> Vec<SpanData> {
+        if self.rules.is_empty() && self.cardinality_limit.is_none() {
+            return batch;
+        }
+        batch
+            .into_iter()
+            .map(|span| self.rename_span(span))
+            .collect()
+    }
+
+    pub fn rename_span(&self, mut span: SpanData) -> SpanData {
+        let mut name = span.name.to_string();
+        for rule in &self.rules {
+            if let Some(Cow::Owned(renamed)) = rule.apply(&name, &span) {
+                name = renamed;
+            }
+        }
+
+        if let Some(cardinality_limit) = &self.cardinality_limit {
+            let mut seen_names = self.seen_names.lock().unwrap_or_else(|e| e.into_inner());
+            if !seen_names.contains(&name) {
+                if seen_names.len() < cardinality_limit.max_names {
+                    seen_names.insert(name.clone());
+                } else {
+                    name = cardinality_limit.fallback_name.clone();
+                }
+            }
+        }
+
+        if name != span.name {
+            span.name = name.into();
+        }
+        span
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::{
+        SpanContext, SpanId, SpanKind, Status, TraceFlags, TraceId, TraceState,
+    };
+    use opentelemetry::{InstrumentationScope, KeyValue};
+    use opentelemetry_sdk::trace::{SpanEvents, SpanLinks};
+    use std::time::SystemTime;
+
+    fn test_span(name: &str, attributes: Vec<KeyValue>) -> SpanData {
+        SpanData {
+            span_context: SpanContext::new(
+                TraceId::from(1),
+                SpanId::from(1),
+                TraceFlags::SAMPLED,
+                false,
+                TraceState::default(),
+            ),
+            parent_span_id: SpanId::INVALID,
+            parent_span_is_remote: false,
+            span_kind: SpanKind::Server,
+            name: name.to_string().into(),
+            start_time: SystemTime::now(),
+            end_time: SystemTime::now(),
+            attributes,
+            dropped_attributes_count: 0,
+            events: SpanEvents::default(),
+            links: SpanLinks::default(),
+            status: Status::Unset,
+            instrumentation_scope: InstrumentationScope::builder("test").build(),
+        }
+    }
+
+    fn renamed(renamer: &SpanRenamer, span: SpanData) -> String {
+        renamer.rename_span(span).name.to_string()
+    }
+
+    #[test]
+    fn renders_templates() {
+        let renamer = SpanRenamer::new(vec![SpanRenameRule::Template(
+            "{http.request.method} {http.route} ({span.name})".to_string(),
+        )]);
+        let span = test_span(
+            "/users/42",
+            vec![
+                KeyValue::new("http.request.method", "GET"),
+                KeyValue::new("http.route", "/users/{id}"),
+            ],
+        );
+        assert_eq!(renamed(&renamer, span), "GET /users/{id} (/users/42)");
+    }
+
+    #[test]
+    fn skips_templates_with_missing_attributes() {
+        let renamer = SpanRenamer::new(vec![SpanRenameRule::Template(
+            "{http.request.method} {http.route}".to_string(),
+        )]);
+        let span = test_span(
+            "/users/42",
+            vec![KeyValue::new("http.request.method", "GET")],
+        );
+        assert_eq!(renamed(&renamer, span), "/users/42");
+    }
+
+    #[test]
+    fn rewrites_with_regex_captures() {
+        let renamer = SpanRenamer::new(vec![
+            SpanRenameRule::Regex {
+                regex: Regex::new(r"^(GET|POST) /users/\d+(/\w+)?$").unwrap(),
+                replacement: "$1 /users/{id}$2".to_string(),
+            },
+            // Applied to the result of the previous rule
+            SpanRenameRule::Template("HTTP {span.name}".to_string()),
+        ]);
+        assert_eq!(
+            renamed(&renamer, test_span("POST /users/42/orders", Vec::new())),
+            "HTTP POST /users/{id}/orders"
+        );
+        assert_eq!(
+            renamed(&renamer, test_span("DELETE /users/42", Vec::new())),
+            "HTTP DELETE /users/42"
+        );
+    }
+
+    #[test]
+    fn falls_back_beyond_cardinality_limit() {
+        let renamer = SpanRenamer::new(Vec::new()).with_cardinality_limit(2, "other");
+        let names: Vec<String> = ["a", "b", "c", "a", "b", "d"]
+            .into_iter()
+            .map(|name| renamed(&renamer, test_span(name, Vec::new())))
+            .collect();
+        assert_eq!(names, vec!["a", "b", "other", "a", "b", "other"]);
+
+        // Clones share the seen names
+        assert_eq!(
+            renamed(&renamer.clone(), test_span("e", Vec::new())),
+            "other"
+        );
+    }
+}
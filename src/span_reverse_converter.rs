@@ -0,0 +1,665 @@
+use crate::errors::{GcloudTraceError, GcloudTraceSystemError};
+use crate::{ResourceAttributesMode, SpanConverter, SpanLinkType, TraceExportResult};
+use gcloud_sdk::google::devtools::cloudtrace::v2::{
+    attribute_value as gcp_attribute_value, span as gspan, AttributeValue as GcpAttributeValue,
+    BatchWriteSpansRequest, Span as GcpSpan,
+};
+use gcloud_sdk::google::rpc::{Code as GcpStatusCode, Status as GcpStatus};
+use gcloud_sdk::prost_types;
+use opentelemetry::trace::{
+    Event, Link, SpanContext, SpanId, SpanKind, Status, TraceFlags, TraceId, TraceState,
+};
+use opentelemetry::{InstrumentationScope, KeyValue};
+use opentelemetry_sdk::trace::{SpanData, SpanEvents, SpanLinks};
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+// Inverse of the forward mapping in `span_converter`.
+// Information that doesn't exist in Cloud Trace (e.g. original attribute types for
+// floats and arrays or the instrumentation scope) can't be restored.
+// Spilled attribute values are reassembled from their annotation chunks
+// when all the chunks are available, otherwise the truncated previews are kept
+// and the chunks are restored as events.
+impl SpanConverter {
+    pub fn convert_gcp_batch(
+        &self,
+        request: &BatchWriteSpansRequest,
+    ) -> TraceExportResult<Vec<SpanData>> {
+        request
+            .spans
+            .iter()
+            .map(|span| self.convert_gcp_span(span))
+            .collect()
+    }
+
+    pub fn convert_gcp_span(&self, span: &GcpSpan) -> TraceExportResult<SpanData> {
+        let (trace_id, span_id) = Self::parse_span_name(&span.name)?;
+        let reverse_attribute_mapping = self.reverse_attribute_mapping();
+        let resource_attributes: Vec<KeyValue> =
+            match (&self.resource, &self.options.resource_attributes_mode) {
+                (Some(resource), mode) if *mode != ResourceAttributesMode::Ignore => resource
+                    .iter()
+                    .map(|(k, v)| KeyValue::new(k.clone(), v.clone()))
+                    .collect(),
+                _ => Vec::new(),
+            };
+
+        let (mut attributes, dropped_attributes_count) = span
+            .attributes
+            .as_ref()
+            .map(|attrs| Self::convert_gcp_attrs(attrs, &reverse_attribute_mapping))
+            .unwrap_or_default();
+        if !span.parent_span_id.is_empty() && span.same_process_as_parent_span.is_none() {
+            attributes.push(KeyValue::new(
+                Self::PARENT_SPAN_IS_REMOTE_ATTRIBUTE,
+                "unknown",
+            ));
+        }
+
+        let start_time = Self::convert_gcp_timestamp(span.start_time.as_ref());
+        let mut attributes: Vec<KeyValue> = attributes
+            .into_iter()
+            .filter(|kv| !resource_attributes.contains(kv))
+            .collect();
+        let mut events = span
+            .time_events
+            .as_ref()
+            .map(|time_events| {
+                Self::convert_gcp_time_events(time_events, &reverse_attribute_mapping)
+            })
+            .unwrap_or_default();
+        Self::reassemble_spilled_attrs(&mut attributes, start_time, &mut events);
+        let status = Self::convert_gcp_status(span.status.as_ref(), &attributes);
+
+        Ok(SpanData {
+            span_context: SpanContext::new(
+                trace_id,
+                span_id,
+                TraceFlags::SAMPLED,
+                false,
+                TraceState::default(),
+            ),
+            parent_span_id: if span.parent_span_id.is_empty() {
+                SpanId::INVALID
+            } else {
+                Self::parse_span_id(&span.parent_span_id)?
+            },
+            parent_span_is_remote: span.same_process_as_parent_span == Some(false),
+            span_kind: Self::convert_gcp_span_kind(span.span_kind()),
+            name: span
+                .display_name
+                .as_ref()
+                .map(|name| name.value.clone())
+                .unwrap_or_default()
+                .into(),
+            start_time,
+            end_time: Self::convert_gcp_timestamp(span.end_time.as_ref()),
+            attributes,
+            dropped_attributes_count,
+            events,
+            links: span
+                .links
+                .as_ref()
+                .map(|links| Self::convert_gcp_links(links, &reverse_attribute_mapping))
+                .transpose()?
+                .unwrap_or_default(),
+            status,
+            instrumentation_scope: InstrumentationScope::builder("opentelemetry-gcloud")
+                .with_version(env!("CARGO_PKG_VERSION"))
+                .build(),
+        })
+    }
+
+    // The forward conversion sets a status without a message from `rpc.grpc.status_code`
+    // for spans with an unset status, so these statuses are restored as unset
+    fn convert_gcp_status(status: Option<&GcpStatus>, attributes: &[KeyValue]) -> Status {
+        match status {
+            None => Status::Unset,
+            Some(status) if status.code == GcpStatusCode::Ok as i32 => Status::Ok,
+            Some(status)
+                if status.message.is_empty()
+                    && attributes.iter().any(|kv| {
+                        kv.key.as_str() == "rpc.grpc.status_code"
+                            && kv.value == opentelemetry::Value::I64(status.code.into())
+                    }) =>
+            {
+                Status::Unset
+            }
+            Some(status) => Status::error(status.message.clone()),
+        }
+    }
+
+    fn parse_span_name(name: &str) -> TraceExportResult<(TraceId, SpanId)> {
+        let parts: Vec<&str> = name.split('/').collect();
+        match parts.as_slice() {
+            ["projects", _, "traces", trace_id, "spans", span_id] => Ok((
+                TraceId::from_hex(trace_id).map_err(|e| {
+                    Self::conversion_error(format!("Invalid trace id in span name {name}: {e}"))
+                })?,
+                Self::parse_span_id(span_id)?,
+            )),
+            _ => Err(Self::conversion_error(format!(
+                "Invalid span name: {name}. Expected projects/[PROJECT_ID]/traces/[TRACE_ID]/spans/[SPAN_ID]"
+            ))),
+        }
+    }
+
+    fn parse_span_id(span_id: &str) -> TraceExportResult<SpanId> {
+        SpanId::from_hex(span_id)
+            .map_err(|e| Self::conversion_error(format!("Invalid span id {span_id}: {e}")))
+    }
+
+    fn conversion_error(message: String) -> GcloudTraceError {
+        GcloudTraceError::SystemError(GcloudTraceSystemError::new(message))
+    }
+
+    // When several keys are mapped to the same label, the lexicographically smallest one is restored
+    fn reverse_attribute_mapping(&self) -> HashMap<String, String> {
+        let mut mapping: Vec<(&String, &String)> =
+            self.options.attribute_mapping.iter().flatten().collect();
+        mapping.sort();
+        mapping
+            .into_iter()
+            .rev()
+            .map(|(k, v)| (v.clone(), k.clone()))
+            .collect()
+    }
+
+    fn convert_gcp_timestamp(timestamp: Option<&prost_types::Timestamp>) -> SystemTime {
+        timestamp
+            .and_then(|ts| SystemTime::try_from(*ts).ok())
+            .unwrap_or(SystemTime::UNIX_EPOCH)
+    }
+
+    fn convert_gcp_attrs(
+        attrs: &gspan::Attributes,
+        reverse_attribute_mapping: &HashMap<String, String>,
+    ) -> (Vec<KeyValue>, u32) {
+        let mut attributes: Vec<KeyValue> = attrs
+            .attribute_map
+            .iter()
+            .filter_map(|(key, value)| {
+                Self::convert_gcp_attr_value(value).map(|value| {
+                    KeyValue::new(
+                        reverse_attribute_mapping
+                            .get(key)
+                            .cloned()
+                            .unwrap_or_else(|| key.clone()),
+                        value,
+                    )
+                })
+            })
+            .collect();
+        // Attribute maps aren't ordered, so sorting to have stable results
+        attributes.sort_by(|a, b| a.key.cmp(&b.key));
+        (attributes, attrs.dropped_attributes_count.max(0) as u32)
+    }
+
+    fn convert_gcp_attr_value(value: &GcpAttributeValue) -> Option<opentelemetry::Value> {
+        match &value.value {
+            Some(gcp_attribute_value::Value::StringValue(str)) => {
+                Some(opentelemetry::Value::String(str.value.clone().into()))
+            }
+            Some(gcp_attribute_value::Value::IntValue(value)) => {
+                Some(opentelemetry::Value::I64(*value))
+            }
+            Some(gcp_attribute_value::Value::BoolValue(value)) => {
+                Some(opentelemetry::Value::Bool(*value))
+            }
+            None => None,
+        }
+    }
+
+    fn convert_gcp_time_events(
+        time_events: &gspan::TimeEvents,
+        reverse_attribute_mapping: &HashMap<String, String>,
+    ) -> SpanEvents {
+        let mut events = SpanEvents::default();
+        events.events = time_events
+            .time_event
+            .iter()
+            .filter_map(|time_event| {
+                let timestamp = Self::convert_gcp_timestamp(time_event.time.as_ref());
+                match time_event.value.as_ref()? {
+                    gspan::time_event::Value::Annotation(annotation) => {
+                        let (attributes, dropped_attributes_count) = annotation
+                            .attributes
+                            .as_ref()
+                            .map(|attrs| Self::convert_gcp_attrs(attrs, reverse_attribute_mapping))
+                            .unwrap_or_default();
+                        Some(Event::new(
+                            annotation
+                                .description
+                                .as_ref()
+                                .map(|description| description.value.clone())
+                                .unwrap_or_default(),
+                            timestamp,
+                            attributes,
+                            dropped_attributes_count,
+                        ))
+                    }
+                    // Using the OpenTelemetry RPC semantic conventions for message events
+                    gspan::time_event::Value::MessageEvent(message_event) => Some(Event::new(
+                        "message",
+                        timestamp,
+                        vec![
+                            KeyValue::new(
+                                "message.type",
+                                message_event.r#type().as_str_name().to_string(),
+                            ),
+                            KeyValue::new("message.id", message_event.id),
+                            KeyValue::new(
+                                "message.uncompressed_size",
+                                message_event.uncompressed_size_bytes,
+                            ),
+                            KeyValue::new(
+                                "message.compressed_size",
+                                message_event.compressed_size_bytes,
+                            ),
+                        ],
+                        0,
+                    )),
+                }
+            })
+            .collect();
+        events.dropped_count = (time_events.dropped_annotations_count.max(0)
+            + time_events.dropped_message_events_count.max(0))
+            as u32;
+        events
+    }
+
+    // Chunk annotations have the `attribute.key` and `attribute.chunk` (`index/count`) attributes,
+    // and the time of the span start or of the event with the spilled attribute
+    fn spilled_attr_chunk(event: &Event) -> Option<(&str, usize, usize)> {
+        let [first, second] = event.attributes.as_slice() else {
+            return None;
+        };
+        let (key, chunk) = match (first.key.as_str(), second.key.as_str()) {
+            ("attribute.chunk", "attribute.key") => (&second.value, &first.value),
+            ("attribute.key", "attribute.chunk") => (&first.value, &second.value),
+            _ => return None,
+        };
+        let (opentelemetry::Value::String(key), opentelemetry::Value::String(chunk)) = (key, chunk)
+        else {
+            return None;
+        };
+        let (index, count) = chunk.as_str().split_once('/')?;
+        Some((key.as_str(), index.parse().ok()?, count.parse().ok()?))
+    }
+
+    fn reassemble_spilled_attrs(
+        attributes: &mut [KeyValue],
+        start_time: SystemTime,
+        events: &mut SpanEvents,
+    ) {
+        // Chunks (index, count and event index) by attribute key and time
+        type Chunks = Vec<(usize, usize, usize)>;
+        let mut chunks: HashMap<(String, SystemTime), Chunks> = HashMap::new();
+        for (event_idx, event) in events.events.iter().enumerate() {
+            if let Some((key, index, count)) = Self::spilled_attr_chunk(event) {
+                chunks
+                    .entry((key.to_string(), event.timestamp))
+                    .or_default()
+                    .push((index, count, event_idx));
+            }
+        }
+        if chunks.is_empty() {
+            return;
+        }
+
+        let mut reassembled_event_indices = Vec::new();
+        for ((key, time), mut attr_chunks) in chunks {
+            attr_chunks.sort_unstable();
+            let count = attr_chunks[0].1;
+            let complete = attr_chunks.len() == count
+                && attr_chunks
+                    .iter()
+                    .enumerate()
+                    .all(|(idx, (index, chunk_count, _))| {
+                        *index == idx + 1 && *chunk_count == count
+                    });
+            if !complete {
+                continue;
+            }
+            let value: String = attr_chunks
+                .iter()
+                .map(|(_, _, event_idx)| events.events[*event_idx].name.as_ref())
+                .collect();
+
+            let is_preview = |kv: &KeyValue| {
+                kv.key.as_str() == key
+                    && matches!(&kv.value, opentelemetry::Value::String(preview) if value.starts_with(preview.as_str()))
+            };
+            let target = if time == start_time {
+                attributes.iter_mut().find(|kv| is_preview(kv))
+            } else {
+                None
+            }
+            .or_else(|| {
+                events
+                    .events
+                    .iter_mut()
+                    .filter(|event| {
+                        event.timestamp == time && Self::spilled_attr_chunk(event).is_none()
+                    })
+                    .flat_map(|event| event.attributes.iter_mut())
+                    .find(|kv| is_preview(kv))
+            });
+            if let Some(target) = target {
+                target.value = opentelemetry::Value::String(value.into());
+                reassembled_event_indices
+                    .extend(attr_chunks.iter().map(|(_, _, event_idx)| *event_idx));
+            }
+        }
+
+        reassembled_event_indices.sort_unstable();
+        for event_idx in reassembled_event_indices.into_iter().rev() {
+            events.events.remove(event_idx);
+        }
+    }
+
+    fn convert_gcp_links(
+        gcp_links: &gspan::Links,
+        reverse_attribute_mapping: &HashMap<String, String>,
+    ) -> TraceExportResult<SpanLinks> {
+        let mut links = SpanLinks::default();
+        links.links = gcp_links
+            .link
+            .iter()
+            .map(|link| {
+                let (mut attributes, dropped_attributes_count) = link
+                    .attributes
+                    .as_ref()
+                    .map(|attrs| Self::convert_gcp_attrs(attrs, reverse_attribute_mapping))
+                    .unwrap_or_default();
+                let link_type = match link.r#type() {
+                    gspan::link::Type::ChildLinkedSpan => SpanLinkType::ChildLinkedSpan,
+                    gspan::link::Type::ParentLinkedSpan => SpanLinkType::ParentLinkedSpan,
+                    gspan::link::Type::Unspecified => SpanLinkType::Unspecified,
+                };
+                if let Some(link_type) = link_type.attribute_value() {
+                    attributes.push(KeyValue::new(SpanLinkType::LINK_TYPE_ATTRIBUTE, link_type));
+                }
+                Ok(Link::new(
+                    SpanContext::new(
+                        TraceId::from_hex(&link.trace_id).map_err(|e| {
+                            Self::conversion_error(format!(
+                                "Invalid link trace id {}: {e}",
+                                link.trace_id
+                            ))
+                        })?,
+                        Self::parse_span_id(&link.span_id)?,
+                        TraceFlags::SAMPLED,
+                        false,
+                        TraceState::default(),
+                    ),
+                    attributes,
+                    dropped_attributes_count,
+                ))
+            })
+            .collect::<TraceExportResult<Vec<Link>>>()?;
+        links.dropped_count = gcp_links.dropped_links_count.max(0) as u32;
+        Ok(links)
+    }
+
+    fn convert_gcp_span_kind(span_kind: gspan::SpanKind) -> SpanKind {
+        match span_kind {
+            gspan::SpanKind::Server => SpanKind::Server,
+            gspan::SpanKind::Client => SpanKind::Client,
+            gspan::SpanKind::Producer => SpanKind::Producer,
+            gspan::SpanKind::Consumer => SpanKind::Consumer,
+            gspan::SpanKind::Internal | gspan::SpanKind::Unspecified => SpanKind::Internal,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SpanConverterOptions;
+    use std::collections::HashSet;
+    use std::time::Duration;
+
+    // Small deterministic generator, so failures are reproducible from the seed
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: u64) -> u64 {
+            self.next() % n
+        }
+
+        fn string(&mut self, max_len: u64) -> String {
+            let len = self.below(max_len) + 1;
+            (0..len)
+                .map(|_| char::from(b'a' + self.below(26) as u8))
+                .collect()
+        }
+    }
+
+    fn random_attrs(rng: &mut Rng, keys: &[&str]) -> Vec<KeyValue> {
+        let mut attrs = Vec::new();
+        for key in keys {
+            if rng.below(2) == 0 {
+                continue;
+            }
+            attrs.push(match rng.below(3) {
+                0 => KeyValue::new(key.to_string(), rng.string(64)),
+                1 => KeyValue::new(key.to_string(), rng.next() as i64),
+                _ => KeyValue::new(key.to_string(), rng.below(2) == 0),
+            });
+        }
+        attrs.sort_by(|a, b| a.key.cmp(&b.key));
+        attrs
+    }
+
+    fn random_span(rng: &mut Rng) -> SpanData {
+        let start_time = SystemTime::UNIX_EPOCH + Duration::from_nanos(rng.below(1 << 60));
+        let mut attributes = random_attrs(
+            rng,
+            &[
+                "http.request.method",
+                "http.route",
+                "key.a",
+                "key.b",
+                "key.c",
+            ],
+        );
+        if rng.below(2) == 0 {
+            // Cloud Trace status codes are derived from gRPC status codes
+            attributes.push(KeyValue::new("rpc.grpc.status_code", rng.below(17) as i64));
+        }
+        if rng.below(2) == 0 {
+            attributes.push(KeyValue::new("zz.statement", rng.string(1500)));
+        }
+
+        let mut events = SpanEvents::default();
+        events.events = (0..rng.below(4))
+            .map(|idx| {
+                let mut event_attributes = random_attrs(rng, &["event.a", "event.b"]);
+                if rng.below(2) == 0 {
+                    event_attributes.push(KeyValue::new("zz.statement", rng.string(700)));
+                }
+                Event::new(
+                    rng.string(32),
+                    start_time + Duration::from_millis(idx + 1),
+                    event_attributes,
+                    0,
+                )
+            })
+            .collect();
+
+        let mut links = SpanLinks::default();
+        links.links = (0..rng.below(3))
+            .map(|_| {
+                let mut link_attributes = random_attrs(rng, &["link.a", "link.b"]);
+                match rng.below(3) {
+                    0 => link_attributes.push(KeyValue::new(
+                        SpanLinkType::LINK_TYPE_ATTRIBUTE,
+                        "parent_linked_span",
+                    )),
+                    1 => link_attributes.push(KeyValue::new(
+                        SpanLinkType::LINK_TYPE_ATTRIBUTE,
+                        "child_linked_span",
+                    )),
+                    _ => {}
+                }
+                Link::new(
+                    SpanContext::new(
+                        TraceId::from(rng.next() as u128 + 1),
+                        SpanId::from(rng.next() + 1),
+                        TraceFlags::SAMPLED,
+                        false,
+                        TraceState::default(),
+                    ),
+                    link_attributes,
+                    0,
+                )
+            })
+            .collect();
+
+        let (parent_span_id, parent_span_is_remote) = match rng.below(3) {
+            0 => (SpanId::INVALID, false),
+            1 => (SpanId::from(rng.next() + 1), false),
+            _ => (SpanId::from(rng.next() + 1), true),
+        };
+
+        SpanData {
+            span_context: SpanContext::new(
+                TraceId::from(((rng.next() as u128) << 64) + 1),
+                SpanId::from(rng.next() + 1),
+                TraceFlags::SAMPLED,
+                false,
+                TraceState::default(),
+            ),
+            parent_span_id,
+            parent_span_is_remote,
+            span_kind: match rng.below(5) {
+                0 => SpanKind::Client,
+                1 => SpanKind::Server,
+                2 => SpanKind::Producer,
+                3 => SpanKind::Consumer,
+                _ => SpanKind::Internal,
+            },
+            name: rng.string(64).into(),
+            start_time,
+            end_time: start_time + Duration::from_nanos(rng.below(1 << 40)),
+            attributes,
+            dropped_attributes_count: 0,
+            events,
+            links,
+            status: match rng.below(3) {
+                0 => Status::Unset,
+                1 => Status::Ok,
+                _ => Status::error(rng.string(64)),
+            },
+            instrumentation_scope: InstrumentationScope::builder("test").build(),
+        }
+    }
+
+    fn test_converter() -> SpanConverter {
+        SpanConverter::new("test-project".to_string()).with_options(
+            SpanConverterOptions::new()
+                .with_attribute_mapping(
+                    [
+                        ("http.request.method", "/http/method"),
+                        ("http.route", "/http/route"),
+                    ]
+                    .into_iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+                )
+                .with_spilled_attribute_keys(HashSet::from(["zz.statement".to_string()])),
+        )
+    }
+
+    fn assert_round_trip(seed: u64, span: &SpanData, restored: &SpanData) {
+        assert_eq!(
+            restored.span_context.trace_id(),
+            span.span_context.trace_id(),
+            "seed {seed}"
+        );
+        assert_eq!(
+            restored.span_context.span_id(),
+            span.span_context.span_id(),
+            "seed {seed}"
+        );
+        assert_eq!(restored.parent_span_id, span.parent_span_id, "seed {seed}");
+        assert_eq!(
+            restored.parent_span_is_remote, span.parent_span_is_remote,
+            "seed {seed}"
+        );
+        assert_eq!(restored.span_kind, span.span_kind, "seed {seed}");
+        assert_eq!(restored.name, span.name, "seed {seed}");
+        assert_eq!(restored.start_time, span.start_time, "seed {seed}");
+        assert_eq!(restored.end_time, span.end_time, "seed {seed}");
+        assert_eq!(restored.attributes, span.attributes, "seed {seed}");
+        assert_eq!(restored.events.events, span.events.events, "seed {seed}");
+        assert_eq!(restored.links.links, span.links.links, "seed {seed}");
+        assert_eq!(restored.status, span.status, "seed {seed}");
+    }
+
+    #[test]
+    fn round_trips_forward_conversion() {
+        let converter = test_converter();
+        for seed in 1..=256u64 {
+            let mut rng = Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+            let span = random_span(&mut rng);
+            let restored = converter
+                .convert_gcp_span(&converter.convert_span(&span))
+                .unwrap();
+            assert_round_trip(seed, &span, &restored);
+        }
+    }
+
+    #[test]
+    fn round_trips_batches() {
+        let converter = test_converter();
+        let mut rng = Rng(42);
+        let spans: Vec<SpanData> = (0..16).map(|_| random_span(&mut rng)).collect();
+        let restored = converter
+            .convert_gcp_batch(&converter.convert_batch(&spans))
+            .unwrap();
+        assert_eq!(restored.len(), spans.len());
+        for (span, restored) in spans.iter().zip(&restored) {
+            assert_round_trip(42, span, restored);
+        }
+    }
+
+    #[test]
+    fn keeps_previews_of_incomplete_spilled_attributes() {
+        let converter = test_converter();
+        let mut rng = Rng(7);
+        let mut span = random_span(&mut rng);
+        span.attributes = vec![KeyValue::new("zz.statement", "x".repeat(1000))];
+        span.events = SpanEvents::default();
+
+        let mut gcp_span = converter.convert_span(&span);
+        let time_events = gcp_span.time_events.as_mut().unwrap();
+        assert_eq!(time_events.time_event.len(), 4);
+        time_events.time_event.remove(1);
+
+        let restored = converter.convert_gcp_span(&gcp_span).unwrap();
+        assert_eq!(
+            restored.attributes,
+            vec![KeyValue::new("zz.statement", "x".repeat(256))]
+        );
+        assert_eq!(restored.events.len(), 3);
+    }
+
+    #[test]
+    fn rejects_invalid_span_names() {
+        let converter = test_converter();
+        let gcp_span = GcpSpan {
+            name: "projects/test-project/spans/0000000000000001".to_string(),
+            ..GcpSpan::default()
+        };
+        assert!(converter.convert_gcp_span(&gcp_span).is_err());
+    }
+}
@@ -0,0 +1,695 @@
+use opentelemetry::metrics::{Counter, Meter};
+use opentelemetry::trace::{Status, TraceId};
+use opentelemetry::{Context, InstrumentationScope, KeyValue};
+use opentelemetry_sdk::error::OTelSdkResult;
+use opentelemetry_sdk::trace::{Span, SpanData, SpanProcessor};
+use opentelemetry_sdk::Resource;
+use regex::Regex;
+use rsb_derive::*;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{mpsc, Arc, Mutex, OnceLock, Weak};
+use std::time::{Duration, Instant};
+
+/// Tail sampling policy. A trace is sampled when any of the policies matches.
+#[derive(Debug, Clone)]
+pub enum TailSamplingPolicy {
+    /// Any span of the trace has the error status.
+    Error,
+    /// The root span took longer than the threshold.
+    RootLatency(Duration),
+    /// Any span of the trace has the attribute with the value.
+    AttributeEquals(KeyValue),
+    /// Any span of the trace has the attribute with a value (as a string) matching the regex.
+    AttributeMatches(String, Regex),
+    /// Baseline ratio of traces (by trace id, so it's consistent across services).
+    Probabilistic(f64),
+}
+
+impl TailSamplingPolicy {
+    fn matches(&self, trace_id: TraceId, spans: &[SpanData]) -> bool {
+        match self {
+            TailSamplingPolicy::Error => spans
+                .iter()
+                .any(|span| matches!(span.status, Status::Error { .. })),
+            TailSamplingPolicy::RootLatency(threshold) => {
+                spans.iter().filter(|span| is_local_root(span)).any(|span| {
+                    span.end_time
+                        .duration_since(span.start_time)
+                        .is_ok_and(|latency| latency > *threshold)
+                })
+            }
+            TailSamplingPolicy::AttributeEquals(attribute) => {
+                spans.iter().any(|span| span.attributes.contains(attribute))
+            }
+            TailSamplingPolicy::AttributeMatches(key, regex) => spans.iter().any(|span| {
+                span.attributes
+                    .iter()
+                    .any(|kv| kv.key.as_str() == key && regex.is_match(&kv.value.as_str()))
+            }),
+            TailSamplingPolicy::Probabilistic(ratio) if *ratio >= 1.0 => true,
+            TailSamplingPolicy::Probabilistic(ratio) => {
+                // The same approach as the SDK `TraceIdRatioBased` sampler
+                let trace_id_low =
+                    u64::from_be_bytes(trace_id.to_bytes()[8..16].try_into().unwrap()) >> 1;
+                (trace_id_low as f64) < ratio.clamp(0.0, 1.0) * (1u64 << 63) as f64
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Builder)]
+pub struct TailSamplingConfig {
+    pub policies: Vec<TailSamplingPolicy>,
+    /// Maximum time to wait for the root span of a trace before deciding without it.
+    #[default = "Duration::from_secs(30)"]
+    pub decision_wait: Duration,
+    /// Maximum number of buffered traces. The oldest traces are decided early when it's reached.
+    #[default = "10000"]
+    pub max_traces: usize,
+    /// Maximum number of buffered spans per trace. Other spans are dropped.
+    #[default = "1000"]
+    pub max_spans_per_trace: usize,
+    /// Number of recent decisions kept to handle spans ending after their local root.
+    #[default = "10000"]
+    pub max_decided_traces: usize,
+}
+
+// Decided trace ids kept in `traces_order` before they are removed
+const MIN_TRACES_ORDER_LEN: usize = 64;
+
+struct BufferedTrace {
+    spans: Vec<SpanData>,
+    started_at: Instant,
+}
+
+#[derive(Default)]
+struct TailSamplingState {
+    traces: HashMap<TraceId, BufferedTrace>,
+    // Trace ids by arrival, to find timed out and oldest traces
+    traces_order: VecDeque<TraceId>,
+    decided_traces: HashMap<TraceId, bool>,
+    decided_traces_order: VecDeque<TraceId>,
+}
+
+#[derive(Debug)]
+struct TailSamplingMetrics {
+    sampled_traces: Counter<u64>,
+    not_sampled_traces: Counter<u64>,
+    timed_out_traces: Counter<u64>,
+    evicted_traces: Counter<u64>,
+    dropped_spans: Counter<u64>,
+}
+
+impl TailSamplingMetrics {
+    fn new(meter: &Meter) -> Self {
+        Self {
+            sampled_traces: meter
+                .u64_counter("gcloud_trace.tail_sampling.sampled_traces")
+                .with_description("Traces sampled by the tail sampling policies")
+                .build(),
+            not_sampled_traces: meter
+                .u64_counter("gcloud_trace.tail_sampling.not_sampled_traces")
+                .with_description("Traces dropped by the tail sampling policies")
+                .build(),
+            timed_out_traces: meter
+                .u64_counter("gcloud_trace.tail_sampling.timed_out_traces")
+                .with_description("Traces decided without their root span after the decision wait")
+                .build(),
+            evicted_traces: meter
+                .u64_counter("gcloud_trace.tail_sampling.evicted_traces")
+                .with_description("Traces decided early because the buffer was full")
+                .build(),
+            dropped_spans: meter
+                .u64_counter("gcloud_trace.tail_sampling.dropped_spans")
+                .with_description("Spans dropped because their trace had too many spans")
+                .build(),
+        }
+    }
+}
+
+struct TailSamplingInner<P> {
+    next: P,
+    config: TailSamplingConfig,
+    state: Mutex<TailSamplingState>,
+    metrics: TailSamplingMetrics,
+}
+
+/// Span processor buffering spans per trace until the local root span ends (or the decision wait expires)
+/// and forwarding whole traces matching the [`TailSamplingPolicy`] list to the next processor
+/// (e.g. the batch processor of [`crate::GcpCloudTraceExporter`]).
+///
+/// Use it with a sampler recording all spans (e.g. `Sampler::AlwaysOn`),
+/// since spans not sampled by the head sampler never reach span processors.
+///
+/// Sampling decisions, timeouts, evictions and dropped spans are reported
+/// using counters of the global meter provider.
+pub struct TailSamplingSpanProcessor<P: SpanProcessor> {
+    inner: Arc<TailSamplingInner<P>>,
+    timer_shutdown: OnceLock<Mutex<Option<mpsc::Sender<()>>>>,
+}
+
+impl<P: SpanProcessor + 'static> TailSamplingSpanProcessor<P> {
+    pub fn new(next: P, config: TailSamplingConfig) -> Self {
+        Self::with_meter(
+            next,
+            config,
+            &opentelemetry::global::meter_with_scope(
+                InstrumentationScope::builder("opentelemetry-gcloud")
+                    .with_version(env!("CARGO_PKG_VERSION"))
+                    .build(),
+            ),
+        )
+    }
+
+    fn with_meter(next: P, config: TailSamplingConfig, meter: &Meter) -> Self {
+        Self {
+            inner: Arc::new(TailSamplingInner {
+                next,
+                config,
+                state: Mutex::new(TailSamplingState::default()),
+                metrics: TailSamplingMetrics::new(meter),
+            }),
+            timer_shutdown: OnceLock::new(),
+        }
+    }
+
+    // Started with the first span instead of in `new`, so `set_resource` still has exclusive access
+    fn start_timer(&self) -> Mutex<Option<mpsc::Sender<()>>> {
+        // Decides timed out traces even when no new spans are ending
+        let check_interval = (self.inner.config.decision_wait / 4).max(Duration::from_millis(100));
+        let (timer_shutdown, timer_shutdown_receiver) = mpsc::channel::<()>();
+        let weak_inner: Weak<TailSamplingInner<P>> = Arc::downgrade(&self.inner);
+        let _ = std::thread::Builder::new()
+            .name("gcloud-trace-tail-sampling".to_string())
+            .spawn(move || {
+                while let Err(mpsc::RecvTimeoutError::Timeout) =
+                    timer_shutdown_receiver.recv_timeout(check_interval)
+                {
+                    match weak_inner.upgrade() {
+                        Some(inner) => inner.decide_timed_out_traces(),
+                        None => break,
+                    }
+                }
+            });
+        Mutex::new(Some(timer_shutdown))
+    }
+}
+
+impl<P: SpanProcessor> TailSamplingInner<P> {
+    fn lock_state(&self) -> std::sync::MutexGuard<'_, TailSamplingState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn on_end(&self, span: SpanData) {
+        let trace_id = span.span_context.trace_id();
+        let mut sampled_spans = Vec::new();
+
+        {
+            let mut state = self.lock_state();
+
+            if let Some(sampled) = state.decided_traces.get(&trace_id) {
+                if *sampled {
+                    sampled_spans.push(span);
+                }
+            } else {
+                if !state.traces.contains_key(&trace_id) {
+                    while state.traces.len() >= self.config.max_traces.max(1) {
+                        match self.decide_oldest_trace(&mut state) {
+                            Some(spans) => {
+                                self.metrics.evicted_traces.add(1, &[]);
+                                sampled_spans.extend(spans);
+                            }
+                            None => break,
+                        }
+                    }
+                    state.traces_order.push_back(trace_id);
+                }
+
+                let is_root = is_local_root(&span);
+                let trace = state
+                    .traces
+                    .entry(trace_id)
+                    .or_insert_with(|| BufferedTrace {
+                        spans: Vec::new(),
+                        started_at: Instant::now(),
+                    });
+                if trace.spans.len() < self.config.max_spans_per_trace {
+                    trace.spans.push(span);
+                } else {
+                    self.metrics.dropped_spans.add(1, &[]);
+                }
+
+                if is_root {
+                    if let Some(trace) = state.traces.remove(&trace_id) {
+                        sampled_spans.extend(self.decide(&mut state, trace_id, trace.spans));
+                        Self::remove_decided_trace_ids(&mut state);
+                    }
+                }
+            }
+        }
+
+        for span in sampled_spans {
+            self.next.on_end(span);
+        }
+    }
+
+    fn decide_timed_out_traces(&self) {
+        let mut sampled_spans = Vec::new();
+        {
+            let mut state = self.lock_state();
+            while let Some(trace_id) = state.traces_order.front().copied() {
+                match state.traces.get(&trace_id) {
+                    Some(trace) if trace.started_at.elapsed() < self.config.decision_wait => break,
+                    Some(_) => {
+                        self.metrics.timed_out_traces.add(1, &[]);
+                        if let Some(spans) = self.decide_oldest_trace(&mut state) {
+                            sampled_spans.extend(spans);
+                        }
+                    }
+                    // Already decided
+                    None => {
+                        state.traces_order.pop_front();
+                    }
+                }
+            }
+        }
+
+        for span in sampled_spans {
+            self.next.on_end(span);
+        }
+    }
+
+    fn decide_all_traces(&self) {
+        let mut sampled_spans = Vec::new();
+        {
+            let mut state = self.lock_state();
+            while let Some(spans) = self.decide_oldest_trace(&mut state) {
+                sampled_spans.extend(spans);
+            }
+        }
+
+        for span in sampled_spans {
+            self.next.on_end(span);
+        }
+    }
+
+    // Ids of traces decided when their root span ended are left in `traces_order`.
+    // They're dropped from the front, and all of them once they outnumber the buffered traces,
+    // so the queue stays bounded by `max_traces`.
+    fn remove_decided_trace_ids(state: &mut TailSamplingState) {
+        while let Some(trace_id) = state.traces_order.front() {
+            if state.traces.contains_key(trace_id) {
+                break;
+            }
+            state.traces_order.pop_front();
+        }
+        if state.traces_order.len() > 2 * state.traces.len().max(MIN_TRACES_ORDER_LEN) {
+            let TailSamplingState {
+                traces,
+                traces_order,
+                ..
+            } = state;
+            traces_order.retain(|trace_id| traces.contains_key(trace_id));
+        }
+    }
+
+    // Returns None when there are no more buffered traces
+    fn decide_oldest_trace(&self, state: &mut TailSamplingState) -> Option<Vec<SpanData>> {
+        while let Some(trace_id) = state.traces_order.pop_front() {
+            if let Some(trace) = state.traces.remove(&trace_id) {
+                return Some(self.decide(state, trace_id, trace.spans));
+            }
+        }
+        None
+    }
+
+    fn decide(
+        &self,
+        state: &mut TailSamplingState,
+        trace_id: TraceId,
+        spans: Vec<SpanData>,
+    ) -> Vec<SpanData> {
+        let sampled = self
+            .config
+            .policies
+            .iter()
+            .any(|policy| policy.matches(trace_id, &spans));
+
+        if self.config.max_decided_traces > 0 {
+            while state.decided_traces.len() >= self.config.max_decided_traces {
+                match state.decided_traces_order.pop_front() {
+                    Some(oldest_trace_id) => {
+                        state.decided_traces.remove(&oldest_trace_id);
+                    }
+                    None => break,
+                }
+            }
+            state.decided_traces.insert(trace_id, sampled);
+            state.decided_traces_order.push_back(trace_id);
+        }
+
+        if sampled {
+            self.metrics.sampled_traces.add(1, &[]);
+            spans
+        } else {
+            self.metrics.not_sampled_traces.add(1, &[]);
+            Vec::new()
+        }
+    }
+}
+
+fn is_local_root(span: &SpanData) -> bool {
+    span.parent_span_id == opentelemetry::trace::SpanId::INVALID || span.parent_span_is_remote
+}
+
+impl<P: SpanProcessor> std::fmt::Debug for TailSamplingSpanProcessor<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TailSamplingSpanProcessor")
+            .field("next", &self.inner.next)
+            .field("config", &self.inner.config)
+            .finish()
+    }
+}
+
+impl<P: SpanProcessor + 'static> SpanProcessor for TailSamplingSpanProcessor<P> {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        self.inner.next.on_start(span, cx);
+    }
+
+    fn on_end(&self, span: SpanData) {
+        self.timer_shutdown.get_or_init(|| self.start_timer());
+        self.inner.on_end(span);
+    }
+
+    fn force_flush(&self) -> OTelSdkResult {
+        self.inner.decide_timed_out_traces();
+        self.inner.next.force_flush()
+    }
+
+    fn shutdown_with_timeout(&self, timeout: Duration) -> OTelSdkResult {
+        if let Some(timer_shutdown) = self.timer_shutdown.get().and_then(|timer_shutdown| {
+            timer_shutdown
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .take()
+        }) {
+            let _ = timer_shutdown.send(());
+        }
+        self.inner.decide_all_traces();
+        self.inner.next.shutdown_with_timeout(timeout)
+    }
+
+    fn set_resource(&mut self, resource: &Resource) {
+        if let Some(inner) = Arc::get_mut(&mut self.inner) {
+            inner.next.set_resource(resource);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::metrics::MeterProvider;
+    use opentelemetry::trace::{SpanContext, SpanId, SpanKind, TraceFlags, TraceState};
+    use opentelemetry_sdk::metrics::data::{AggregatedMetrics, MetricData};
+    use opentelemetry_sdk::metrics::{InMemoryMetricExporter, SdkMeterProvider};
+    use opentelemetry_sdk::trace::{
+        InMemorySpanExporter, SimpleSpanProcessor, SpanEvents, SpanLinks,
+    };
+    use std::time::SystemTime;
+
+    struct TestProcessor {
+        processor: TailSamplingSpanProcessor<SimpleSpanProcessor<InMemorySpanExporter>>,
+        exporter: InMemorySpanExporter,
+        meter_provider: SdkMeterProvider,
+        metric_exporter: InMemoryMetricExporter,
+    }
+
+    impl TestProcessor {
+        fn new(config: TailSamplingConfig) -> Self {
+            let exporter = InMemorySpanExporter::default();
+            let metric_exporter = InMemoryMetricExporter::default();
+            let meter_provider = SdkMeterProvider::builder()
+                .with_periodic_exporter(metric_exporter.clone())
+                .build();
+            let processor = TailSamplingSpanProcessor::with_meter(
+                SimpleSpanProcessor::new(exporter.clone()),
+                config,
+                &meter_provider.meter("test"),
+            );
+            Self {
+                processor,
+                exporter,
+                meter_provider,
+                metric_exporter,
+            }
+        }
+
+        fn exported_span_ids(&self) -> Vec<u64> {
+            self.exporter
+                .get_finished_spans()
+                .unwrap()
+                .iter()
+                .map(|span| u64::from_be_bytes(span.span_context.span_id().to_bytes()))
+                .collect()
+        }
+
+        fn counter(&self, name: &str) -> u64 {
+            self.meter_provider.force_flush().unwrap();
+            self.metric_exporter
+                .get_finished_metrics()
+                .unwrap()
+                .iter()
+                .flat_map(|resource_metrics| resource_metrics.scope_metrics())
+                .flat_map(|scope_metrics| scope_metrics.metrics())
+                .filter(|metric| metric.name() == name)
+                .filter_map(|metric| match metric.data() {
+                    AggregatedMetrics::U64(MetricData::Sum(sum)) => {
+                        sum.data_points().map(|point| point.value()).last()
+                    }
+                    _ => None,
+                })
+                .last()
+                .unwrap_or(0)
+        }
+    }
+
+    fn test_span(trace_id: u128, span_id: u64, parent_span_id: u64) -> SpanData {
+        let start_time = SystemTime::now();
+        SpanData {
+            span_context: SpanContext::new(
+                TraceId::from(trace_id),
+                SpanId::from(span_id),
+                TraceFlags::SAMPLED,
+                false,
+                TraceState::default(),
+            ),
+            parent_span_id: SpanId::from(parent_span_id),
+            parent_span_is_remote: false,
+            span_kind: SpanKind::Internal,
+            name: "test".into(),
+            start_time,
+            end_time: start_time + Duration::from_millis(10),
+            attributes: Vec::new(),
+            dropped_attributes_count: 0,
+            events: SpanEvents::default(),
+            links: SpanLinks::default(),
+            status: Status::Unset,
+            instrumentation_scope: InstrumentationScope::builder("test").build(),
+        }
+    }
+
+    fn error_span(trace_id: u128, span_id: u64, parent_span_id: u64) -> SpanData {
+        SpanData {
+            status: Status::error("failed"),
+            ..test_span(trace_id, span_id, parent_span_id)
+        }
+    }
+
+    fn error_config() -> TailSamplingConfig {
+        TailSamplingConfig::new(vec![TailSamplingPolicy::Error])
+    }
+
+    #[test]
+    fn buffers_traces_until_the_root_span_ends() {
+        let test = TestProcessor::new(error_config());
+
+        test.processor.on_end(error_span(1, 2, 1));
+        test.processor.on_end(test_span(1, 3, 1));
+        assert!(test.exported_span_ids().is_empty());
+
+        test.processor.on_end(test_span(1, 1, 0));
+        assert_eq!(test.exported_span_ids(), vec![2, 3, 1]);
+        assert_eq!(test.counter("gcloud_trace.tail_sampling.sampled_traces"), 1);
+    }
+
+    #[test]
+    fn remembers_decisions_for_late_spans() {
+        let test = TestProcessor::new(error_config());
+
+        test.processor.on_end(test_span(1, 1, 0));
+        test.processor.on_end(error_span(2, 1, 0));
+        // Spans ending after their local root follow the decision of their trace
+        test.processor.on_end(error_span(1, 2, 1));
+        test.processor.on_end(test_span(2, 2, 1));
+
+        assert_eq!(test.exported_span_ids(), vec![1, 2]);
+        assert_eq!(
+            test.exporter.get_finished_spans().unwrap()[1]
+                .span_context
+                .trace_id(),
+            TraceId::from(2)
+        );
+        assert_eq!(
+            test.counter("gcloud_trace.tail_sampling.not_sampled_traces"),
+            1
+        );
+    }
+
+    #[test]
+    fn forgets_oldest_decisions() {
+        let test = TestProcessor::new(error_config().with_max_decided_traces(1));
+
+        test.processor.on_end(error_span(1, 1, 0));
+        test.processor.on_end(test_span(2, 1, 0));
+        // The decision of trace 1 was replaced by trace 2, so this span is buffered again
+        test.processor.on_end(test_span(1, 2, 1));
+
+        assert_eq!(test.exported_span_ids(), vec![1]);
+        assert_eq!(test.processor.inner.lock_state().traces.len(), 1);
+    }
+
+    #[test]
+    fn matches_policies() {
+        let root_span = |trace_id: u128, latency: Duration, attributes: Vec<KeyValue>| {
+            let span = test_span(trace_id, 1, 0);
+            SpanData {
+                end_time: span.start_time + latency,
+                attributes,
+                ..span
+            }
+        };
+        let test = TestProcessor::new(TailSamplingConfig::new(vec![
+            TailSamplingPolicy::RootLatency(Duration::from_secs(1)),
+            TailSamplingPolicy::AttributeEquals(KeyValue::new("tenant", "vip")),
+            TailSamplingPolicy::AttributeMatches(
+                "http.route".to_string(),
+                Regex::new("^/checkout").unwrap(),
+            ),
+        ]));
+
+        let traces = [
+            (Duration::from_secs(2), vec![]),
+            (Duration::from_millis(1), vec![]),
+            (
+                Duration::from_millis(1),
+                vec![KeyValue::new("tenant", "vip")],
+            ),
+            (
+                Duration::from_millis(1),
+                vec![KeyValue::new("tenant", "other")],
+            ),
+            (
+                Duration::from_millis(1),
+                vec![KeyValue::new("http.route", "/checkout/{id}")],
+            ),
+            (
+                Duration::from_millis(1),
+                vec![KeyValue::new("http.route", "/cart/checkout")],
+            ),
+        ];
+        for (trace_id, (latency, attributes)) in traces.into_iter().enumerate() {
+            test.processor
+                .on_end(root_span(trace_id as u128 + 1, latency, attributes));
+        }
+
+        let sampled_trace_ids: Vec<TraceId> = test
+            .exporter
+            .get_finished_spans()
+            .unwrap()
+            .iter()
+            .map(|span| span.span_context.trace_id())
+            .collect();
+        assert_eq!(
+            sampled_trace_ids,
+            vec![TraceId::from(1), TraceId::from(3), TraceId::from(5)]
+        );
+    }
+
+    #[test]
+    fn matches_probabilistic_policy_by_trace_id() {
+        let policy = |ratio: f64| TailSamplingPolicy::Probabilistic(ratio);
+        let trace_id = TraceId::from(u128::from(u64::MAX / 4));
+
+        assert!(!policy(0.0).matches(trace_id, &[]));
+        assert!(!policy(0.2).matches(trace_id, &[]));
+        assert!(policy(0.3).matches(trace_id, &[]));
+        assert!(policy(1.0).matches(TraceId::from(u128::from(u64::MAX)), &[]));
+    }
+
+    #[test]
+    fn decides_timed_out_traces() {
+        let test =
+            TestProcessor::new(error_config().with_decision_wait(Duration::from_millis(200)));
+
+        test.processor.on_end(error_span(1, 2, 1));
+        std::thread::sleep(Duration::from_millis(300));
+        test.processor.on_end(error_span(2, 2, 1));
+        test.processor.force_flush().unwrap();
+
+        // Only the first trace waited longer than the decision wait
+        assert_eq!(test.exporter.get_finished_spans().unwrap().len(), 1);
+        assert_eq!(
+            test.counter("gcloud_trace.tail_sampling.timed_out_traces"),
+            1
+        );
+
+        // Done on shutdown, before the next processor is shut down
+        test.processor.inner.decide_all_traces();
+        assert_eq!(test.exporter.get_finished_spans().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn evicts_oldest_traces() {
+        let test = TestProcessor::new(error_config().with_max_traces(2));
+
+        test.processor.on_end(error_span(1, 2, 1));
+        test.processor.on_end(error_span(2, 2, 1));
+        test.processor.on_end(error_span(3, 2, 1));
+
+        let exported = test.exporter.get_finished_spans().unwrap();
+        assert_eq!(exported.len(), 1);
+        assert_eq!(exported[0].span_context.trace_id(), TraceId::from(1));
+        assert_eq!(test.counter("gcloud_trace.tail_sampling.evicted_traces"), 1);
+    }
+
+    #[test]
+    fn limits_spans_per_trace() {
+        let test = TestProcessor::new(error_config().with_max_spans_per_trace(2));
+
+        test.processor.on_end(error_span(1, 2, 1));
+        test.processor.on_end(test_span(1, 3, 1));
+        test.processor.on_end(test_span(1, 4, 1));
+        test.processor.on_end(test_span(1, 1, 0));
+
+        assert_eq!(test.exported_span_ids(), vec![2, 3]);
+        assert_eq!(test.counter("gcloud_trace.tail_sampling.dropped_spans"), 2);
+    }
+
+    #[test]
+    fn bounds_trace_order() {
+        let test = TestProcessor::new(error_config());
+
+        // A trace waiting for its root span in front of traces decided when their root ended
+        test.processor.on_end(test_span(1, 2, 1));
+        for trace_id in 2..1000 {
+            test.processor.on_end(test_span(trace_id, 2, 1));
+            test.processor.on_end(test_span(trace_id, 1, 0));
+        }
+
+        let state = test.processor.inner.lock_state();
+        assert_eq!(state.traces.len(), 1);
+        assert!(state.traces_order.len() <= 2 * MIN_TRACES_ORDER_LEN);
+        assert_eq!(state.traces_order.front(), Some(&TraceId::from(1)));
+    }
+}
@@ -0,0 +1,416 @@
+use crate::{GcpCloudTraceContextPropagator, SdkTracer};
+use axum::extract::MatchedPath;
+use futures::future::BoxFuture;
+use futures::FutureExt as _;
+use http::{header, HeaderMap, Request, Response};
+use opentelemetry::context::FutureExt as OtelContextFutureExt;
+use opentelemetry::propagation::{Extractor, TextMapPropagator};
+use opentelemetry::trace::{SpanKind, Status, TraceContextExt, Tracer, TracerProvider};
+use opentelemetry::{Context, InstrumentationScope, KeyValue};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use std::fmt::Display;
+use std::task::Poll;
+use tower::{Layer, Service};
+
+/// Tower layer (e.g. for axum) creating a SERVER span for every HTTP request.
+///
+/// - The parent is extracted from `traceparent` or, if it is missing, from `X-Cloud-Trace-Context`.
+/// - Spans are named `METHOD route` using the axum matched route when available.
+/// - The Cloud Trace `/http/*` well-known labels and the response status are recorded,
+///   and 5xx responses are marked as errors.
+///
+/// Add it with `Router::layer` to have the matched route available.
+#[derive(Debug, Clone)]
+pub struct GcpCloudTraceLayer {
+    tracer: SdkTracer,
+    tracer_provider: SdkTracerProvider,
+    force_flush: bool,
+}
+
+impl GcpCloudTraceLayer {
+    pub fn new(tracer_provider: SdkTracerProvider) -> Self {
+        let scope = InstrumentationScope::builder("opentelemetry-gcloud")
+            .with_version(env!("CARGO_PKG_VERSION"))
+            .build();
+        Self {
+            tracer: tracer_provider.tracer_with_scope(scope),
+            tracer_provider,
+            force_flush: false,
+        }
+    }
+
+    /// Flushes the spans at the end of every request before the response is returned.
+    ///
+    /// Useful on Cloud Run with CPU allocated only during requests,
+    /// where the background export can be throttled indefinitely.
+    pub fn with_force_flush(self, force_flush: bool) -> Self {
+        Self {
+            force_flush,
+            ..self
+        }
+    }
+}
+
+impl<S> Layer<S> for GcpCloudTraceLayer {
+    type Service = GcpCloudTraceService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GcpCloudTraceService {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GcpCloudTraceService<S> {
+    inner: S,
+    layer: GcpCloudTraceLayer,
+}
+
+pub(crate) struct HeaderExtractor<'a>(pub(crate) &'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+/// Extracts the remote parent from `traceparent` or `X-Cloud-Trace-Context`.
+pub(crate) fn extract_parent_context(extractor: &dyn Extractor) -> Context {
+    let parent_cx = TraceContextPropagator::new().extract(extractor);
+    if parent_cx.span().span_context().is_valid() {
+        parent_cx
+    } else {
+        GcpCloudTraceContextPropagator::new().extract(extractor)
+    }
+}
+
+fn header_value(headers: &HeaderMap, name: header::HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string())
+}
+
+fn content_length(headers: &HeaderMap) -> Option<i64> {
+    headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<i64>().ok())
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for GcpCloudTraceService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Display + Send + 'static,
+    ReqBody: Send + 'static,
+    ResBody: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        // The service which was polled ready needs to be used for the call
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let layer = self.layer.clone();
+
+        let parent_cx = extract_parent_context(&HeaderExtractor(req.headers()));
+
+        let method = req.method().to_string();
+        let route = req
+            .extensions()
+            .get::<MatchedPath>()
+            .map(|path| path.as_str().to_string());
+        let headers = req.headers();
+
+        let mut attributes = vec![
+            KeyValue::new("/http/method", method.clone()),
+            KeyValue::new("/http/path", req.uri().path().to_string()),
+            KeyValue::new("/http/url", req.uri().to_string()),
+        ];
+        if let Some(host) = req
+            .uri()
+            .host()
+            .map(|host| host.to_string())
+            .or_else(|| header_value(headers, header::HOST))
+        {
+            attributes.push(KeyValue::new("/http/host", host));
+        }
+        if let Some(user_agent) = header_value(headers, header::USER_AGENT) {
+            attributes.push(KeyValue::new("/http/user_agent", user_agent));
+        }
+        if let Some(request_size) = content_length(headers) {
+            attributes.push(KeyValue::new("/http/request/size", request_size));
+        }
+        if let Some(route) = &route {
+            attributes.push(KeyValue::new("/http/route", route.clone()));
+        }
+
+        let span_name = match &route {
+            Some(route) => format!("{method} {route}"),
+            None => method,
+        };
+        let span = layer
+            .tracer
+            .span_builder(span_name)
+            .with_kind(SpanKind::Server)
+            .with_attributes(attributes)
+            .start_with_context(&layer.tracer, &parent_cx);
+        let cx = parent_cx.with_span(span);
+
+        async move {
+            let result = inner.call(req).with_context(cx.clone()).await;
+
+            let span = cx.span();
+            match &result {
+                Ok(response) => {
+                    let status_code = response.status();
+                    span.set_attribute(KeyValue::new(
+                        "/http/status_code",
+                        status_code.as_u16() as i64,
+                    ));
+                    if let Some(response_size) = content_length(response.headers()) {
+                        span.set_attribute(KeyValue::new("/http/response/size", response_size));
+                    }
+                    if status_code.is_server_error() {
+                        span.set_status(Status::error(status_code.to_string()));
+                    }
+                }
+                Err(err) => span.set_status(Status::error(err.to_string())),
+            }
+            span.end();
+
+            if layer.force_flush {
+                let tracer_provider = layer.tracer_provider.clone();
+                if let Ok(Err(err)) =
+                    tokio::task::spawn_blocking(move || tracer_provider.force_flush()).await
+                {
+                    tracing::warn!("Failed to flush spans at the end of the request: {err}");
+                }
+            }
+
+            result
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::StatusCode;
+    use axum::routing::get;
+    use axum::Router;
+    use opentelemetry::trace::{SpanId, TraceId};
+    use opentelemetry::Value;
+    use opentelemetry_sdk::trace::{
+        BatchConfigBuilder, BatchSpanProcessor, InMemorySpanExporter, SpanData,
+    };
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    fn router() -> Router {
+        Router::new()
+            .route("/users/{id}", get(|| async { "user" }))
+            .route("/fail", get(|| async { StatusCode::INTERNAL_SERVER_ERROR }))
+            .route("/missing", get(|| async { StatusCode::NOT_FOUND }))
+    }
+
+    async fn call(
+        exporter: &InMemorySpanExporter,
+        provider: SdkTracerProvider,
+        request: Request<Body>,
+    ) -> SpanData {
+        let response = router()
+            .layer(GcpCloudTraceLayer::new(provider.clone()))
+            .oneshot(request)
+            .await
+            .unwrap();
+        drop(response);
+        provider.force_flush().unwrap();
+        let mut spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 1);
+        spans.remove(0)
+    }
+
+    fn test_provider() -> (InMemorySpanExporter, SdkTracerProvider) {
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        (exporter, provider)
+    }
+
+    fn attribute(span: &SpanData, key: &str) -> Option<Value> {
+        span.attributes
+            .iter()
+            .find(|kv| kv.key.as_str() == key)
+            .map(|kv| kv.value.clone())
+    }
+
+    #[test]
+    fn prefers_traceparent_over_cloud_trace_context() {
+        let headers = HeaderMap::from_iter([
+            (
+                header::HeaderName::from_static("traceparent"),
+                header::HeaderValue::from_static(
+                    "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+                ),
+            ),
+            (
+                header::HeaderName::from_static("x-cloud-trace-context"),
+                header::HeaderValue::from_static("105445aa7843bc8bf206b12000100000/1;o=1"),
+            ),
+        ]);
+        let cx = extract_parent_context(&HeaderExtractor(&headers));
+        assert_eq!(
+            cx.span().span_context().trace_id(),
+            TraceId::from_hex("0af7651916cd43dd8448eb211c80319c").unwrap()
+        );
+
+        // Invalid traceparent headers fall back to X-Cloud-Trace-Context
+        let headers = HeaderMap::from_iter([
+            (
+                header::HeaderName::from_static("traceparent"),
+                header::HeaderValue::from_static("invalid"),
+            ),
+            (
+                header::HeaderName::from_static("x-cloud-trace-context"),
+                header::HeaderValue::from_static("105445aa7843bc8bf206b12000100000/1;o=1"),
+            ),
+        ]);
+        let cx = extract_parent_context(&HeaderExtractor(&headers));
+        assert_eq!(
+            cx.span().span_context().trace_id(),
+            TraceId::from_hex("105445aa7843bc8bf206b12000100000").unwrap()
+        );
+        assert_eq!(cx.span().span_context().span_id(), SpanId::from(1));
+    }
+
+    #[tokio::test]
+    async fn records_server_spans() {
+        let (exporter, provider) = test_provider();
+        let request = Request::get("http://example.com/users/42?details=true")
+            .header(header::USER_AGENT, "test-agent")
+            .header(
+                "x-cloud-trace-context",
+                "105445aa7843bc8bf206b12000100000/1;o=1",
+            )
+            .header(header::CONTENT_LENGTH, "5")
+            .body(Body::from("hello"))
+            .unwrap();
+        let span = call(&exporter, provider, request).await;
+
+        assert_eq!(span.name, "GET /users/{id}");
+        assert_eq!(span.span_kind, SpanKind::Server);
+        assert_eq!(
+            span.span_context.trace_id(),
+            TraceId::from_hex("105445aa7843bc8bf206b12000100000").unwrap()
+        );
+        assert_eq!(span.parent_span_id, SpanId::from(1));
+        assert!(span.parent_span_is_remote);
+        assert_eq!(span.status, Status::Unset);
+        for (key, value) in [
+            ("/http/method", Value::from("GET")),
+            ("/http/path", Value::from("/users/42")),
+            (
+                "/http/url",
+                Value::from("http://example.com/users/42?details=true"),
+            ),
+            ("/http/host", Value::from("example.com")),
+            ("/http/user_agent", Value::from("test-agent")),
+            ("/http/route", Value::from("/users/{id}")),
+            ("/http/status_code", Value::from(200)),
+            ("/http/request/size", Value::from(5)),
+        ] {
+            assert_eq!(attribute(&span, key), Some(value), "{key}");
+        }
+    }
+
+    #[tokio::test]
+    async fn names_spans_without_matched_path() {
+        let (exporter, provider) = test_provider();
+        // Layered outside of the router, so no route is matched yet
+        let service = GcpCloudTraceLayer::new(provider.clone()).layer(router());
+        service
+            .oneshot(Request::get("/users/42").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        provider.force_flush().unwrap();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].name, "GET");
+        assert_eq!(attribute(&spans[0], "/http/route"), None);
+    }
+
+    #[tokio::test]
+    async fn marks_only_server_errors() {
+        let (exporter, provider) = test_provider();
+        let span = call(
+            &exporter,
+            provider.clone(),
+            Request::get("/fail").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(
+            attribute(&span, "/http/status_code"),
+            Some(Value::from(500))
+        );
+        assert!(matches!(span.status, Status::Error { .. }));
+
+        exporter.reset();
+        let span = call(
+            &exporter,
+            provider,
+            Request::get("/missing").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(
+            attribute(&span, "/http/status_code"),
+            Some(Value::from(404))
+        );
+        assert_eq!(span.status, Status::Unset);
+    }
+
+    #[tokio::test]
+    async fn flushes_before_responding() {
+        for force_flush in [false, true] {
+            let exporter = InMemorySpanExporter::default();
+            // Without a flush, the batch isn't exported before the scheduled delay
+            let processor = BatchSpanProcessor::builder(exporter.clone())
+                .with_batch_config(
+                    BatchConfigBuilder::default()
+                        .with_scheduled_delay(Duration::from_secs(60))
+                        .build(),
+                )
+                .build();
+            let provider = SdkTracerProvider::builder()
+                .with_span_processor(processor)
+                .build();
+
+            router()
+                .layer(GcpCloudTraceLayer::new(provider.clone()).with_force_flush(force_flush))
+                .oneshot(Request::get("/users/42").body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+
+            let expected = if force_flush { 1 } else { 0 };
+            assert_eq!(exporter.get_finished_spans().unwrap().len(), expected);
+        }
+    }
+}
@@ -0,0 +1,442 @@
+use crate::errors::{GcloudTraceError, GcloudTraceSystemError};
+use crate::{GcpCloudTraceCredentials, TraceExportResult, GCP_CLOUD_TRACE_API_URL};
+use gcloud_sdk::google::devtools::cloudtrace::v1::{
+    list_traces_request::ViewType, trace_service_client::TraceServiceClient, trace_span,
+    GetTraceRequest, ListTracesRequest, Trace, TraceSpan,
+};
+use gcloud_sdk::*;
+use opentelemetry::trace::{SpanId, SpanKind, TraceId};
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::time::SystemTime;
+
+/// A trace fetched from Cloud Trace.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GcpTrace {
+    pub project_id: String,
+    pub trace_id: TraceId,
+    pub spans: Vec<GcpTraceSpan>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GcpTraceSpan {
+    pub span_id: SpanId,
+    pub parent_span_id: Option<SpanId>,
+    pub name: String,
+    /// Cloud Trace v1 only distinguishes RPC server and client spans.
+    pub kind: Option<SpanKind>,
+    pub start_time: Option<SystemTime>,
+    pub end_time: Option<SystemTime>,
+    pub labels: HashMap<String, String>,
+}
+
+/// A span with its children, reconstructed from parent span ids.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GcpTraceSpanNode {
+    pub span: GcpTraceSpan,
+    pub children: Vec<GcpTraceSpanNode>,
+}
+
+impl GcpTrace {
+    /// Reconstructs the span tree. Spans with parents missing in the trace are returned as roots,
+    /// and so are the first spans of parent cycles (or duplicated span ids) not reachable from them.
+    pub fn span_tree(&self) -> Vec<GcpTraceSpanNode> {
+        let span_ids: HashSet<SpanId> = self.spans.iter().map(|span| span.span_id).collect();
+        let mut children_by_parent: HashMap<SpanId, Vec<&GcpTraceSpan>> = HashMap::new();
+        let mut roots: Vec<&GcpTraceSpan> = Vec::new();
+
+        for span in &self.spans {
+            match span.parent_span_id {
+                Some(parent_span_id) if span_ids.contains(&parent_span_id) => children_by_parent
+                    .entry(parent_span_id)
+                    .or_default()
+                    .push(span),
+                _ => roots.push(span),
+            }
+        }
+
+        // Spans are compared by address, since span ids may be duplicated
+        fn build_node(
+            span: &GcpTraceSpan,
+            children_by_parent: &HashMap<SpanId, Vec<&GcpTraceSpan>>,
+            visited: &mut HashSet<*const GcpTraceSpan>,
+        ) -> GcpTraceSpanNode {
+            let mut children = Vec::new();
+            for child in children_by_parent.get(&span.span_id).into_iter().flatten() {
+                if visited.insert(*child) {
+                    children.push(build_node(child, children_by_parent, visited));
+                }
+            }
+            children.sort_by_key(|child| child.span.start_time);
+            GcpTraceSpanNode {
+                span: span.clone(),
+                children,
+            }
+        }
+
+        let mut visited: HashSet<*const GcpTraceSpan> = HashSet::new();
+        let mut unvisited: Vec<&GcpTraceSpan> = self.spans.iter().collect();
+        unvisited.sort_by_key(|span| span.start_time);
+        roots.sort_by_key(|span| span.start_time);
+        let mut tree: Vec<GcpTraceSpanNode> = Vec::new();
+        for root in roots.into_iter().chain(unvisited) {
+            if visited.insert(root) {
+                tree.push(build_node(root, &children_by_parent, &mut visited));
+            }
+        }
+        tree
+    }
+}
+
+/// Reads traces from Cloud Trace using the v1 API (e.g. for end-to-end tests and debugging tools).
+#[derive(Clone)]
+pub struct GcpCloudTraceReader {
+    client: GoogleApi<TraceServiceClient<GoogleAuthMiddleware>>,
+    google_project_id: String,
+}
+
+impl GcpCloudTraceReader {
+    pub async fn new(google_project_id: &str) -> TraceExportResult<Self> {
+        Self::with_options(
+            google_project_id,
+            GCP_CLOUD_TRACE_API_URL,
+            &GcpCloudTraceCredentials::default(),
+        )
+        .await
+    }
+
+    pub async fn with_options(
+        google_project_id: &str,
+        api_url: &str,
+        credentials: &GcpCloudTraceCredentials,
+    ) -> TraceExportResult<Self> {
+        let client = crate::credentials::create_google_api(
+            TraceServiceClient::new,
+            api_url,
+            credentials,
+            HeaderMap::new(),
+        )
+        .await?;
+
+        Ok(Self {
+            client,
+            google_project_id: google_project_id.to_string(),
+        })
+    }
+
+    pub async fn get_trace(&self, trace_id: TraceId) -> TraceExportResult<GcpTrace> {
+        let trace = self
+            .client
+            .get()
+            .get_trace(tonic::Request::new(GetTraceRequest {
+                project_id: self.google_project_id.clone(),
+                trace_id: trace_id.to_string(),
+            }))
+            .await?
+            .into_inner();
+
+        Self::convert_trace(trace)
+    }
+
+    /// Lists complete traces matching the filter (using the Cloud Trace filter syntax,
+    /// empty to list all traces) that started within the time range.
+    pub async fn list_traces(
+        &self,
+        filter: &str,
+        time_range: Range<SystemTime>,
+    ) -> TraceExportResult<Vec<GcpTrace>> {
+        let mut traces = Vec::new();
+        let mut page_token = String::new();
+
+        loop {
+            let response = self
+                .client
+                .get()
+                .list_traces(tonic::Request::new(ListTracesRequest {
+                    project_id: self.google_project_id.clone(),
+                    view: ViewType::Complete.into(),
+                    page_token,
+                    start_time: Some(prost_types::Timestamp::from(time_range.start)),
+                    end_time: Some(prost_types::Timestamp::from(time_range.end)),
+                    filter: filter.to_string(),
+                    ..ListTracesRequest::default()
+                }))
+                .await?
+                .into_inner();
+
+            for trace in response.traces {
+                traces.push(Self::convert_trace(trace)?);
+            }
+
+            if response.next_page_token.is_empty() {
+                break;
+            }
+            page_token = response.next_page_token;
+        }
+
+        Ok(traces)
+    }
+
+    fn convert_trace(trace: Trace) -> TraceExportResult<GcpTrace> {
+        Ok(GcpTrace {
+            trace_id: TraceId::from_hex(&trace.trace_id).map_err(|e| {
+                GcloudTraceError::SystemError(GcloudTraceSystemError::new(format!(
+                    "Invalid trace id {}: {e}",
+                    trace.trace_id
+                )))
+            })?,
+            project_id: trace.project_id,
+            spans: trace.spans.into_iter().map(Self::convert_span).collect(),
+        })
+    }
+
+    fn convert_span(span: TraceSpan) -> GcpTraceSpan {
+        GcpTraceSpan {
+            span_id: SpanId::from_bytes(span.span_id.to_be_bytes()),
+            parent_span_id: if span.parent_span_id != 0 {
+                Some(SpanId::from_bytes(span.parent_span_id.to_be_bytes()))
+            } else {
+                None
+            },
+            kind: match span.kind() {
+                trace_span::SpanKind::RpcServer => Some(SpanKind::Server),
+                trace_span::SpanKind::RpcClient => Some(SpanKind::Client),
+                trace_span::SpanKind::Unspecified => None,
+            },
+            start_time: span.start_time.and_then(|ts| SystemTime::try_from(ts).ok()),
+            end_time: span.end_time.and_then(|ts| SystemTime::try_from(ts).ok()),
+            name: span.name,
+            labels: span.labels,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{Body, Bytes};
+    use axum::http::{header, HeaderMap, HeaderValue};
+    use gcloud_sdk::google::devtools::cloudtrace::v1::ListTracesResponse;
+    use gcloud_sdk::prost::Message;
+    use http_body_util::StreamBody;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    fn test_span(span_id: u64, parent_span_id: Option<u64>, start_secs: u64) -> GcpTraceSpan {
+        GcpTraceSpan {
+            span_id: SpanId::from(span_id),
+            parent_span_id: parent_span_id.map(SpanId::from),
+            name: format!("span-{span_id}"),
+            kind: None,
+            start_time: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(start_secs)),
+            end_time: None,
+            labels: HashMap::new(),
+        }
+    }
+
+    fn test_trace(spans: Vec<GcpTraceSpan>) -> GcpTrace {
+        GcpTrace {
+            project_id: "test-project".to_string(),
+            trace_id: TraceId::from(1),
+            spans,
+        }
+    }
+
+    // Span names by depth
+    fn tree_names(nodes: &[GcpTraceSpanNode], depth: usize, names: &mut Vec<String>) {
+        for node in nodes {
+            names.push(format!("{}{}", "  ".repeat(depth), node.span.name));
+            tree_names(&node.children, depth + 1, names);
+        }
+    }
+
+    fn span_tree_names(trace: &GcpTrace) -> Vec<String> {
+        let mut names = Vec::new();
+        tree_names(&trace.span_tree(), 0, &mut names);
+        names
+    }
+
+    #[test]
+    fn builds_span_tree() {
+        let trace = test_trace(vec![
+            test_span(3, Some(1), 3),
+            test_span(2, Some(1), 2),
+            test_span(4, Some(2), 4),
+            test_span(1, None, 1),
+            // Parent missing in the trace
+            test_span(5, Some(9), 0),
+        ]);
+        assert_eq!(
+            span_tree_names(&trace),
+            vec!["span-5", "span-1", "  span-2", "    span-4", "  span-3"]
+        );
+    }
+
+    #[test]
+    fn span_tree_keeps_parent_cycles() {
+        let trace = test_trace(vec![
+            test_span(1, None, 1),
+            test_span(2, Some(3), 2),
+            test_span(3, Some(2), 3),
+        ]);
+        assert_eq!(
+            span_tree_names(&trace),
+            vec!["span-1", "span-2", "  span-3"]
+        );
+    }
+
+    #[test]
+    fn span_tree_handles_duplicated_span_ids() {
+        let mut duplicate = test_span(2, Some(2), 3);
+        duplicate.name = "duplicate".to_string();
+        let trace = test_trace(vec![
+            test_span(1, None, 1),
+            test_span(2, Some(2), 2),
+            duplicate,
+        ]);
+        assert_eq!(
+            span_tree_names(&trace),
+            vec!["span-1", "span-2", "  duplicate"]
+        );
+    }
+
+    #[test]
+    fn converts_spans() {
+        let span = GcpCloudTraceReader::convert_span(TraceSpan {
+            span_id: 0x0102030405060708,
+            kind: trace_span::SpanKind::RpcServer.into(),
+            name: "test".to_string(),
+            start_time: Some(prost_types::Timestamp {
+                seconds: 10,
+                nanos: 0,
+            }),
+            parent_span_id: 0,
+            labels: HashMap::from([("key".to_string(), "value".to_string())]),
+            ..TraceSpan::default()
+        });
+        assert_eq!(span.span_id, SpanId::from_hex("0102030405060708").unwrap());
+        assert_eq!(span.parent_span_id, None);
+        assert_eq!(span.kind, Some(SpanKind::Server));
+        assert_eq!(
+            span.start_time,
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(10))
+        );
+        assert_eq!(span.end_time, None);
+        assert_eq!(span.labels["key"], "value");
+
+        for (kind, expected_kind) in [
+            (trace_span::SpanKind::RpcClient, Some(SpanKind::Client)),
+            (trace_span::SpanKind::Unspecified, None),
+        ] {
+            let span = GcpCloudTraceReader::convert_span(TraceSpan {
+                span_id: 2,
+                parent_span_id: 1,
+                kind: kind.into(),
+                ..TraceSpan::default()
+            });
+            assert_eq!(span.kind, expected_kind);
+            assert_eq!(span.parent_span_id, Some(SpanId::from(1)));
+        }
+    }
+
+    fn grpc_response(message: impl Message) -> axum::response::Response {
+        let message = message.encode_to_vec();
+        let mut frame = vec![0u8];
+        frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&message);
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", HeaderValue::from_static("0"));
+        let body = StreamBody::new(futures::stream::iter(vec![
+            Ok::<_, std::convert::Infallible>(http_body::Frame::data(Bytes::from(frame))),
+            Ok(http_body::Frame::trailers(trailers)),
+        ]));
+        axum::response::Response::builder()
+            .header(header::CONTENT_TYPE, "application/grpc")
+            .body(Body::new(body))
+            .unwrap()
+    }
+
+    fn grpc_trace(trace_id: u128) -> Trace {
+        Trace {
+            project_id: "test-project".to_string(),
+            trace_id: TraceId::from(trace_id).to_string(),
+            spans: vec![TraceSpan {
+                span_id: 1,
+                name: "root".to_string(),
+                ..TraceSpan::default()
+            }],
+        }
+    }
+
+    // Local stand-in for the Cloud Trace v1 API returning one trace per page
+    async fn start_server() -> (String, Arc<Mutex<Vec<ListTracesRequest>>>) {
+        let requests: Arc<Mutex<Vec<ListTracesRequest>>> = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        let app = axum::Router::new().route(
+            "/google.devtools.cloudtrace.v1.TraceService/ListTraces",
+            axum::routing::post(move |body: Bytes| {
+                let recorded = recorded.clone();
+                async move {
+                    let request = ListTracesRequest::decode(&body[5..]).unwrap();
+                    let response = match request.page_token.as_str() {
+                        "" => ListTracesResponse {
+                            traces: vec![grpc_trace(1)],
+                            next_page_token: "page-2".to_string(),
+                        },
+                        _ => ListTracesResponse {
+                            traces: vec![grpc_trace(2)],
+                            next_page_token: String::new(),
+                        },
+                    };
+                    recorded.lock().unwrap().push(request);
+                    grpc_response(response)
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{addr}"), requests)
+    }
+
+    #[tokio::test]
+    async fn lists_all_pages() {
+        let (api_url, requests) = start_server().await;
+        let reader = GcpCloudTraceReader::with_options(
+            "test-project",
+            &api_url,
+            &crate::credentials::StaticTokenSource::credentials(),
+        )
+        .await
+        .unwrap();
+
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(100);
+        let end = start + Duration::from_secs(60);
+        let traces = reader.list_traces("root", start..end).await.unwrap();
+
+        assert_eq!(
+            traces
+                .iter()
+                .map(|trace| trace.trace_id)
+                .collect::<Vec<_>>(),
+            vec![TraceId::from(1), TraceId::from(2)]
+        );
+        assert_eq!(traces[0].spans[0].name, "root");
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(
+            requests
+                .iter()
+                .map(|request| request.page_token.as_str())
+                .collect::<Vec<_>>(),
+            vec!["", "page-2"]
+        );
+        assert!(requests
+            .iter()
+            .all(|request| request.project_id == "test-project"
+                && request.filter == "root"
+                && request.view == ViewType::Complete as i32
+                && request.start_time.as_ref().map(|ts| ts.seconds) == Some(100)
+                && request.end_time.as_ref().map(|ts| ts.seconds) == Some(160)));
+    }
+}
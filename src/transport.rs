@@ -0,0 +1,42 @@
+use crate::TraceExportResult;
+use async_trait::async_trait;
+use gcloud_sdk::google::devtools::cloudtrace::v2::trace_service_client::TraceServiceClient;
+use gcloud_sdk::google::devtools::cloudtrace::v2::BatchWriteSpansRequest;
+use gcloud_sdk::tonic::codegen::{Body, Bytes, StdError};
+use gcloud_sdk::{tonic, GoogleApi, GoogleAuthMiddleware};
+
+/// Sends converted spans to Cloud Trace.
+///
+/// The default implementation is the authenticated gRPC client created by the exporter,
+/// but it can be replaced with a custom one (e.g. a tonic client with interceptors,
+/// a different auth middleware or a recording client for tests)
+/// using [`crate::GcpCloudTraceExporter::with_transport`].
+#[async_trait]
+pub trait CloudTraceTransport: Send + Sync {
+    async fn batch_write_spans(&self, request: BatchWriteSpansRequest) -> TraceExportResult<()>;
+}
+
+#[async_trait]
+impl CloudTraceTransport for GoogleApi<TraceServiceClient<GoogleAuthMiddleware>> {
+    async fn batch_write_spans(&self, request: BatchWriteSpansRequest) -> TraceExportResult<()> {
+        let mut client = self.get();
+        TraceServiceClient::batch_write_spans(&mut client, tonic::Request::new(request)).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<T> CloudTraceTransport for TraceServiceClient<T>
+where
+    T: tonic::client::GrpcService<tonic::body::Body> + Clone + Send + Sync + 'static,
+    T::Error: Into<StdError>,
+    T::ResponseBody: Body<Data = Bytes> + Send + 'static,
+    <T::ResponseBody as Body>::Error: Into<StdError> + Send,
+    T::Future: Send,
+{
+    async fn batch_write_spans(&self, request: BatchWriteSpansRequest) -> TraceExportResult<()> {
+        let mut client = self.clone();
+        TraceServiceClient::batch_write_spans(&mut client, tonic::Request::new(request)).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,428 @@
+use crate::errors::{GcloudTraceError, GcloudTraceSystemError};
+use crate::{OtlpResourceSpanData, TraceExportResult};
+use opentelemetry::trace::{
+    Event, SpanContext, SpanId, SpanKind, Status, TraceFlags, TraceId, TraceState,
+};
+use opentelemetry::{InstrumentationScope, KeyValue};
+use opentelemetry_sdk::trace::{SpanData, SpanEvents, SpanLinks};
+use opentelemetry_sdk::Resource;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime};
+
+type JsonResult<T> = Result<T, String>;
+
+/// Converts Zipkin v2 JSON spans (as sent to `/api/v2/spans`) into `SpanData`.
+///
+/// Spans are grouped by their local endpoint, which is converted to resource attributes.
+/// Tags are converted to attributes, annotations to events and the remote endpoint
+/// to `peer.service`/`net.peer.*` attributes.
+///
+/// Spans without a `timestamp` (e.g. incomplete spans with late annotations) are skipped.
+/// Shared server spans reuse the span id of their client span in Zipkin, so they get
+/// a span id derived from it and the client span as their parent. Children of shared spans
+/// from the same local endpoint in the same request are reparented to the derived id.
+pub struct ZipkinTraceConverter;
+
+impl ZipkinTraceConverter {
+    pub fn convert_json(spans: &Value) -> TraceExportResult<Vec<OtlpResourceSpanData>> {
+        Self::convert_spans(spans).map_err(|message| {
+            GcloudTraceError::SystemError(GcloudTraceSystemError::new(format!(
+                "Invalid Zipkin spans: {message}"
+            )))
+        })
+    }
+
+    fn convert_spans(spans: &Value) -> JsonResult<Vec<OtlpResourceSpanData>> {
+        let spans = spans
+            .as_array()
+            .ok_or_else(|| "expected an array of spans".to_string())?;
+
+        let mut converted_spans: Vec<(Option<&Value>, SpanData)> = Vec::new();
+        for span in spans {
+            let obj = span
+                .as_object()
+                .ok_or_else(|| "span must be an object".to_string())?;
+            if let Some(span_data) = Self::convert_span(obj)? {
+                converted_spans.push((obj.get("localEndpoint"), span_data));
+            }
+        }
+
+        // Shared spans by their original span id, which their children use as the parent id
+        let shared_spans: HashSet<(TraceId, SpanId, Option<&Value>)> = converted_spans
+            .iter()
+            .filter(|(_, span)| span.parent_span_is_remote)
+            .map(|(local_endpoint, span)| {
+                (
+                    span.span_context.trace_id(),
+                    span.parent_span_id,
+                    *local_endpoint,
+                )
+            })
+            .collect();
+
+        let mut resource_spans: Vec<(Option<&Value>, OtlpResourceSpanData)> = Vec::new();
+        for (local_endpoint, mut span_data) in converted_spans {
+            let trace_id = span_data.span_context.trace_id();
+            if !span_data.parent_span_is_remote
+                && shared_spans.contains(&(trace_id, span_data.parent_span_id, local_endpoint))
+            {
+                span_data.parent_span_id = Self::shared_span_id(trace_id, span_data.parent_span_id);
+            }
+            match resource_spans
+                .iter_mut()
+                .find(|(endpoint, _)| *endpoint == local_endpoint)
+            {
+                Some((_, data)) => data.spans.push(span_data),
+                None => resource_spans.push((
+                    local_endpoint,
+                    OtlpResourceSpanData {
+                        resource: Self::convert_local_endpoint(local_endpoint),
+                        spans: vec![span_data],
+                    },
+                )),
+            }
+        }
+
+        Ok(resource_spans.into_iter().map(|(_, data)| data).collect())
+    }
+
+    fn convert_local_endpoint(endpoint: Option<&Value>) -> Resource {
+        let endpoint = endpoint.and_then(Value::as_object);
+        let mut attributes = vec![KeyValue::new(
+            "service.name",
+            endpoint
+                .and_then(|endpoint| endpoint.get("serviceName"))
+                .and_then(Value::as_str)
+                .unwrap_or("unknown_service")
+                .to_string(),
+        )];
+        if let Some(endpoint) = endpoint {
+            attributes.extend(Self::convert_endpoint_address(endpoint, "net.host"));
+        }
+        Resource::builder_empty()
+            .with_attributes(attributes)
+            .build()
+    }
+
+    fn convert_endpoint_address(endpoint: &Map<String, Value>, prefix: &str) -> Vec<KeyValue> {
+        let mut attributes = Vec::new();
+        if let Some(ip) = endpoint
+            .get("ipv4")
+            .or_else(|| endpoint.get("ipv6"))
+            .and_then(Value::as_str)
+        {
+            attributes.push(KeyValue::new(format!("{prefix}.ip"), ip.to_string()));
+        }
+        if let Some(port) = endpoint.get("port").and_then(Value::as_i64) {
+            attributes.push(KeyValue::new(format!("{prefix}.port"), port));
+        }
+        attributes
+    }
+
+    fn convert_span(obj: &Map<String, Value>) -> JsonResult<Option<SpanData>> {
+        let trace_id = get_str(obj, "traceId")?.ok_or_else(|| "traceId is required".to_string())?;
+        // 64-bit trace ids are left padded to 128 bits
+        let trace_id = TraceId::from_hex(&format!("{trace_id:0>32}"))
+            .map_err(|e| format!("invalid traceId {trace_id}: {e}"))?;
+        let span_id =
+            Self::parse_span_id(get_str(obj, "id")?.ok_or_else(|| "id is required".to_string())?)?;
+        let parent_span_id = get_str(obj, "parentId")?
+            .map(Self::parse_span_id)
+            .transpose()?
+            .unwrap_or(SpanId::INVALID);
+
+        let Some(timestamp) = obj.get("timestamp") else {
+            return Ok(None);
+        };
+        let start_time = Self::convert_time(timestamp)?;
+        let end_time = start_time + Duration::from_micros(get_u64(obj, "duration")?.unwrap_or(0));
+
+        let tags = match obj.get("tags") {
+            Some(tags) => tags
+                .as_object()
+                .ok_or_else(|| "tags must be an object".to_string())?
+                .iter()
+                .map(|(key, value)| {
+                    value
+                        .as_str()
+                        .map(|value| (key.as_str(), value))
+                        .ok_or_else(|| format!("tag {key} must be a string"))
+                })
+                .collect::<JsonResult<Vec<_>>>()?,
+            None => Vec::new(),
+        };
+
+        let mut attributes: Vec<KeyValue> = tags
+            .iter()
+            .filter(|(key, _)| !matches!(*key, "error" | "otel.status_code"))
+            .map(|(key, value)| KeyValue::new(key.to_string(), value.to_string()))
+            .collect();
+        let shared = obj.get("shared").and_then(Value::as_bool) == Some(true);
+        // Zipkin only tells whether the parent is remote for shared spans
+        if !shared && parent_span_id != SpanId::INVALID {
+            attributes.push(KeyValue::new(
+                crate::SpanConverter::PARENT_SPAN_IS_REMOTE_ATTRIBUTE,
+                "unknown",
+            ));
+        }
+        if let Some(remote_endpoint) = obj.get("remoteEndpoint").and_then(Value::as_object) {
+            if let Some(service_name) = remote_endpoint.get("serviceName").and_then(Value::as_str) {
+                attributes.push(KeyValue::new("peer.service", service_name.to_string()));
+            }
+            attributes.extend(Self::convert_endpoint_address(remote_endpoint, "net.peer"));
+        }
+
+        let status = match tags
+            .iter()
+            .find(|(key, _)| *key == "error")
+            .map(|(_, value)| *value)
+        {
+            Some(description) => Status::error(description.to_string()),
+            None if tags.contains(&("otel.status_code", "OK")) => Status::Ok,
+            None => Status::Unset,
+        };
+
+        let mut events = SpanEvents::default();
+        if let Some(annotations) = obj.get("annotations") {
+            events.events = annotations
+                .as_array()
+                .ok_or_else(|| "annotations must be an array".to_string())?
+                .iter()
+                .map(|annotation| {
+                    let annotation = annotation
+                        .as_object()
+                        .ok_or_else(|| "annotation must be an object".to_string())?;
+                    Ok(Event::new(
+                        get_str(annotation, "value")?
+                            .unwrap_or_default()
+                            .to_string(),
+                        Self::convert_time(
+                            annotation
+                                .get("timestamp")
+                                .ok_or_else(|| "annotation timestamp is required".to_string())?,
+                        )?,
+                        Vec::new(),
+                        0,
+                    ))
+                })
+                .collect::<JsonResult<_>>()?;
+        }
+
+        // Shared spans are server spans started by a remote client span with the same id
+        let (span_id, parent_span_id) = if shared {
+            (Self::shared_span_id(trace_id, span_id), span_id)
+        } else {
+            (span_id, parent_span_id)
+        };
+
+        Ok(Some(SpanData {
+            span_context: SpanContext::new(
+                trace_id,
+                span_id,
+                TraceFlags::SAMPLED,
+                false,
+                TraceState::default(),
+            ),
+            parent_span_id,
+            parent_span_is_remote: shared,
+            span_kind: match get_str(obj, "kind")? {
+                Some("CLIENT") => SpanKind::Client,
+                Some("SERVER") => SpanKind::Server,
+                Some("PRODUCER") => SpanKind::Producer,
+                Some("CONSUMER") => SpanKind::Consumer,
+                _ => SpanKind::Internal,
+            },
+            name: get_str(obj, "name")?.unwrap_or_default().to_string().into(),
+            start_time,
+            end_time,
+            attributes,
+            dropped_attributes_count: 0,
+            events,
+            links: SpanLinks::default(),
+            status,
+            instrumentation_scope: InstrumentationScope::builder("zipkin").build(),
+        }))
+    }
+
+    // Deterministic, so the same shared span gets the same id in every request
+    fn shared_span_id(trace_id: TraceId, span_id: SpanId) -> SpanId {
+        let hash = Sha256::new()
+            .chain_update(trace_id.to_bytes())
+            .chain_update(span_id.to_bytes())
+            .finalize();
+        match SpanId::from_bytes(hash[..8].try_into().unwrap()) {
+            SpanId::INVALID => SpanId::from_bytes(hash[8..16].try_into().unwrap()),
+            shared_span_id => shared_span_id,
+        }
+    }
+
+    fn parse_span_id(span_id: &str) -> JsonResult<SpanId> {
+        SpanId::from_hex(span_id).map_err(|e| format!("invalid span id {span_id}: {e}"))
+    }
+
+    // Zipkin timestamps are in microseconds since the epoch
+    fn convert_time(timestamp: &Value) -> JsonResult<SystemTime> {
+        let micros = timestamp
+            .as_u64()
+            .ok_or_else(|| "timestamp must be a positive integer".to_string())?;
+        Ok(SystemTime::UNIX_EPOCH + Duration::from_micros(micros))
+    }
+}
+
+fn get_str<'a>(obj: &'a Map<String, Value>, key: &str) -> JsonResult<Option<&'a str>> {
+    obj.get(key)
+        .map(|value| {
+            value
+                .as_str()
+                .ok_or_else(|| format!("{key} must be a string"))
+        })
+        .transpose()
+}
+
+fn get_u64(obj: &Map<String, Value>, key: &str) -> JsonResult<Option<u64>> {
+    obj.get(key)
+        .map(|value| {
+            value
+                .as_u64()
+                .ok_or_else(|| format!("{key} must be a positive integer"))
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const TRACE_ID: &str = "5af7183fb1d4cf5f";
+
+    fn convert(spans: Value) -> Vec<SpanData> {
+        ZipkinTraceConverter::convert_json(&spans)
+            .unwrap()
+            .into_iter()
+            .flat_map(|resource_spans| resource_spans.spans)
+            .collect()
+    }
+
+    fn span_id(span_id: &str) -> SpanId {
+        SpanId::from_hex(span_id).unwrap()
+    }
+
+    #[test]
+    fn converts_spans() {
+        let spans = convert(json!([{
+            "traceId": TRACE_ID,
+            "id": "352bff9a74ca9ad2",
+            "parentId": "6b221d5bc9e6496c",
+            "name": "get /api",
+            "kind": "CLIENT",
+            "timestamp": 1556604172355737u64,
+            "duration": 1431,
+            "localEndpoint": { "serviceName": "frontend" },
+            "tags": { "http.method": "GET", "error": "timeout" },
+            "annotations": [{ "timestamp": 1556604172355800u64, "value": "retry" }]
+        }]));
+
+        assert_eq!(spans.len(), 1);
+        let span = &spans[0];
+        assert_eq!(
+            span.span_context.trace_id(),
+            TraceId::from_hex(&format!("{TRACE_ID:0>32}")).unwrap()
+        );
+        assert_eq!(span.span_context.span_id(), span_id("352bff9a74ca9ad2"));
+        assert_eq!(span.parent_span_id, span_id("6b221d5bc9e6496c"));
+        assert_eq!(span.span_kind, SpanKind::Client);
+        assert_eq!(
+            span.start_time,
+            SystemTime::UNIX_EPOCH + Duration::from_micros(1556604172355737)
+        );
+        assert_eq!(
+            span.end_time.duration_since(span.start_time).unwrap(),
+            Duration::from_micros(1431)
+        );
+        assert_eq!(span.status, Status::error("timeout"));
+        assert!(span
+            .attributes
+            .contains(&KeyValue::new("http.method", "GET")));
+        assert_eq!(span.events.events[0].name, "retry");
+    }
+
+    #[test]
+    fn skips_spans_without_timestamp() {
+        let spans = convert(json!([
+            {
+                "traceId": TRACE_ID,
+                "id": "352bff9a74ca9ad2",
+                "annotations": [{ "timestamp": 1556604172355800u64, "value": "late" }]
+            },
+            {
+                "traceId": TRACE_ID,
+                "id": "6b221d5bc9e6496c",
+                "timestamp": 1556604172355737u64
+            }
+        ]));
+
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].span_context.span_id(), span_id("6b221d5bc9e6496c"));
+    }
+
+    #[test]
+    fn rejects_annotations_without_timestamp() {
+        assert!(ZipkinTraceConverter::convert_json(&json!([{
+            "traceId": TRACE_ID,
+            "id": "352bff9a74ca9ad2",
+            "timestamp": 1556604172355737u64,
+            "annotations": [{ "value": "retry" }]
+        }]))
+        .is_err());
+    }
+
+    #[test]
+    fn splits_shared_spans() {
+        let frontend = json!({ "serviceName": "frontend" });
+        let backend = json!({ "serviceName": "backend" });
+        let spans = convert(json!([
+            {
+                "traceId": TRACE_ID,
+                "id": "352bff9a74ca9ad2",
+                "parentId": "6b221d5bc9e6496c",
+                "kind": "CLIENT",
+                "timestamp": 1556604172355737u64,
+                "localEndpoint": frontend
+            },
+            {
+                "traceId": TRACE_ID,
+                "id": "352bff9a74ca9ad2",
+                "parentId": "6b221d5bc9e6496c",
+                "kind": "SERVER",
+                "shared": true,
+                "timestamp": 1556604172355800u64,
+                "localEndpoint": backend
+            },
+            {
+                "traceId": TRACE_ID,
+                "id": "e457b5a2e4d86bd1",
+                "parentId": "352bff9a74ca9ad2",
+                "timestamp": 1556604172355900u64,
+                "localEndpoint": backend
+            }
+        ]));
+
+        let client = &spans[0];
+        let server = &spans[1];
+        let server_child = &spans[2];
+        assert_eq!(client.span_context.span_id(), span_id("352bff9a74ca9ad2"));
+        assert_eq!(client.parent_span_id, span_id("6b221d5bc9e6496c"));
+
+        let server_span_id = server.span_context.span_id();
+        assert_ne!(server_span_id, client.span_context.span_id());
+        assert_ne!(server_span_id, SpanId::INVALID);
+        assert_eq!(server.parent_span_id, client.span_context.span_id());
+        assert!(server.parent_span_is_remote);
+
+        assert_eq!(server_child.parent_span_id, server_span_id);
+        assert!(!server_child.parent_span_is_remote);
+    }
+}